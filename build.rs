@@ -0,0 +1,97 @@
+//! Generates `DataInstruction` and its tables from the opcode definitions in `opcodes.json`.
+
+use std::{env, fmt::Write, fs, path::Path};
+
+fn main() {
+    println!("cargo::rerun-if-changed=opcodes.json");
+
+    let spec = fs::read_to_string("opcodes.json").expect("read opcodes.json");
+    let spec: serde_json::Value = serde_json::from_str(&spec).expect("parse opcodes.json");
+
+    let mut variants = String::new();
+    let mut arity = String::new();
+    let mut name = String::new();
+    let mut gas_tier = String::new();
+    let mut opcode = String::new();
+    let mut since = String::new();
+    let mut from_name = String::new();
+    let mut from_opcode = String::new();
+
+    for op in spec.as_array().expect("opcodes.json should hold an array") {
+        let field = |key: &str| op.get(key).unwrap_or_else(|| panic!("missing {key} in {op}"));
+        let mnemonic = field("name").as_str().unwrap();
+        let byte = field("byte").as_str().unwrap();
+        let inputs = field("inputs").as_u64().unwrap();
+        let outputs = field("outputs").as_u64().unwrap();
+        let gas = field("gas").as_str().unwrap();
+        let fork = field("fork").as_str().unwrap();
+
+        let mut chars = mnemonic.chars();
+        let variant: String = chars.next().unwrap().to_uppercase().chain(chars).collect();
+
+        writeln!(variants, "    {variant},").unwrap();
+        writeln!(arity, "            {variant} => ({inputs}, {outputs}),").unwrap();
+        writeln!(name, "            {variant} => \"{mnemonic}\",").unwrap();
+        writeln!(gas_tier, "            {variant} => GasTier::{gas},").unwrap();
+        writeln!(opcode, "            {variant} => {byte},").unwrap();
+        writeln!(since, "            {variant} => EvmVersion::{fork},").unwrap();
+        writeln!(from_name, "            \"{mnemonic}\" => Some({variant}),").unwrap();
+        writeln!(from_opcode, "            {byte} => Some({variant}),").unwrap();
+    }
+
+    let code = format!("\
+#[derive(Debug, Clone, Copy)]
+pub enum DataInstruction {{
+{variants}}}
+
+impl DataInstruction {{
+    pub fn arity(&self) -> (usize, usize) {{
+        use DataInstruction::*;
+        match self {{
+{arity}        }}
+    }}
+
+    pub fn name(&self) -> &'static str {{
+        use DataInstruction::*;
+        match self {{
+{name}        }}
+    }}
+
+    fn gas_tier(&self) -> GasTier {{
+        use DataInstruction::*;
+        match self {{
+{gas_tier}        }}
+    }}
+
+    pub fn opcode(&self) -> u8 {{
+        use DataInstruction::*;
+        match self {{
+{opcode}        }}
+    }}
+
+    /// The first version where the instruction is available.
+    pub fn since(&self) -> EvmVersion {{
+        use DataInstruction::*;
+        match self {{
+{since}        }}
+    }}
+
+    pub fn from_name(name: &str) -> Option<Self> {{
+        use DataInstruction::*;
+        match name {{
+{from_name}            _ => None,
+        }}
+    }}
+
+    pub fn from_opcode(opcode: u8) -> Option<Self> {{
+        use DataInstruction::*;
+        match opcode {{
+{from_opcode}            _ => None,
+        }}
+    }}
+}}
+");
+
+    let out = Path::new(&env::var("OUT_DIR").unwrap()).join("data_instructions.rs");
+    fs::write(out, code).unwrap();
+}
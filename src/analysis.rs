@@ -1,19 +1,170 @@
 use std::collections::HashMap;
+use std::ops::Range;
 
-use crate::{program::{Expression, Statement}, scope::{ResolvedBlock, Var}};
+use alloy_primitives::U256;
+
+use crate::evm::DataInstruction;
+use crate::program::{Block, Expression, Statement};
+use crate::scope::{self, ResolveError, ResolvedBlock, Var};
 
 pub fn count_occurrences(rblock: &ResolvedBlock) -> Vec<usize> {
-    let mut counts = Vec::new();
-    counts.resize(rblock.var_count, 0);
+    let mut counts = vec![0; rblock.var_count];
+    for (_, args) in rblock.flat.iter() {
+        for &x in args {
+            counts[x.index()] += 1;
+        }
+    }
+    counts
+}
+
+/// For each variable, the statements from its definition to its last use, or just the definition
+/// if it's never used.
+pub fn live_ranges(rblock: &ResolvedBlock) -> Vec<Option<Range<usize>>> {
+    let mut ranges: Vec<Option<Range<usize>>> = vec![None; rblock.var_count];
+    for (i, (vs, args)) in rblock.flat.iter().enumerate() {
+        for &x in args {
+            if let Some(range) = &mut ranges[x.index()] {
+                range.end = i;
+            }
+        }
+        for &v in vs {
+            ranges[v.index()] = Some(i..i);
+        }
+    }
+    ranges
+}
+
+/// Number of variables live across each statement, counting a variable from its definition to
+/// its last use.
+pub fn live_counts(rblock: &ResolvedBlock) -> Vec<usize> {
+    let mut changes = vec![0isize; rblock.flat.len() + 1];
+    for range in live_ranges(rblock).into_iter().flatten() {
+        changes[range.start] += 1;
+        changes[range.end + 1] -= 1;
+    }
+    changes[..rblock.flat.len()].iter().scan(0, |live, change| { *live += change; Some(*live as usize) }).collect()
+}
+
+/// Largest number of variables live at once.
+pub fn max_live(rblock: &ResolvedBlock) -> usize {
+    live_counts(rblock).into_iter().max().unwrap_or(0)
+}
+
+/// Statements that define variables but none that are used, and have no effect besides them.
+pub fn unused_results(rblock: &ResolvedBlock) -> Vec<usize> {
+    let counts = count_occurrences(rblock);
+    rblock.block.0.iter().enumerate()
+        .filter(|(_, Statement(vs, e))| {
+            let pure = match e {
+                Expression::Const(_) | Expression::Immutable(_) => true,
+                Expression::Op(op, _) => op.parse::<DataInstruction>().is_ok_and(DataInstruction::is_pure),
+                Expression::Verbatim(..) | Expression::Input | Expression::Output(_) => false,
+            };
+            pure && !vs.is_empty() && vs.iter().all(|v| counts[v.index()] == 0)
+        })
+        .map(|(i, _)| i)
+        .collect()
+}
+
+pub fn constant_values(rblock: &ResolvedBlock) -> Vec<Option<U256>> {
+    let mut values = vec![None; rblock.var_count];
+    for Statement(vs, e) in &rblock.block.0 {
+        if let (Expression::Const(c), [v]) = (e, &vs[..]) {
+            values[v.index()] = Some(*c);
+        }
+    }
+    values
+}
+
+/// Whether each variable is used as a memory offset, and whether it is used otherwise.
+fn offset_uses(rblock: &ResolvedBlock) -> (Vec<bool>, Vec<bool>) {
+    let mut offset = vec![false; rblock.var_count];
+    let mut other = vec![false; rblock.var_count];
     for Statement(_, e) in &rblock.block.0 {
-        match e {
-            Expression::Const(_) => (),
-            Expression::Op(_, args) => {
-                for &x in args {
-                    counts[x.index()] += 1;
-                }
+        let (args, memory) = match e {
+            Expression::Op(op, args) => (&args[..], memory_offsets(op)),
+            Expression::Verbatim(_, _, args) | Expression::Output(args) => (&args[..], &[][..]),
+            Expression::Const(_) | Expression::Immutable(_) | Expression::Input => continue,
+        };
+        for (i, a) in args.iter().enumerate() {
+            if memory.contains(&i) { offset[a.index()] = true } else { other[a.index()] = true }
+        }
+    }
+    (offset, other)
+}
+
+fn memory_offsets(op: &str) -> &'static [usize] {
+    op.parse::<DataInstruction>().map_or(&[], DataInstruction::memory_offsets)
+}
+
+/// Variables holding constants that are used only as memory offsets, such as the address of an
+/// `mload` or the offset of the data of a `return`.
+pub fn constant_offsets(rblock: &ResolvedBlock) -> Vec<bool> {
+    let (offset, other) = offset_uses(rblock);
+    constant_values(rblock).iter().zip(offset.iter().zip(other))
+        .map(|(c, (&offset, other))| c.is_some() && offset && !other)
+        .collect()
+}
+
+/// Gives each constant used both as a memory offset and otherwise a copy named with a `%memory`
+/// suffix, defined right after it, that takes over its uses as an offset. Constants are then used
+/// either only as offsets or not at all, so that relocating memory moves every constant offset.
+pub fn split_offsets(rblock: ResolvedBlock) -> Result<ResolvedBlock, ResolveError> {
+    let (offset, other) = offset_uses(&rblock);
+    let values = constant_values(&rblock);
+    let mixed: Vec<bool> = (0..rblock.var_count).map(|v| values[v].is_some() && offset[v] && other[v]).collect();
+    if !mixed.contains(&true) {
+        return Ok(rblock);
+    }
+
+    let ResolvedBlock { block: Block(statements, spans, comments), names, .. } = rblock;
+    let name = |v: Var| names[v.index()].clone();
+    let copy = |v: Var| format!("{}%memory", names[v.index()]);
+    let mut block = Block(Vec::new(), Vec::new(), Vec::new());
+    let mut moved = Vec::with_capacity(statements.len());
+    for (i, Statement(vs, e)) in statements.into_iter().enumerate() {
+        moved.push(block.0.len());
+        let split = match (&e, &vs[..]) {
+            (Expression::Const(c), [v]) if mixed[v.index()] => Some((copy(*v), *c)),
+            _ => None,
+        };
+        let e = match e {
+            Expression::Op(op, args) => {
+                let memory = memory_offsets(&op);
+                let args = args.iter().enumerate()
+                    .map(|(i, &a)| if mixed[a.index()] && memory.contains(&i) { copy(a) } else { name(a) })
+                    .collect();
+                Expression::Op(op, args)
             }
+            e => e.map(name),
+        };
+        block.0.push(Statement(vs.into_iter().map(name).collect(), e));
+        block.1.extend(spans.get(i).cloned());
+        if let Some((copy, c)) = split {
+            block.0.push(Statement(vec![copy], Expression::Const(c)));
+            block.1.extend(spans.get(i).cloned());
         }
     }
-    counts
+    block.2 = comments.into_iter().map(|(i, comment)| (moved.get(i).copied().unwrap_or(block.0.len()), comment)).collect();
+    scope::resolve(block)
+}
+
+/// Memory written by `mstore`, `mcopy` and `codecopy` at statically known destinations, with the
+/// index of the statement doing the write. A copy of unknown size is taken to write its first byte,
+/// and one of size zero writes nothing.
+pub fn constant_stores(rblock: &ResolvedBlock) -> Vec<(usize, Range<U256>)> {
+    let values = constant_values(rblock);
+    rblock.block.0.iter().enumerate()
+        .filter_map(|(i, Statement(_, e))| {
+            let Expression::Op(op, args) = e else { return None };
+            let op: DataInstruction = op.parse().ok()?;
+            let size = match op {
+                DataInstruction::Mstore => U256::from(32),
+                DataInstruction::Mcopy | DataInstruction::Codecopy => values[args.get(2)?.index()].unwrap_or(U256::from(1)),
+                _ => return None,
+            };
+            let address = values[args.get(*op.memory_offsets().first()?)?.index()]?;
+            (!size.is_zero()).then(|| (i, address..address.saturating_add(size)))
+        })
+        .collect()
 }
@@ -0,0 +1,127 @@
+//! Random well-formed programs for property testing.
+
+use alloy_primitives::U256;
+use proptest::prelude::*;
+
+use crate::program::{Block, Expression, Statement};
+
+/// Shape of the generated programs.
+#[derive(Clone, Debug)]
+pub struct ProgramParams {
+    /// Most variables defined by a program.
+    pub vars: usize,
+    /// Most statements a variable stays live after its definition.
+    pub lifetime: usize,
+}
+
+impl Default for ProgramParams {
+    fn default() -> Self {
+        ProgramParams { vars: 64, lifetime: 48 }
+    }
+}
+
+/// Memory written and read by generated programs, far above the spill registers.
+const MEMORY_BASE: u64 = 0x10000;
+
+/// Operations without arguments, reading values the interpreter takes from its environment.
+const ENVIRONMENT: [&str; 4] = ["caller", "callvalue", "timestamp", "chainid"];
+
+struct Live {
+    name: String,
+    /// Statement after which the variable is popped.
+    deadline: usize,
+    /// Whether the variable holds an address in the memory used by the program.
+    address: bool,
+}
+
+fn op(op: &str, args: Vec<String>) -> Expression<String> {
+    Expression::Op(op.to_string(), args)
+}
+
+struct Builder {
+    statements: Vec<Statement<String>>,
+    live: Vec<Live>,
+    count: usize,
+}
+
+impl Builder {
+    fn define(&mut self, e: Expression<String>, lifetime: usize, address: bool) {
+        let name = format!("v{}", self.count);
+        self.count += 1;
+        let deadline = self.statements.len() + lifetime;
+        self.statements.push(Statement(vec![name.clone()], e));
+        self.live.push(Live { name, deadline, address });
+    }
+
+    fn pick(&self, choice: usize) -> String {
+        self.live[choice % self.live.len()].name.clone()
+    }
+
+    fn pick_address(&self, choice: usize) -> Option<String> {
+        let addresses: Vec<_> = self.live.iter().filter(|v| v.address).collect();
+        (!addresses.is_empty()).then(|| addresses[choice % addresses.len()].name.clone())
+    }
+
+    fn pop_expired(&mut self, now: usize) {
+        let (expired, live) = std::mem::take(&mut self.live).into_iter().partition(|v| v.deadline <= now);
+        self.live = live;
+        for v in expired {
+            let e = op("pop", vec![v.name]);
+            self.statements.push(Statement(vec![], e));
+        }
+    }
+
+    /// Adds the statement described by `kind`, reading its operands from `a` and `b`.
+    fn step(&mut self, kind: u8, a: usize, b: usize, lifetime: usize) {
+        if self.live.is_empty() {
+            return self.define(Expression::Const(U256::from(a)), lifetime, false);
+        }
+        match (kind, self.pick_address(a)) {
+            (0..=2, _) => self.define(Expression::Const(U256::from(a)), lifetime, false),
+            (3, _) => {
+                let address = U256::from(MEMORY_BASE + 32 * (a % 64) as u64);
+                self.define(Expression::Const(address), lifetime, true)
+            }
+            (4 | 5, _) => {
+                let e = op("add", vec![self.pick(a), self.pick(b)]);
+                self.define(e, lifetime, false)
+            }
+            (6, Some(address)) => {
+                let e = op("mload", vec![address]);
+                self.define(e, lifetime, false)
+            }
+            (7, Some(address)) => {
+                let e = op("mstore", vec![address, self.pick(b)]);
+                self.statements.push(Statement(vec![], e));
+            }
+            (8, _) => {
+                let e = op("tstore", vec![self.pick(a), self.pick(b)]);
+                self.statements.push(Statement(vec![], e));
+            }
+            (9, _) => self.define(Expression::Immutable(format!("i{}", a % 4)), lifetime, false),
+            (10, _) => self.define(op(ENVIRONMENT[b % ENVIRONMENT.len()], vec![]), lifetime, false),
+            _ => self.define(Expression::Const(U256::from(b)), lifetime, false),
+        }
+    }
+}
+
+impl Arbitrary for Block<String> {
+    type Parameters = ProgramParams;
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(params: ProgramParams) -> Self::Strategy {
+        let lifetime = 1..=params.lifetime.max(1);
+        let step = (0..11u8, any::<u16>(), any::<u16>(), lifetime);
+        prop::collection::vec(step, 1..=params.vars.max(1))
+            .prop_map(|steps| {
+                let mut builder = Builder { statements: Vec::new(), live: Vec::new(), count: 0 };
+                for (kind, a, b, lifetime) in steps {
+                    builder.pop_expired(builder.statements.len());
+                    builder.step(kind, a.into(), b.into(), lifetime);
+                }
+                builder.pop_expired(usize::MAX);
+                Block(builder.statements, Vec::new(), Vec::new())
+            })
+            .boxed()
+    }
+}
@@ -0,0 +1,176 @@
+//! Importer for solc's JSON assembly (`solc --asm-json`), which cuts the code of each assembly
+//! into straight-line segments and turns each one into a block, so that this spiller can be run on
+//! code solc's own stack allocator gave up on.
+//!
+//! A segment ends at a tag, a jump, a halt, or an instruction outside the data instructions of this
+//! crate, and segments without any data instruction are left out. Values the segment finds on the stack when it starts become immutables `in0`, `in1`, ...,
+//! counted from the top, and values left on the stack at the end are dropped. Pushes of tags,
+//! subassemblies, immutables and other values solc fills in when linking become immutables as well.
+
+use alloy_primitives::U256;
+use serde_json::Value;
+
+use crate::evm::DataInstruction;
+use crate::program::{Block, Expression, Statement};
+
+#[derive(Debug, thiserror::Error)]
+pub enum ImportError {
+    #[error("invalid JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("bad assembly item {index} in {assembly}: {message}")]
+    Item { assembly: String, index: usize, message: String },
+}
+
+/// Straight-line code from one of the assemblies.
+pub struct Segment {
+    /// Path of the assembly, such as `.code` or `.data.0.code`.
+    pub assembly: String,
+    /// Index of the first item of the segment in the assembly.
+    pub start: usize,
+    pub block: Block<String>,
+}
+
+/// Symbolic stack of the segment being built, with the names of the values on it.
+struct Builder {
+    statements: Vec<Statement<String>>,
+    stack: Vec<String>,
+    inputs: usize,
+    next: usize,
+}
+
+impl Builder {
+    fn new() -> Builder {
+        Builder { statements: Vec::new(), stack: Vec::new(), inputs: 0, next: 0 }
+    }
+
+    fn fresh(&mut self) -> String {
+        self.next += 1;
+        format!("v{}", self.next - 1)
+    }
+
+    /// Makes sure the stack holds at least `depth` values, taking the missing ones as inputs.
+    fn reach(&mut self, depth: usize) {
+        while self.stack.len() < depth {
+            let name = format!("in{}", self.inputs);
+            self.inputs += 1;
+            self.statements.push(Statement(vec![name.clone()], Expression::Immutable(name.clone())));
+            self.stack.insert(0, name);
+        }
+    }
+
+    fn push(&mut self, e: Expression<String>) {
+        let name = self.fresh();
+        self.statements.push(Statement(vec![name.clone()], e));
+        self.stack.push(name);
+    }
+
+    fn op(&mut self, op: DataInstruction) {
+        let (inputs, outputs) = op.arity();
+        self.reach(inputs);
+        let args: Vec<_> = self.stack.drain(self.stack.len() - inputs..).rev().collect();
+        let results: Vec<_> = (0..outputs).map(|_| self.fresh()).collect();
+        self.statements.push(Statement(results.clone(), Expression::Op(op.name().to_string(), args)));
+        self.stack.extend(results);
+    }
+}
+
+/// Name for a value filled in by solc, from the kind of push and its operand.
+fn link_name(kind: &str, value: &str) -> String {
+    let value: String = value.chars().filter(|c| c.is_ascii_alphanumeric()).collect();
+    let value = value.trim_start_matches('0');
+    format!("{kind}{}", if value.is_empty() { "0" } else { value })
+}
+
+fn import_assembly(assembly: &Value, path: &str, segments: &mut Vec<Segment>) -> Result<(), ImportError> {
+    let items = assembly.get(".code").and_then(Value::as_array).map(Vec::as_slice).unwrap_or_default();
+    let code_path = format!("{path}.code");
+
+    let mut builder = Builder::new();
+    let mut start = 0;
+    let mut finish = |builder: &mut Builder, start: usize| {
+        let builder = std::mem::replace(builder, Builder::new());
+        if builder.statements.iter().any(|s| matches!(s.1, Expression::Op(..))) {
+            segments.push(Segment { assembly: code_path.clone(), start, block: Block(builder.statements, vec![], vec![]) });
+        }
+    };
+
+    for (index, item) in items.iter().enumerate() {
+        let error = |message: String| ImportError::Item { assembly: code_path.clone(), index, message };
+        let name = item.get("name").and_then(Value::as_str).ok_or_else(|| error("missing name".to_string()))?;
+        let value = item.get("value").and_then(Value::as_str).unwrap_or_default();
+
+        let linked = match name {
+            "PUSH [tag]" => Some("tag"),
+            "PUSH [$]" => Some("sub"),
+            "PUSH #[$]" => Some("subsize"),
+            "PUSH data" => Some("data"),
+            "PUSHIMMUTABLE" => Some("immutable"),
+            "PUSHLIB" => Some("lib"),
+            "PUSHSIZE" => Some("codesize"),
+            "PUSHDEPLOYADDRESS" => Some("deployaddress"),
+            _ => None,
+        };
+        if let Some(kind) = linked {
+            builder.push(Expression::Immutable(link_name(kind, value)));
+            continue;
+        }
+
+        if name.strip_prefix("PUSH").is_some_and(|width| width.chars().all(|c| c.is_ascii_digit())) {
+            let c = U256::from_str_radix(if value.is_empty() { "0" } else { value }, 16)
+                .map_err(|e| error(format!("bad push value {value}: {e}")))?;
+            builder.push(Expression::Const(c));
+        } else if let Some(n) = name.strip_prefix("DUP").and_then(|n| n.parse::<usize>().ok()) {
+            builder.reach(n);
+            builder.stack.push(builder.stack[builder.stack.len() - n].clone());
+        } else if let Some(n) = name.strip_prefix("SWAP").and_then(|n| n.parse::<usize>().ok()) {
+            builder.reach(n + 1);
+            let len = builder.stack.len();
+            builder.stack.swap(len - 1, len - 1 - n);
+        } else if name == "POP" {
+            builder.reach(1);
+            builder.stack.pop();
+        } else if let Some(op) = DataInstruction::from_name(&name.to_lowercase()) {
+            builder.op(op);
+            if matches!(op, DataInstruction::Stop | DataInstruction::Return | DataInstruction::Revert) {
+                finish(&mut builder, start);
+                start = index + 1;
+            }
+        } else {
+            finish(&mut builder, start);
+            start = index + 1;
+        }
+    }
+    finish(&mut builder, start);
+
+    if let Some(data) = assembly.get(".data").and_then(Value::as_object) {
+        for (key, sub) in data {
+            if sub.is_object() {
+                import_assembly(sub, &format!("{path}.data.{key}"), segments)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Cuts the assemblies in `input` into straight-line segments, the outer assembly first and then
+/// its subassemblies. The input is either one assembly in JSON, or the output of `solc --asm-json`
+/// with an `EVM assembly:` section for each contract, whose paths then start with its name.
+pub fn import(input: &str) -> Result<Vec<Segment>, ImportError> {
+    let mut segments = Vec::new();
+    if input.trim_start().starts_with('{') {
+        import_assembly(&serde_json::from_str(input)?, "", &mut segments)?;
+        return Ok(segments);
+    }
+
+    let mut contract = "";
+    let mut lines = input.lines();
+    while let Some(line) = lines.next() {
+        if let Some(name) = line.strip_prefix("=======").and_then(|l| l.strip_suffix("=======")) {
+            contract = name.trim();
+        } else if line.trim() == "EVM assembly:" {
+            let json: String = lines.by_ref().take_while(|l| !l.trim().is_empty()).collect::<Vec<_>>().join("\n");
+            import_assembly(&serde_json::from_str(&json)?, contract, &mut segments)?;
+        }
+    }
+    Ok(segments)
+}
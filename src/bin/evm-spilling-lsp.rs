@@ -0,0 +1,299 @@
+//! Language server for the DSL, speaking LSP over stdio.
+
+use std::collections::HashMap;
+
+use eyre::Result;
+use lsp_server::{Connection, Message, Notification, Request, Response};
+use lsp_types::notification::{DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, Notification as _, PublishDiagnostics};
+use lsp_types::request::{GotoDefinition, HoverRequest, Request as _};
+use lsp_types::{
+    Diagnostic, DiagnosticSeverity, GotoDefinitionParams, GotoDefinitionResponse, Hover, HoverContents, HoverParams,
+    HoverProviderCapability, Location, MarkupContent, MarkupKind, OneOf, Position, PublishDiagnosticsParams, Range,
+    ServerCapabilities, TextDocumentSyncCapability, TextDocumentSyncKind, Uri,
+};
+
+use evm_spilling::parser::{self, Token};
+use evm_spilling::program::Span;
+use evm_spilling::scope::Var;
+use evm_spilling::{compile, dispatch, Options, Output};
+
+/// A document along with what was learned from compiling it.
+struct Document {
+    text: String,
+    /// Span of each variable's definition, indexed like `Var`.
+    definitions: Vec<Span>,
+    /// Every identifier that names a variable, with the variable.
+    occurrences: Vec<(Span, usize)>,
+    diagnostics: Vec<(Span, String)>,
+    warnings: Vec<(Span, String)>,
+    output: Option<Output>,
+}
+
+impl Document {
+    fn new(text: String) -> Document {
+        let mut doc = Document { text, definitions: Vec::new(), occurrences: Vec::new(), diagnostics: Vec::new(), warnings: Vec::new(), output: None };
+        doc.analyze();
+        doc
+    }
+
+    fn analyze(&mut self) {
+        let parsed = if parser::is_contract(&self.text) {
+            parser::parse_contract(&self.text).map(drop)
+        } else {
+            parser::parse(&self.text).map(drop)
+        };
+        let tokens = match parsed.and_then(|_| parser::spanned_tokens(&self.text)) {
+            Ok(tokens) => tokens,
+            Err(e) => {
+                let offset = e.offset();
+                self.diagnostics.push((offset..offset, e.to_string()));
+                return;
+            }
+        };
+
+        // Resolve names as the resolver does, but keeping the span of every identifier.
+        let mut env: HashMap<&str, usize> = HashMap::new();
+        let mut pending = Vec::new();
+        let mut in_let = false;
+        let mut expecting_op = true;
+        // The identifier taken as an op, which turns out to be a condition if a `?` follows.
+        let mut op = None;
+        // Inside an extern declaration, which names an op and no variables.
+        let mut in_extern = false;
+        for (token, span) in tokens {
+            match token {
+                Token::Extern => in_extern = true,
+                Token::Semi if in_extern => in_extern = false,
+                _ if in_extern => (),
+                Token::Let => {
+                    in_let = true;
+                    expecting_op = false;
+                }
+                Token::Eq => {
+                    in_let = false;
+                    expecting_op = true;
+                }
+                // A constant is a literal or a hash like `selector("...")`, named like an op.
+                Token::Const => expecting_op = true,
+                Token::Immutable => expecting_op = false,
+                Token::Identifier(id) if in_let => pending.push((id, span)),
+                // `takes (a, b);` defines its variables like a `let`.
+                Token::Identifier("takes") if expecting_op => {
+                    in_let = true;
+                    expecting_op = false;
+                }
+                Token::Identifier(id) if expecting_op => {
+                    op = Some((id, span));
+                    expecting_op = false;
+                }
+                Token::Question => {
+                    if let Some((id, span)) = op.take() {
+                        match env.get(id) {
+                            Some(&var) => self.occurrences.push((span, var)),
+                            None => self.diagnostics.push((span, format!("Unknown variable: {id}"))),
+                        }
+                    }
+                }
+                Token::Identifier(id) => match env.get(id) {
+                    Some(&var) => self.occurrences.push((span, var)),
+                    None => self.diagnostics.push((span, format!("Unknown variable: {id}"))),
+                },
+                Token::Semi => {
+                    for (id, span) in pending.drain(..) {
+                        env.insert(id, self.definitions.len());
+                        self.occurrences.push((span.clone(), self.definitions.len()));
+                        self.definitions.push(span);
+                    }
+                    expecting_op = true;
+                    op = None;
+                }
+                // Each function of a contract has its own variables.
+                Token::LBrace | Token::RBrace => {
+                    if token == Token::RBrace {
+                        env.clear();
+                    }
+                    expecting_op = true;
+                    op = None;
+                }
+                Token::Comma | Token::Colon | Token::LParen | Token::RParen | Token::Literal(_) | Token::String(_) => (),
+            }
+        }
+        if !self.diagnostics.is_empty() {
+            return;
+        }
+
+        // The variables of a contract are numbered per function, so hovers are only for programs.
+        if parser::is_contract(&self.text) {
+            if let Err(e) = dispatch::compile(&self.text, &Options::default()) {
+                self.diagnostics.push((0..0, format!("{e:#}")));
+            }
+            return;
+        }
+        match compile(&self.text, &Options::default()) {
+            Ok(output) => {
+                self.warnings = output.warnings().into_iter().filter_map(|w| Some((w.span?, w.message))).collect();
+                self.output = Some(output);
+            }
+            Err(e) => self.diagnostics.push((0..0, format!("{e:#}"))),
+        }
+    }
+
+    fn position(&self, offset: usize) -> Position {
+        let before = &self.text[..offset];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        let character = before[line_start..].encode_utf16().count();
+        Position::new(before.matches('\n').count() as u32, character as u32)
+    }
+
+    fn offset(&self, position: Position) -> usize {
+        let line_start: usize = self.text.split_inclusive('\n').take(position.line as usize).map(str::len).sum();
+        let mut units = 0;
+        for (i, c) in self.text[line_start..].char_indices() {
+            if units >= position.character as usize || c == '\n' {
+                return line_start + i;
+            }
+            units += c.len_utf16();
+        }
+        self.text.len()
+    }
+
+    fn range(&self, span: &Span) -> Range {
+        Range::new(self.position(span.start), self.position(span.end))
+    }
+
+    fn var_at(&self, position: Position) -> Option<(&Span, usize)> {
+        let offset = self.offset(position);
+        self.occurrences.iter()
+            .find(|(span, _)| span.start <= offset && offset <= span.end)
+            .map(|(span, var)| (span, *var))
+    }
+
+    /// Where the variable lives during execution: on the stack, or in which registers.
+    fn fate(&self, var: usize) -> String {
+        let Some(output) = &self.output else { return String::new() };
+        let spills: Vec<_> = output.code.spills.iter().filter(|s| s.var == Var::new(var)).collect();
+        if spills.is_empty() {
+            return "kept on the stack".to_string();
+        }
+        let events: Vec<_> = spills.iter()
+            .map(|s| format!("{} r{} in statement {}", if s.restore { "restored from" } else { "spilled to" }, s.register, s.statement))
+            .collect();
+        events.join(", ")
+    }
+}
+
+fn publish(connection: &Connection, uri: Uri, doc: &Document) -> Result<()> {
+    let errors = doc.diagnostics.iter().map(|d| (d, DiagnosticSeverity::ERROR));
+    let warnings = doc.warnings.iter().map(|d| (d, DiagnosticSeverity::WARNING));
+    let diagnostics = errors.chain(warnings)
+        .map(|((span, message), severity)| Diagnostic {
+            range: doc.range(span),
+            severity: Some(severity),
+            source: Some("evm-spilling".to_string()),
+            message: message.clone(),
+            ..Diagnostic::default()
+        })
+        .collect();
+    let params = PublishDiagnosticsParams { uri, diagnostics, version: None };
+    connection.sender.send(Message::Notification(Notification::new(PublishDiagnostics::METHOD.to_string(), params)))?;
+    Ok(())
+}
+
+fn handle_request(connection: &Connection, docs: &HashMap<String, Document>, request: Request) -> Result<()> {
+    let result = match request.method.as_str() {
+        GotoDefinition::METHOD => {
+            let params: GotoDefinitionParams = serde_json::from_value(request.params)?;
+            let position = params.text_document_position_params;
+            let uri = position.text_document.uri;
+            docs.get(uri.as_str())
+                .and_then(|doc| {
+                    let (_, var) = doc.var_at(position.position)?;
+                    let range = doc.range(&doc.definitions[var]);
+                    Some(GotoDefinitionResponse::Scalar(Location::new(uri.clone(), range)))
+                })
+                .map(serde_json::to_value)
+                .transpose()?
+        }
+
+        HoverRequest::METHOD => {
+            let params: HoverParams = serde_json::from_value(request.params)?;
+            let position = params.text_document_position_params;
+            docs.get(position.text_document.uri.as_str())
+                .and_then(|doc| {
+                    let (span, var) = doc.var_at(position.position)?;
+                    let name = &doc.text[span.clone()];
+                    let contents = HoverContents::Markup(MarkupContent {
+                        kind: MarkupKind::Markdown,
+                        value: format!("`{name}`: {}", doc.fate(var)),
+                    });
+                    Some(Hover { contents, range: Some(doc.range(span)) })
+                })
+                .map(serde_json::to_value)
+                .transpose()?
+        }
+
+        _ => None,
+    };
+    let response = Response::new_ok(request.id, result.unwrap_or(serde_json::Value::Null));
+    connection.sender.send(Message::Response(response))?;
+    Ok(())
+}
+
+fn handle_notification(connection: &Connection, docs: &mut HashMap<String, Document>, notification: Notification) -> Result<()> {
+    match notification.method.as_str() {
+        DidOpenTextDocument::METHOD => {
+            let params: lsp_types::DidOpenTextDocumentParams = serde_json::from_value(notification.params)?;
+            let doc = Document::new(params.text_document.text);
+            publish(connection, params.text_document.uri.clone(), &doc)?;
+            docs.insert(params.text_document.uri.to_string(), doc);
+        }
+
+        DidChangeTextDocument::METHOD => {
+            let params: lsp_types::DidChangeTextDocumentParams = serde_json::from_value(notification.params)?;
+            // Full sync: the last change holds the whole text.
+            if let Some(change) = params.content_changes.into_iter().last() {
+                let doc = Document::new(change.text);
+                publish(connection, params.text_document.uri.clone(), &doc)?;
+                docs.insert(params.text_document.uri.to_string(), doc);
+            }
+        }
+
+        DidCloseTextDocument::METHOD => {
+            let params: lsp_types::DidCloseTextDocumentParams = serde_json::from_value(notification.params)?;
+            docs.remove(params.text_document.uri.as_str());
+        }
+
+        _ => {}
+    }
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let (connection, io_threads) = Connection::stdio();
+
+    let capabilities = ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        definition_provider: Some(OneOf::Left(true)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        ..ServerCapabilities::default()
+    };
+    connection.initialize(serde_json::to_value(capabilities)?)?;
+
+    // Keyed by URI text, since `Uri` caches parts of itself and makes a poor key.
+    let mut docs = HashMap::new();
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection.handle_shutdown(&request)? {
+                    break;
+                }
+                handle_request(&connection, &docs, request)?;
+            }
+            Message::Notification(notification) => handle_notification(&connection, &mut docs, notification)?,
+            Message::Response(_) => {}
+        }
+    }
+
+    io_threads.join()?;
+    Ok(())
+}
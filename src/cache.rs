@@ -0,0 +1,89 @@
+//! Cache of generated code keyed by a hash of the resolved program and the codegen options, so
+//! that recompiling a program whose statements haven't changed, as in watch mode after an edit to
+//! comments or whitespace, skips code generation.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use alloy_primitives::{hex, keccak256, B256};
+
+use crate::codegen::{self, Code};
+use crate::program::{Expression, Statement};
+use crate::scope::ResolvedBlock;
+use crate::{compile_with, Error, Options, Output};
+
+#[derive(Default)]
+pub struct Cache {
+    entries: HashMap<B256, Code>,
+    pub hits: usize,
+    pub misses: usize,
+}
+
+/// Hash of everything code generation depends on. Variables are hashed by index and not by name,
+/// so renaming them doesn't miss either.
+fn key(program: &ResolvedBlock, options: &codegen::Options) -> B256 {
+    let codegen::Options {
+        verify,
+        stable_registers,
+        minimize_swaps,
+        spill_base,
+        base_on_stack,
+        evm_version,
+        constant_pool,
+        constant_registers,
+        reserved_slots,
+        spill_depth,
+        treeify,
+        relocate_memory,
+        retries,
+        record_pre,
+        fuel,
+        // Nothing reads the seed yet, so it doesn't change the code.
+        seed: _,
+    } = options;
+    let mut text = format!(
+        "{verify} {stable_registers} {minimize_swaps} {spill_base} {base_on_stack} {evm_version} {constant_pool:?} {constant_registers:?} {reserved_slots} {spill_depth} {treeify} {relocate_memory} {retries} {record_pre} {fuel:?}\n"
+    );
+    for statement in &program.block.0 {
+        writeln!(text, "{statement}").unwrap();
+        // The name of an extern op is printed, but not the bytes it was declared with.
+        if let Statement(_, Expression::Verbatim(_, bytes, _)) = statement {
+            writeln!(text, "{}", hex::encode(bytes)).unwrap();
+        }
+    }
+    keccak256(text)
+}
+
+impl Cache {
+    pub fn new() -> Cache {
+        Cache::default()
+    }
+
+    /// Compiles `source` like [`crate::compile`], reusing the code generated earlier for the same
+    /// program and options.
+    pub fn compile(&mut self, source: &str, options: &Options) -> Result<Output, Error> {
+        compile_with(source, options, |program| {
+            let key = key(program, &options.codegen);
+            if let Some(code) = self.entries.get(&key) {
+                self.hits += 1;
+                return Ok(code.clone());
+            }
+            self.misses += 1;
+            let code = codegen::generate(program, &options.codegen)?;
+            self.entries.insert(key, code.clone());
+            Ok(code)
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
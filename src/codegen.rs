@@ -1,28 +1,120 @@
-use std::iter::repeat;
-use std::collections::HashMap;
+use std::iter::repeat_n;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Display;
+use std::ops::Range;
 
 use alloy_primitives::U256;
-use eyre::{ensure, Ok, Result};
+use eyre::{ensure, eyre, Result};
+use tracing::{debug, trace};
 
 use crate::scope::{ResolvedBlock, Var};
-use crate::program::{Expression, Statement};
-use crate::evm::{Instruction, DataInstruction, StackInstruction};
-use crate::analysis::count_occurrences;
+use crate::program::{Expression, Span, Statement};
+use crate::evm::{Instruction, DataInstruction, EvmVersion, StackInstruction, Word};
+use crate::analysis::{constant_offsets, constant_stores, count_occurrences};
+use crate::peephole::minimize_swaps;
+use crate::pool::{pool_constants, register_constants};
+
+/// Errors from generating code. Errors about stack depth carry the variable accessed where known,
+/// and the origin it was scheduled under; `generate` fills in the span of that statement.
+#[derive(Debug, thiserror::Error)]
+pub enum CodegenError {
+    #[error("Unknown operator: {0}")]
+    UnknownOperator(String),
+    #[error("Operator {op} is not available in {evm_version:?}")]
+    Unsupported { op: String, evm_version: EvmVersion },
+    #[error("Wrong number of arguments for {op}: expected {expected}, found {found}")]
+    WrongArgumentCount { op: String, expected: usize, found: usize },
+    #[error("Wrong number of results: expected {expected}, found {found}")]
+    WrongResultCount { expected: usize, found: usize },
+    #[error("Swap too deep: {depth}")]
+    SwapTooDeep { var: Option<Var>, depth: usize, statement: Option<usize>, span: Option<Span> },
+    #[error("Copy too deep: {depth}")]
+    DupTooDeep { var: Option<Var>, depth: usize, statement: Option<usize>, span: Option<Span> },
+    #[error("Unspillable value accessed too deep: {depth}")]
+    UnspillableTooDeep { var: Option<Var>, depth: usize, statement: Option<usize>, span: Option<Span> },
+    #[error("Store to address {address} in statement {statement} overlaps spill registers")]
+    SpillCollision { statement: usize, address: U256, span: Option<Span> },
+    #[error("Stack of {height} values on top of {reserved} reserved ones exceeds the limit of 1024")]
+    StackOverflow { height: usize, reserved: usize },
+    #[error("Stack underflow: {needed} values needed, but only {height} on the stack")]
+    StackUnderflow { needed: usize, height: usize },
+    #[error("alloc and revert messages need the spill registers at a fixed address, which base_on_stack doesn't give")]
+    RelocatableHeap,
+    #[error("treeify addresses its registers directly, which base_on_stack doesn't allow")]
+    RelocatableTreeify,
+    #[error("Internal codegen error: {0}")]
+    Internal(&'static str),
+    #[error("{0}")]
+    Verification(String),
+}
+
+impl CodegenError {
+    /// Records `origin` as the statement of a depth error that doesn't have one yet.
+    fn at(mut self, origin: usize) -> CodegenError {
+        use CodegenError::*;
+        if let SwapTooDeep { statement, .. } | DupTooDeep { statement, .. } | UnspillableTooDeep { statement, .. } = &mut self {
+            statement.get_or_insert(origin);
+        }
+        self
+    }
+
+    fn with_span(mut self, spans: &[Span]) -> CodegenError {
+        use CodegenError::*;
+        match &mut self {
+            SwapTooDeep { statement: Some(statement), span, .. }
+            | DupTooDeep { statement: Some(statement), span, .. }
+            | UnspillableTooDeep { statement: Some(statement), span, .. } => *span = spans.get(*statement).cloned(),
+            SpillCollision { statement, span, .. } => *span = spans.get(*statement).cloned(),
+            _ => (),
+        }
+        self
+    }
+}
 
 #[derive(Clone)]
 enum PreStackInstruction {
     Rotate { from_depth: usize, to_depth: usize },
     Dup(usize),
-    Push(Box<U256>),
+    Push(Word),
 }
 
 #[derive(Clone)]
 enum PreInstruction {
     Stack(PreStackInstruction),
-    Data(DataInstruction),
+    /// A data instruction, verbatim bytes, or an immutable.
+    Data(Instruction),
+    /// This many values already on the stack at the start, which emits no code.
+    Input(usize),
+    /// Moves the value on top into a register of its own, where it stays until its uses.
+    Store,
+    /// Pushes the value of a variable kept in a register, the last time if true.
+    Load(bool),
 }
 
-#[derive(Clone, Copy)]
+impl Display for PreInstruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PreInstruction::Stack(PreStackInstruction::Rotate { from_depth, to_depth }) => write!(f, "rotate {from_depth} {to_depth}"),
+            PreInstruction::Stack(PreStackInstruction::Dup(depth)) => write!(f, "dup {depth}"),
+            PreInstruction::Stack(PreStackInstruction::Push(c)) => write!(f, "push {c}"),
+            PreInstruction::Data(op) => write!(f, "{op}"),
+            PreInstruction::Input(n) => write!(f, "input {n}"),
+            PreInstruction::Store => write!(f, "store"),
+            PreInstruction::Load(last) => write!(f, "load{}", if *last { " last" } else { "" }),
+        }
+    }
+}
+
+fn arity(op: &Instruction) -> (usize, usize) {
+    match op {
+        Instruction::Data(op) => op.arity(),
+        Instruction::Verbatim(_, takes, returns) => (*takes as usize, *returns as usize),
+        Instruction::Immutable(_) => (0, 1),
+        _ => unreachable!("not an operation: {op}"),
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
 enum VarInstance {
     Main(Var),
     Copy(Var),
@@ -35,108 +127,189 @@ struct VarMeta {
 
 struct Machine {
     code: Vec<PreInstruction>,
+    origins: Vec<usize>,
+    defs: Vec<Var>,
+    statement: usize,
     stack: Vec<VarInstance>,
     meta: HashMap<Var, VarMeta>,
+    /// Depth from which values are out of reach, see [`Options::spill_depth`].
+    reach: usize,
 }
 
 impl Machine {
-    fn new() -> Machine {
+    fn new(reach: usize) -> Machine {
         Machine {
             code: Vec::new(),
+            origins: Vec::new(),
+            defs: Vec::new(),
+            statement: 0,
             stack: Vec::new(),
             meta: HashMap::new(),
+            reach,
         }
     }
 
-    fn get_meta(&mut self, name: Var) -> &mut VarMeta {
-        self.meta.get_mut(&name).unwrap()
+    fn emit(&mut self, instr: PreInstruction) {
+        self.code.push(instr);
+        self.origins.push(self.statement);
+    }
+
+    fn get_meta(&mut self, name: Var) -> Result<&mut VarMeta, CodegenError> {
+        self.meta.get_mut(&name).ok_or(CodegenError::Internal("variable not on stack"))
     }
 
-    fn set_location(&mut self, instance: VarInstance, index: Option<usize>) {
+    fn set_location(&mut self, instance: VarInstance, index: Option<usize>) -> Result<(), CodegenError> {
         match instance {
             VarInstance::Main(name) => {
                 if let Some(index) = index {
-                    self.get_meta(name).main_index = index;
+                    self.get_meta(name)?.main_index = index;
                 } else {
-                    let meta = self.meta.remove(&name).unwrap();
-                    assert!(meta.copy_index.is_none());
+                    let meta = self.meta.remove(&name).ok_or(CodegenError::Internal("variable not on stack"))?;
+                    if meta.copy_index.is_some() {
+                        return Err(CodegenError::Internal("variable removed while copy on stack"));
+                    }
                 }
             }
 
             VarInstance::Copy(name) => {
-                self.get_meta(name).copy_index = index;
+                self.get_meta(name)?.copy_index = index;
             }
         }
+        Ok(())
     }
 
-    fn find(&self, name: Var) -> usize {
-        let meta = self.meta.get(&name).unwrap();
-        let index = meta.copy_index.unwrap_or(meta.main_index);
-        let depth = self.stack.len() - 1 - index;
-        depth
+    /// Depth of the copy of `name` if there is one within reach, and otherwise of the original,
+    /// which unlike copies can be spilled.
+    fn find(&self, name: Var) -> Result<usize, CodegenError> {
+        let meta = self.meta.get(&name).ok_or(CodegenError::Internal("variable not on stack"))?;
+        let top_index = self.stack.len() - 1;
+        let index = meta.copy_index.filter(|&i| within_reach(top_index - i, self.reach)).unwrap_or(meta.main_index);
+        Ok(top_index - index)
+    }
+
+    /// Checks that there are at least `needed` values on the stack.
+    fn ensure_height(&self, needed: usize) -> Result<(), CodegenError> {
+        let height = self.stack.len();
+        if height < needed {
+            return Err(CodegenError::StackUnderflow { needed, height });
+        }
+        Ok(())
     }
 
-    fn pop(&mut self) {
-        let instance = self.stack.pop().unwrap();
-        self.set_location(instance, None);
-        self.code.push(PreInstruction::Stack(PreStackInstruction::Rotate { from_depth: 0, to_depth: 0 }));
-        self.code.push(PreInstruction::Data(DataInstruction::Pop));
+    fn pop(&mut self) -> Result<(), CodegenError> {
+        let instance = self.stack.pop().ok_or(CodegenError::Internal("pop from empty stack"))?;
+        trace!(?instance, "pop dead value");
+        self.set_location(instance, None)?;
+        self.emit(PreInstruction::Data(Instruction::Data(DataInstruction::Pop)));
+        Ok(())
+    }
+
+    /// Starts with `inputs` on the stack, the last on top.
+    fn input(&mut self, inputs: &[Var]) {
+        trace!(?inputs, "input");
+        for &name in inputs {
+            self.stack.push(VarInstance::Main(name));
+            self.meta.insert(name, VarMeta { main_index: self.stack.len() - 1, copy_index: None });
+        }
+        self.defs.extend(inputs);
+        self.emit(PreInstruction::Input(inputs.len()));
     }
 
     fn push(&mut self, name: Var, value: U256) {
+        trace!(var = %name, %value, "push");
+        self.stack.push(VarInstance::Main(name));
+        self.defs.push(name);
+        self.meta.insert(name, VarMeta {
+            main_index: self.stack.len() - 1,
+            copy_index: None,
+        });
+        self.emit(PreInstruction::Stack(PreStackInstruction::Push(value.into())));
+    }
+
+    /// Moves `name`, which must be on top, out to memory for good.
+    fn store(&mut self, name: Var) -> Result<(), CodegenError> {
+        if !matches!(self.stack.last(), Some(VarInstance::Main(top)) if *top == name) {
+            return Err(CodegenError::Internal("stored variable not on top"));
+        }
+        trace!(var = %name, "store in memory");
+        self.stack.pop();
+        self.set_location(VarInstance::Main(name), None)?;
+        self.emit(PreInstruction::Store);
+        Ok(())
+    }
+
+    fn load(&mut self, name: Var, last: bool) {
+        trace!(var = %name, last, "load from memory");
         self.stack.push(VarInstance::Main(name));
+        self.defs.push(name);
         self.meta.insert(name, VarMeta {
             main_index: self.stack.len() - 1,
             copy_index: None,
         });
-        self.code.push(PreInstruction::Stack(PreStackInstruction::Push(value.into())));
+        self.emit(PreInstruction::Load(last));
     }
 
-    fn stack_swap(&mut self, from_depth: usize, to_depth: usize) {
+    fn stack_swap(&mut self, from_depth: usize, to_depth: usize) -> Result<(), CodegenError> {
         let top_index = self.stack.len() - 1;
         let from_index = top_index - from_depth;
         let to_index = top_index - to_depth;
         let from_instance = self.stack[from_index];
         let to_instance = self.stack[to_index];
         self.stack.swap(from_index, to_index);
-        self.set_location(from_instance, Some(to_index));
-        self.set_location(to_instance, Some(from_index));
+        self.set_location(from_instance, Some(to_index))?;
+        self.set_location(to_instance, Some(from_index))
     }
 
-    fn rotate_to(&mut self, from_name: Var, to_depth: usize) {
-        assert!(to_depth <= 16, "Swap too deep");
-        let from_depth = self.find(from_name);
-        self.stack_swap(from_depth, 0);
-        self.stack_swap(0, to_depth);
-        self.code.push(PreInstruction::Stack(PreStackInstruction::Rotate { from_depth, to_depth }));
+    fn rotate_to(&mut self, from_name: Var, to_depth: usize) -> Result<(), CodegenError> {
+        if !within_reach(to_depth, self.reach) {
+            return Err(CodegenError::SwapTooDeep { var: Some(from_name), depth: to_depth, statement: Some(self.statement), span: None });
+        }
+        self.ensure_height(to_depth + 1)?;
+        let from_depth = self.find(from_name)?;
+        trace!(var = %from_name, from_depth, to_depth, "rotate into place");
+        self.stack_swap(from_depth, 0)?;
+        self.stack_swap(0, to_depth)?;
+        self.emit(PreInstruction::Stack(PreStackInstruction::Rotate { from_depth, to_depth }));
+        Ok(())
     }
 
-    fn copy_to(&mut self, from_name: Var, to_depth: usize) {
-        assert!(to_depth <= 16, "Copy too deep");
+    fn copy_to(&mut self, from_name: Var, to_depth: usize) -> Result<(), CodegenError> {
+        if !within_reach(to_depth, self.reach) {
+            return Err(CodegenError::DupTooDeep { var: Some(from_name), depth: to_depth, statement: Some(self.statement), span: None });
+        }
+        // The copy itself adds one value.
+        self.ensure_height(to_depth)?;
 
-        let from_depth = self.find(from_name);
+        let from_depth = self.find(from_name)?;
+        trace!(var = %from_name, from_depth, to_depth, "copy into place, value is used again");
         let copy_instance = VarInstance::Copy(from_name);
         self.stack.push(copy_instance);
-        self.set_location(copy_instance, Some(self.stack.len() - 1));
-        self.code.push(PreInstruction::Stack(PreStackInstruction::Dup(from_depth)));
+        self.set_location(copy_instance, Some(self.stack.len() - 1))?;
+        self.emit(PreInstruction::Stack(PreStackInstruction::Dup(from_depth)));
 
         if to_depth != 0 {
-            self.stack_swap(0, to_depth);
-            self.code.push(PreInstruction::Stack(PreStackInstruction::Rotate { from_depth: 0, to_depth }));
+            self.stack_swap(0, to_depth)?;
+            self.emit(PreInstruction::Stack(PreStackInstruction::Rotate { from_depth: 0, to_depth }));
         }
+        Ok(())
     }
 
-    fn apply(&mut self, op: DataInstruction, ress: &[Var]) {
-        let (nargs, nress) = op.arity();
+    fn apply(&mut self, op: Instruction, ress: &[Var]) -> Result<(), CodegenError> {
+        let (nargs, nress) = arity(&op);
+        self.ensure_height(nargs)?;
         let stack_base = self.stack.len() - nargs;
+        trace!(%op, ?ress, "apply");
 
         let removed = self.stack.split_off(stack_base);
-        for &instance in &removed {
-            self.set_location(instance, None);
+        // Copies go first, so that a variable consumed along with its copy is removed last.
+        let (copies, mains): (Vec<_>, Vec<_>) = removed.into_iter().partition(|i| matches!(i, VarInstance::Copy(_)));
+        for instance in copies.into_iter().chain(mains) {
+            self.set_location(instance, None)?;
         }
         self.stack.extend(ress.iter().map(|&name| VarInstance::Main(name)));
+        self.defs.extend(&ress[..nress]);
 
-        self.code.push(PreInstruction::Data(op));
+        self.emit(PreInstruction::Data(op));
 
         for (i, &name) in ress.iter().enumerate() {
             self.meta.insert(name, VarMeta {
@@ -144,6 +317,7 @@ impl Machine {
                 copy_index: None,
             });
         }
+        Ok(())
     }
 }
 
@@ -158,305 +332,1374 @@ struct Spill {
     outward: bool,
 }
 
-fn make_spills(machine: &Machine) -> Vec<Spill> {
-    enum SpillStatus {
-        Unspillable,
-        MaybeSpilled(SpillLocation),
-        Spilled,
-        MaybeRestored(SpillLocation),
-        Restored,
-    }
+enum SpillStatus {
+    Unspillable,
+    MaybeSpilled(SpillLocation),
+    Spilled,
+    MaybeRestored(SpillLocation),
+}
 
-    use SpillStatus::*;
+impl SpillStatus {
+    fn set_reachable_at(&mut self, location: SpillLocation) -> Result<(), CodegenError> {
+        use SpillStatus::*;
 
-    impl SpillStatus {
-        fn set_reachable_at(&mut self, location: SpillLocation) {
-            assert!(location.depth < 16);
-            match *self {
-                Unspillable => (),
-                MaybeSpilled(_) => *self = MaybeSpilled(location),
-                Spilled => *self = MaybeRestored(location),
-                MaybeRestored(_) => (),
-                Restored => panic!("already restored?"),
-            }
+        if !within_reach(location.depth, MAX_REACH) {
+            return Err(CodegenError::Internal("reachable location too deep"));
+        }
+        match *self {
+            Unspillable => (),
+            MaybeSpilled(_) => *self = MaybeSpilled(location),
+            Spilled => *self = MaybeRestored(location),
+            MaybeRestored(_) => (),
         }
+        Ok(())
     }
 
-    struct State {
-        stack: Vec<SpillStatus>,
-        spills: Vec<Spill>,
+    fn pending_location(&self) -> Option<SpillLocation> {
+        match *self {
+            SpillStatus::MaybeSpilled(l) | SpillStatus::MaybeRestored(l) => Some(l),
+            _ => None,
+        }
     }
+}
 
-    impl State {
-        fn ensure_reachable(&mut self, depth: usize) {
-            if depth >= 16 {
-                let index = self.stack.len() - 1 - depth;
-                let ref mut status = self.stack[index];
-                match *status {
-                    Unspillable => panic!("unspillable accessed too deep"),
-                    MaybeSpilled(l) => {
-                        *status = Spilled;
-                        self.spills.push(Spill { location: l, outward: true });
-                    }
-                    Spilled => (),
-                    MaybeRestored(_) => *status = Spilled,
-                    Restored => panic!("restored back too deep"),
+/// Decides spills as pre-instructions are produced. A spill is placed at the last location where the
+/// value was still reachable, so decisions refer back to earlier pre-instructions.
+struct Spiller {
+    stack: Vec<SpillStatus>,
+    spills: Vec<Spill>,
+    reach: usize,
+}
+
+impl Spiller {
+    fn new(reach: usize) -> Spiller {
+        Spiller {
+            stack: Vec::new(),
+            spills: Vec::new(),
+            reach,
+        }
+    }
+
+    fn ensure_reachable(&mut self, depth: usize) -> Result<(), CodegenError> {
+        use SpillStatus::*;
+
+        if !within_reach(depth, self.reach) {
+            let index = self.stack.len() - 1 - depth;
+            let status = &mut self.stack[index];
+            match *status {
+                Unspillable => return Err(CodegenError::UnspillableTooDeep { var: None, depth, statement: None, span: None }),
+                MaybeSpilled(l) => {
+                    debug!(depth, at = l.code_index, at_depth = l.depth, "spill: value needed out of reach, stored where it was last reachable");
+                    *status = Spilled;
+                    self.spills.push(Spill { location: l, outward: true });
+                }
+                Spilled => (),
+                MaybeRestored(_) => {
+                    trace!(depth, "value out of reach again, left in memory");
+                    *status = Spilled;
                 }
             }
         }
+        Ok(())
     }
 
-    let mut state = State {
-        stack: Vec::with_capacity(machine.stack.capacity()),
-        spills: Vec::new(),
-    };
+    fn step(&mut self, code_index: usize, instr: &PreInstruction) -> Result<(), CodegenError> {
+        use SpillStatus::*;
 
-    for (code_index, instr) in machine.code.iter().enumerate() {
         match *instr {
             PreInstruction::Stack(PreStackInstruction::Rotate { from_depth, to_depth }) => {
-                assert!(to_depth < 16);
+                if !within_reach(to_depth, self.reach) {
+                    return Err(CodegenError::SwapTooDeep { var: None, depth: to_depth, statement: None, span: None });
+                }
 
-                let top_index = state.stack.len() - 1;
+                let top_index = self.stack.len() - 1;
                 let from_index = top_index - from_depth;
                 let to_index = top_index - to_depth;
 
-                state.ensure_reachable(from_depth);
+                self.ensure_reachable(from_depth)?;
 
-                if from_depth < 16 {
-                    state.stack[from_index].set_reachable_at(SpillLocation { code_index, depth: to_depth });
+                if within_reach(from_depth, self.reach) {
+                    self.stack[from_index].set_reachable_at(SpillLocation { code_index, depth: to_depth })?;
                 } else {
-                    assert!(!matches!(state.stack[top_index], Unspillable));
-                    state.stack[top_index] = Spilled;
+                    if matches!(self.stack[top_index], Unspillable) {
+                        return Err(CodegenError::UnspillableTooDeep { var: None, depth: from_depth, statement: None, span: None });
+                    }
+                    self.stack[top_index] = Spilled;
 
-                    assert!(matches!(state.stack[from_index], Spilled));
-                    state.stack[from_index] = Restored;
+                    if !matches!(self.stack[from_index], Spilled) {
+                        return Err(CodegenError::Internal("deep value not spilled"));
+                    }
+                    // Back on the stack, and may be spilled again from where it lands.
+                    trace!(from_depth, to_depth, at = code_index, "deep value loaded from memory");
+                    self.stack[from_index] = MaybeSpilled(SpillLocation { code_index, depth: to_depth });
                 }
 
-                state.stack.swap(from_index, top_index);
-                state.stack.swap(top_index, to_index);
+                self.stack.swap(from_index, top_index);
+                self.stack.swap(top_index, to_index);
             }
 
             PreInstruction::Stack(PreStackInstruction::Dup(depth)) => {
-                state.ensure_reachable(depth);
-                if depth + 1 < 16 {
-                    let index = state.stack.len() - 1 - depth;
-                    state.stack[index].set_reachable_at(SpillLocation { code_index, depth: depth + 1 });
+                self.ensure_reachable(depth)?;
+                if within_reach(depth + 1, self.reach) {
+                    let index = self.stack.len() - 1 - depth;
+                    self.stack[index].set_reachable_at(SpillLocation { code_index, depth: depth + 1 })?;
                 }
-                state.stack.push(Unspillable);
+                self.stack.push(Unspillable);
             }
 
-            PreInstruction::Stack(PreStackInstruction::Push(_)) => {
-                state.stack.push(MaybeSpilled(SpillLocation { code_index, depth: 0 }));
+            PreInstruction::Stack(PreStackInstruction::Push(_)) | PreInstruction::Load(_) => {
+                self.stack.push(MaybeSpilled(SpillLocation { code_index, depth: 0 }));
             }
 
-            PreInstruction::Data(op) => {
-                let (nargs, nress) = op.arity();
-                for status in state.stack.drain(state.stack.len() - nargs..) {
-                    if let MaybeRestored(l) = status {
-                        state.spills.push(Spill { location: l, outward: false });
-                    } else if let Spilled = status {
-                        panic!("spilled value not restored");
+            PreInstruction::Store => match self.stack.pop() {
+                Some(MaybeRestored(l)) => {
+                    debug!(at = l.code_index, at_depth = l.depth, "restore: value stored, written back where it was last reachable");
+                    self.spills.push(Spill { location: l, outward: false });
+                }
+                Some(Spilled) => return Err(CodegenError::Internal("spilled value not restored")),
+                Some(_) => (),
+                None => return Err(CodegenError::Internal("store from empty stack")),
+            },
+
+            // Inputs out of reach from the start can't be stored anywhere.
+            PreInstruction::Input(n) => {
+                self.stack.extend((0..n).rev().map(|depth| {
+                    if within_reach(depth, self.reach) { MaybeSpilled(SpillLocation { code_index, depth }) } else { Unspillable }
+                }));
+            }
+
+            PreInstruction::Data(ref op) => {
+                let (nargs, nress) = arity(op);
+                // A popped value is discarded from memory as well, so it is never written back.
+                let discard = matches!(op, Instruction::Data(DataInstruction::Pop));
+                for status in self.stack.drain(self.stack.len() - nargs..) {
+                    match status {
+                        MaybeRestored(l) if !discard => {
+                            debug!(at = l.code_index, at_depth = l.depth, "restore: operand consumed, written back where it was last reachable");
+                            self.spills.push(Spill { location: l, outward: false });
+                        }
+                        Spilled if !discard => return Err(CodegenError::Internal("spilled value not restored")),
+                        _ => (),
                     }
                 }
-                state.stack.extend((0..nress).rev().map(|depth|
+                self.stack.extend((0..nress).rev().map(|depth|
                     MaybeSpilled(SpillLocation { code_index, depth })
                 ));
             }
         }
+
+        Ok(())
+    }
+
+    /// Index of the earliest pre-instruction that may still receive a spill.
+    fn settled(&self, next_index: usize) -> usize {
+        self.stack.iter()
+            .filter_map(|s| s.pending_location())
+            .map(|l| l.code_index)
+            .min()
+            .unwrap_or(next_index)
+    }
+
+    /// Writes back the values left on the stack at the end that are still in memory.
+    fn restore_remaining(&mut self) -> Result<(), CodegenError> {
+        for status in &mut self.stack {
+            match *status {
+                SpillStatus::MaybeRestored(l) => {
+                    debug!(at = l.code_index, at_depth = l.depth, "restore: value left on the stack at the end");
+                    self.spills.push(Spill { location: l, outward: false });
+                    *status = SpillStatus::Unspillable;
+                }
+                SpillStatus::Spilled => return Err(CodegenError::Internal("value left at the end out of reach")),
+                _ => (),
+            }
+        }
+        Ok(())
     }
 
-    state.spills.sort_unstable_by_key(|s| s.location.code_index);
-    state.spills
+    /// Removes the spills located before `until`, in code order.
+    fn take_settled(&mut self, until: usize) -> Vec<Spill> {
+        let (mut ready, rest): (Vec<_>, Vec<_>) = self.spills.drain(..).partition(|s| s.location.code_index < until);
+        self.spills = rest;
+        ready.sort_by_key(|s| s.location.code_index);
+        ready
+    }
 }
 
-fn register_store(register: usize) -> impl Iterator<Item=Instruction> {
-    use Instruction::*;
-    use StackInstruction::*;
-    use DataInstruction::*;
+/// Immutable that codegen replaces with the address where the memory for `alloc` and revert
+/// messages starts, past the spill registers and past the first 0x80 bytes, which are reserved as
+/// in Solidity.
+pub const HEAP_START: &str = "%heap";
 
-    let ptr = (register * 32).try_into().unwrap();
-    [
-        Stack(Push(Box::new(ptr))),
-        Data(Mstore),
-    ].into_iter()
+/// Prefix of the immutables codegen replaces with a constant memory offset moved past the spill
+/// registers, followed by the offset in decimal.
+pub(crate) const RELOCATED: &str = "%memory";
+
+pub(crate) fn register_address(spill_base: U256, register: usize) -> U256 {
+    spill_base + U256::from(register * 32)
 }
 
-fn register_load(register: usize) -> impl Iterator<Item=Instruction> {
-    use Instruction::*;
-    use StackInstruction::*;
-    use DataInstruction::*;
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Value {
+    Var(Var),
+    Const(U256),
+    /// One of the values beneath the program's stack, numbered from the bottom.
+    Reserved(usize),
+    Unknown,
+}
 
-    let ptr = (register * 32).try_into().unwrap();
-    [
-        Stack(Push(Box::new(ptr))), // todo: fix register location
-        Data(Mload),
-    ].into_iter()
+/// Symbolically executes the final instruction stream alongside the Machine's model of the stack,
+/// checking after every pre-instruction that each variable is where the model says it is, and that
+/// the reserved values beneath are left alone.
+struct Checker {
+    model: Vec<Var>,
+    stack: Vec<Value>,
+    memory: HashMap<U256, Value>,
+    spill_base: U256,
+    reserved: usize,
 }
 
-pub fn generate(rblock: &ResolvedBlock) -> Result<impl Iterator<Item=Instruction>> {
-    let mut occurs = count_occurrences(&rblock);
-    let mut machine = Machine::new();
+impl Checker {
+    fn new(spill_base: U256, reserved: usize) -> Checker {
+        Checker {
+            model: Vec::new(),
+            stack: (0..reserved).map(Value::Reserved).collect(),
+            memory: HashMap::new(),
+            spill_base,
+            reserved,
+        }
+    }
 
-    for Statement(ress, e) in &rblock.block.0 {
-        match *e {
-            Expression::Const(c) => {
-                ensure!(ress.len() == 1, "Wrong number of results");
-                let name = ress[0];
-                machine.push(name, c);
-            }
-
-            Expression::Op(ref op, ref args) => {
-                let op: DataInstruction = op.parse()?;
-                let (nargs, nres) = op.arity();
-
-                ensure!(args.len() == nargs, "Wrong number of arguments");
-                ensure!(ress.len() == nres, "Wrong number of results");
-
-                let mut ndups = 0;
-                let dups: Vec<_> = args.iter()
-                    .map(|&a| {
-                        occurs[a.index()] -= 1;
-                        let dup = occurs[a.index()] > 0;
-                        if dup { ndups += 1; }
-                        dup
-                    })
-                    .collect();
-
-                for (i, (&arg, dup)) in args.iter().zip(dups).enumerate().rev() {
-                    if dup { ndups -= 1; }
-                    let to_depth = i - ndups;
-                    if dup {
-                        machine.copy_to(arg, to_depth);
-                    } else {
-                        machine.rotate_to(arg, to_depth);
+    fn constant(&self, value: Value) -> Option<U256> {
+        match value {
+            Value::Const(c) => Some(c),
+            Value::Var(v) if v == Var::RESERVED => Some(self.spill_base),
+            _ => None,
+        }
+    }
+
+    fn step(&mut self, instr: &PreInstruction, results: &[Var], emitted: &[Instruction], registers: &[Option<usize>]) -> Result<()> {
+        match *instr {
+            PreInstruction::Stack(PreStackInstruction::Rotate { from_depth, to_depth }) => {
+                let top_index = self.model.len() - 1;
+                self.model.swap(top_index - from_depth, top_index);
+                self.model.swap(top_index, top_index - to_depth);
+            }
+
+            PreInstruction::Stack(PreStackInstruction::Dup(depth)) => {
+                self.model.push(self.model[self.model.len() - 1 - depth]);
+            }
+
+            PreInstruction::Stack(PreStackInstruction::Push(_)) => {
+                self.model.push(results[0]);
+            }
+
+            PreInstruction::Data(ref op) => {
+                let (nargs, _) = arity(op);
+                self.model.truncate(self.model.len() - nargs);
+                self.model.extend(results);
+            }
+
+            PreInstruction::Input(_) => {
+                self.model.extend(results);
+                self.stack.extend(results.iter().map(|&v| Value::Var(v)));
+            }
+
+            PreInstruction::Store => {
+                self.model.pop();
+            }
+
+            PreInstruction::Load(_) => {
+                self.model.push(results[0]);
+            }
+        }
+
+        // The first instruction emitted for a push or an operation is the one that defines its results.
+        let defines = matches!(instr, PreInstruction::Stack(PreStackInstruction::Push(_)) | PreInstruction::Data(_));
+
+        for (i, e) in emitted.iter().enumerate() {
+            if defines && i == 0 {
+                let nargs = match e {
+                    Instruction::Data(_) | Instruction::Verbatim(..) | Instruction::Immutable(_) => arity(e).0,
+                    _ => 0,
+                };
+                ensure!(self.stack.len() >= self.reserved + nargs, "Verification failed: stack underflow at {e}");
+                self.stack.truncate(self.stack.len() - nargs);
+                self.stack.extend(results.iter().map(|&v| Value::Var(v)));
+            } else {
+                self.execute(e)?;
+            }
+        }
+
+        ensure!(self.stack.len() == self.reserved + self.model.len(), "Verification failed: stack has {} items, expected {}", self.stack.len(), self.reserved + self.model.len());
+        ensure!(registers.len() == self.model.len(), "Verification failed: register map has {} items, expected {}", registers.len(), self.model.len());
+
+        let (reserved, stack) = self.stack.split_at(self.reserved);
+        if let Some(slot) = reserved.iter().enumerate().position(|(i, &value)| value != Value::Reserved(i)) {
+            return Err(eyre!("Verification failed: reserved slot {slot} disturbed"));
+        }
+        for (index, (&var, &register)) in self.model.iter().zip(registers).enumerate() {
+            let actual = match register {
+                Some(register) => self.memory.get(&register_address(self.spill_base, register)).copied().unwrap_or(Value::Unknown),
+                None => stack[index],
+            };
+            ensure!(actual == Value::Var(var), "Verification failed: slot {index} holds {actual:?}, expected var {}", var.index());
+        }
+
+        Ok(())
+    }
+
+    fn execute(&mut self, instr: &Instruction) -> Result<()> {
+        use DataInstruction::*;
+        use StackInstruction::*;
+
+        let len = self.stack.len();
+        match instr {
+            Instruction::Stack(Dup(depth)) => {
+                ensure!(*depth < len, "Verification failed: stack underflow at {instr}");
+                self.stack.push(self.stack[len - 1 - depth]);
+            }
+
+            Instruction::Stack(Swap(depth)) => {
+                ensure!(*depth < len, "Verification failed: stack underflow at {instr}");
+                self.stack.swap(len - 1, len - 1 - depth);
+            }
+
+            Instruction::Stack(Push(c)) => self.stack.push(Value::Const(c.value())),
+
+            Instruction::Data(Mstore) => {
+                ensure!(len >= 2, "Verification failed: stack underflow at {instr}");
+                let Some(address) = self.stack.pop().and_then(|a| self.constant(a)) else {
+                    return Err(eyre!("Verification failed: spill store to unknown address"));
+                };
+                let value = self.stack.pop().unwrap();
+                self.memory.insert(address, value);
+            }
+
+            Instruction::Data(Mload) => {
+                let Some(address) = self.stack.pop().and_then(|a| self.constant(a)) else {
+                    return Err(eyre!("Verification failed: spill load from unknown address"));
+                };
+                self.stack.push(self.memory.get(&address).copied().unwrap_or(Value::Unknown));
+            }
+
+            Instruction::Data(Add) => {
+                ensure!(len >= 2, "Verification failed: stack underflow at {instr}");
+                let a = self.stack.pop().unwrap();
+                let b = self.stack.pop().unwrap();
+                let sum = self.constant(a).zip(self.constant(b)).map(|(a, b)| a + b);
+                self.stack.push(sum.map_or(Value::Unknown, Value::Const));
+            }
+
+            Instruction::Data(_) | Instruction::Verbatim(..) | Instruction::Immutable(_) => {
+                let (nargs, nress) = arity(instr);
+                ensure!(len >= nargs, "Verification failed: stack underflow at {instr}");
+                self.stack.truncate(len - nargs);
+                self.stack.extend(repeat_n(Value::Unknown, nress));
+            }
+
+            Instruction::Control(_) => return Err(eyre!("Verification failed: unexpected control flow")),
+            Instruction::Unknown(_) | Instruction::Truncated(_) => {
+                return Err(eyre!("Verification failed: unexpected instruction {instr}"))
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub struct Options {
+    /// Check the generated code against the stack model while lowering.
+    pub verify: bool,
+    /// Give each spilled variable its own register, numbered in order of first spill, instead of
+    /// reusing freed registers. Costs memory but keeps offsets stable across small input changes.
+    pub stable_registers: bool,
+    /// Rewrite chains of swaps into minimal ones.
+    pub minimize_swaps: bool,
+    /// Memory address where the spill registers start, raised to 0x80 in a program that uses the
+    /// heap so that they stay clear of the free memory pointer.
+    pub spill_base: U256,
+    /// Keep the spill base address in a dedicated stack slot and address registers relative to it,
+    /// so that the region can be positioned at runtime by replacing the initial push.
+    pub base_on_stack: bool,
+    /// Target EVM version, which determines the operators available and how pushes are encoded.
+    pub evm_version: EvmVersion,
+    /// Move constants into a pool of words appended to the code, loading them with `codecopy`
+    /// wherever that saves at least this many bytes overall. The loads go through one more memory
+    /// register past the spill registers. Ignored in `base_on_stack` mode, since the pool loads use
+    /// absolute addresses.
+    pub constant_pool: Option<usize>,
+    /// Store constants pushed repeatedly into memory registers past the spill registers at the start
+    /// of the code, and load them from there wherever that saves at least this many bytes overall.
+    /// Each use costs 3 more gas than a push. Ignored in `base_on_stack` mode.
+    pub constant_registers: Option<usize>,
+    /// Number of values beneath the program's stack that it must leave in place, such as those of a
+    /// surrounding routine. They count toward the stack limit, and verification checks that no
+    /// instruction reaches them.
+    pub reserved_slots: usize,
+    /// Depth from which a value counts as out of reach and is spilled, between 1 and 16, or 8 in
+    /// `base_on_stack` mode so that the base stays within reach beneath the arguments of any
+    /// operation. Below 16 it leaves headroom for values pushed while the arguments of an operation
+    /// are set up, at the cost of more spills. With a depth of `N` once within those bounds, the
+    /// code uses no `dup` past `dupN` and no `swap` past `swapN`.
+    pub spill_depth: usize,
+    /// Generate code without swaps, in the manner of solc's old treeify: constants and pure
+    /// operations used once are recomputed where they are used, and every other value is stored
+    /// in a register of its own when it is defined and loaded from there at each use. Not
+    /// available in `base_on_stack` mode.
+    pub treeify: bool,
+    /// Move the program's memory past the spill registers, by adding their end to every constant
+    /// used only as a memory offset, and to the start of the heap. [`crate::compile`] first splits
+    /// off the offset uses of a constant that is also used otherwise, see
+    /// [`crate::analysis::split_offsets`]. Offsets computed at runtime are left as they are.
+    /// Ignored in `base_on_stack` mode, since the end of the registers isn't known until runtime.
+    pub relocate_memory: bool,
+    /// When scheduling fails because a value would have to be accessed too deep, schedule again up
+    /// to this many times, each time with one more variable stored to memory as soon as it is
+    /// defined and loaded back at each use, to make room on the stack.
+    pub retries: usize,
+    /// Record the pre-instructions and the spills decided after each one in `Code::pre`.
+    pub record_pre: bool,
+    /// Apply at most this many optional optimizations: reusing a freed register, rewriting a run
+    /// of swaps, or pooling a constant or keeping it in a register. Bisecting on it finds the one that breaks a program.
+    pub fuel: Option<usize>,
+    /// Seed for heuristics that make random choices, so that their results can be reproduced. None
+    /// of the current ones do: for a given program and options the output is the same on every run
+    /// and platform, whatever the seed.
+    pub seed: u64,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            verify: cfg!(debug_assertions),
+            stable_registers: false,
+            minimize_swaps: true,
+            spill_base: U256::ZERO,
+            base_on_stack: false,
+            evm_version: EvmVersion::default(),
+            constant_pool: None,
+            constant_registers: None,
+            reserved_slots: 0,
+            spill_depth: 16,
+            treeify: false,
+            relocate_memory: false,
+            retries: 16,
+            record_pre: false,
+            fuel: None,
+            seed: 0,
+        }
+    }
+}
+
+impl Options {
+    /// [`Options::spill_depth`] brought within its bounds.
+    fn reach(&self) -> usize {
+        self.spill_depth.clamp(if self.base_on_stack { 8 } else { 1 }, MAX_REACH)
+    }
+}
+
+/// Depths `dup16` can copy from, 0 to 15. `swap16` also reaches 16, but a value placed there could
+/// never be copied back, so values are only ever placed within reach as well.
+const MAX_REACH: usize = 16;
+
+/// Whether a value at `depth` can be copied, or placed to be used again, with `reach` as given by
+/// [`Options::reach`].
+fn within_reach(depth: usize, reach: usize) -> bool {
+    depth < reach
+}
+
+/// What is left of [`Options::fuel`].
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Fuel(Option<usize>);
+
+impl Fuel {
+    pub(crate) fn new(fuel: Option<usize>) -> Fuel {
+        Fuel(fuel)
+    }
+
+    /// Whether the optimization `what` may be applied, using up one unit of fuel if so.
+    pub(crate) fn consume(&mut self, what: &str) -> bool {
+        match &mut self.0 {
+            None => true,
+            Some(0) => {
+                debug!("out of fuel, skipping {what}");
+                false
+            }
+            Some(fuel) => {
+                *fuel -= 1;
+                debug!(remaining = *fuel, "{what}");
+                true
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Code {
+    pub instructions: Vec<Instruction>,
+    /// Index of the source statement each instruction was generated for.
+    pub origins: Vec<usize>,
+    /// Number of memory registers used, starting at the spill base. Includes the registers holding
+    /// constants and the scratch register for loads from the constant pool.
+    pub register_count: usize,
+    /// Address of the first register.
+    pub spill_base: U256,
+    /// Whether the first instruction pushes the spill base to keep it on the stack.
+    pub base_on_stack: bool,
+    /// Number of values beneath the program's stack, left untouched.
+    pub reserved_slots: usize,
+    /// What was added to the constant memory offsets of the program, if its memory was relocated.
+    pub memory_offset: Option<U256>,
+    /// Address where the memory handed out by `alloc` starts.
+    pub heap_start: U256,
+    pub evm_version: EvmVersion,
+    /// The pre-instructions with their spills, if recorded.
+    pub pre: Vec<String>,
+    pub spills: Vec<SpillEvent>,
+}
+
+/// A value moved into a memory register, or out of one when it is restored.
+#[derive(Debug, Clone, Copy)]
+pub struct SpillEvent {
+    pub statement: usize,
+    pub var: Var,
+    pub register: usize,
+    pub restore: bool,
+    /// Gas of the instructions moving the value, without memory expansion.
+    pub gas: u64,
+}
+
+impl Code {
+    /// Bytes of memory taken by the spill registers, the scratch register of the pool included.
+    pub fn spill_size(&self) -> usize {
+        32 * self.register_count
+    }
+
+    /// Addresses of the spill registers. In `base_on_stack` mode they are relative to the base on
+    /// the stack, and this is where they are before the initial push is replaced.
+    pub fn spill_region(&self) -> Range<U256> {
+        self.spill_base..register_address(self.spill_base, self.register_count)
+    }
+
+    /// Gas to expand memory from empty to the end of the spill registers, which bounds what
+    /// spilling adds to the memory expansion of the code.
+    pub fn spill_memory_cost(&self) -> u64 {
+        if self.register_count == 0 {
+            return 0;
+        }
+        crate::evm::memory_cost(self.spill_region().end.try_into().unwrap_or(u64::MAX))
+    }
+
+    pub fn annotated<'a>(&'a self, rblock: &'a ResolvedBlock) -> Annotated<'a> {
+        Annotated { code: self, rblock }
+    }
+
+    /// Source map in the compressed format of solc, mapping each opcode to the span of the
+    /// statement it was generated for in source file 0. `None` if the block has no spans.
+    pub fn source_map(&self, rblock: &ResolvedBlock) -> Option<String> {
+        let spans = &rblock.block.1;
+        if spans.is_empty() {
+            return None;
+        }
+
+        let mut entries = Vec::new();
+        let mut last: Option<[String; 4]> = None;
+        for (instr, &origin) in self.instructions.iter().zip(&self.origins) {
+            let span = &spans[origin];
+            let fields = [span.start.to_string(), span.len().to_string(), "0".to_string(), "-".to_string()];
+            for _ in 0..instr.opcode_count() {
+                // Fields equal to the previous entry's are left empty, and trailing empty ones dropped.
+                let entry = match &last {
+                    None => fields.join(":"),
+                    Some(last) => {
+                        let len = (0..4).rev().find(|&i| fields[i] != last[i]).map_or(0, |i| i + 1);
+                        fields[..len].iter().zip(last)
+                            .map(|(field, last)| if field == last { "" } else { field.as_str() })
+                            .collect::<Vec<_>>()
+                            .join(":")
                     }
-                }
+                };
+                entries.push(entry);
+                last = Some(fields.clone());
+            }
+        }
+        Some(entries.join(";"))
+    }
+}
 
-                machine.apply(op, ress);
+/// Displays the instructions with a comment naming the source statement before each group, and
+/// before that the source comments attached to it.
+pub struct Annotated<'a> {
+    code: &'a Code,
+    rblock: &'a ResolvedBlock,
+}
+
+impl Display for Annotated<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut last = None;
+        let mut comments = self.rblock.block.2.iter().peekable();
+        for (instr, &origin) in self.code.instructions.iter().zip(&self.code.origins) {
+            if last != Some(origin) {
+                // Comments of statements without instructions go with the next one that has some.
+                while let Some((_, comment)) = comments.next_if(|(statement, _)| *statement <= origin) {
+                    writeln!(f, "; {comment}")?;
+                }
+                writeln!(f, "; {}", self.rblock.named_statement(origin))?;
+                last = Some(origin);
             }
+            writeln!(f, "{}", instr.display(self.code.evm_version))?;
         }
+        std::fmt::Result::Ok(())
+    }
+}
 
-        for &r in ress.iter().rev() {
-            if occurs[r.index()] == 0 {
-                machine.rotate_to(r, 0);
-                machine.pop();
+/// Lowers settled pre-instructions to EVM instructions, assigning registers to spilled values.
+struct Lowering {
+    code: Vec<Instruction>,
+    origins: Vec<usize>,
+    stack: Vec<Option<usize>>,
+    vars: Vec<Var>,
+    register_count: usize,
+    free_registers: Vec<usize>,
+    stable_registers: Option<HashMap<Var, usize>>,
+    /// Registers of the variables moved out to memory by [`PreInstruction::Store`].
+    stored: HashMap<Var, usize>,
+    spill_base: U256,
+    base_on_stack: bool,
+    reach: usize,
+    evm_version: EvmVersion,
+    fuel: Fuel,
+    lowered: usize,
+    defs: VecDeque<Var>,
+    checker: Option<Checker>,
+    pre: Option<Vec<String>>,
+    spills: Vec<SpillEvent>,
+}
+
+impl Lowering {
+    fn new(options: &Options) -> Lowering {
+        Lowering {
+            code: Vec::new(),
+            origins: Vec::new(),
+            stack: Vec::new(),
+            vars: Vec::new(),
+            register_count: 0,
+            free_registers: Vec::new(),
+            stable_registers: options.stable_registers.then(HashMap::new),
+            stored: HashMap::new(),
+            spill_base: options.spill_base,
+            base_on_stack: options.base_on_stack,
+            reach: options.reach(),
+            evm_version: options.evm_version,
+            fuel: Fuel(options.fuel),
+            lowered: 0,
+            defs: VecDeque::new(),
+            checker: options.verify.then(|| Checker::new(options.spill_base, options.reserved_slots)),
+            pre: options.record_pre.then(Vec::new),
+            spills: Vec::new(),
+        }
+    }
+
+    fn lower_until(&mut self, until: usize, pending: &mut VecDeque<(PreInstruction, usize)>, spiller: &mut Spiller) -> Result<(), CodegenError> {
+        let spills = spiller.take_settled(until);
+        let mut spills = &spills[..];
+
+        while self.lowered < until {
+            let (instr, origin) = pending.pop_front().ok_or(CodegenError::Internal("missing pre-instruction"))?;
+            let n = spills.iter().take_while(|s| s.location.code_index == self.lowered).count();
+            if let Some(pre) = &mut self.pre {
+                let mut line = instr.to_string();
+                for spill in &spills[..n] {
+                    let action = if spill.outward { "spill" } else { "restore" };
+                    line += &format!(" ; {action} {}", spill.location.depth);
+                }
+                pre.push(line);
             }
+            self.lower(instr, origin, &spills[..n])?;
+            spills = &spills[n..];
+            self.lowered += 1;
         }
+
+        Ok(())
     }
 
-    let spills = make_spills(&machine);
+    fn allocate_register(&mut self, var: Var) -> usize {
+        let register_count = &mut self.register_count;
+        let mut fresh = || {
+            let register = *register_count;
+            *register_count += 1;
+            register
+        };
+        let register = match &mut self.stable_registers {
+            Some(registers) => *registers.entry(var).or_insert_with(fresh),
+            None if !self.free_registers.is_empty() && self.fuel.consume("reuse register") => self.free_registers.pop().unwrap(),
+            None => fresh(),
+        };
+        debug!(%var, register, register_count = self.register_count, "allocate register");
+        register
+    }
 
-    #[derive(Clone, Copy, PartialEq, Eq)]
-    enum StackItem {
-        Stack { value: usize },
-        Register { register: usize }
+    fn free_register(&mut self, register: usize) {
+        if self.stable_registers.is_none() {
+            debug!(register, "free register");
+            self.free_registers.push(register);
+        }
     }
 
-    let mut code = Vec::with_capacity(machine.code.capacity());
-    let mut stack: Vec<Option<usize>> = Vec::with_capacity(machine.stack.capacity());
-    let mut register_count = 0;
-    let mut free_registers = Vec::new();
+    /// Pushes the address of `register`, with `transient` values on top of the modelled stack.
+    fn push_register_address(&mut self, register: usize, transient: usize) -> Result<(), CodegenError> {
+        use Instruction::*;
+        use StackInstruction::*;
 
-    let mut spills_end = 0;
+        if !self.base_on_stack {
+            self.code.push(Stack(Push(register_address(self.spill_base, register).into())));
+            return Ok(());
+        }
 
-    for (code_index, instr) in machine.code.into_iter().enumerate() {
-        let spills_start = spills[spills_end..].iter()
-            .position(|s| s.location.code_index >= code_index)
-            .map_or(spills.len(), |i| i + spills_end);
+        let depth = self.vars.iter().rev().position(|&v| v == Var::RESERVED)
+            .ok_or(CodegenError::Internal("spill base not on stack"))? + transient;
+        if !within_reach(depth, MAX_REACH) {
+            return Err(CodegenError::Internal("spill base out of reach"));
+        }
+        self.code.push(Stack(Dup(depth)));
 
-        spills_end = spills[spills_start..].iter()
-            .position(|s| s.location.code_index > code_index)
-            .map_or(spills.len(), |i| i + spills_start);
+        let offset = register_address(U256::ZERO, register);
+        if !offset.is_zero() {
+            self.code.push(Stack(Push(offset.into())));
+            self.code.push(Data(DataInstruction::Add));
+        }
+        Ok(())
+    }
+
+    fn register_load(&mut self, register: usize) -> Result<(), CodegenError> {
+        self.push_register_address(register, 0)?;
+        self.code.push(Instruction::Data(DataInstruction::Mload));
+        Ok(())
+    }
 
-        let instr_spills = &spills[spills_start..spills_end];
+    /// Stores the value on top of the modelled stack into `register`.
+    fn register_store(&mut self, register: usize) -> Result<(), CodegenError> {
+        self.push_register_address(register, 1)?;
+        self.code.push(Instruction::Data(DataInstruction::Mstore));
+        Ok(())
+    }
 
-        match instr {
-            PreInstruction::Stack(PreStackInstruction::Rotate { from_depth, to_depth }) if from_depth != to_depth => {
-                let top_index = stack.len() - 1;
+    fn lower(&mut self, instr: PreInstruction, origin: usize, spills: &[Spill]) -> Result<(), CodegenError> {
+        let code_start = self.code.len();
+
+        let ndefs = match instr {
+            PreInstruction::Stack(PreStackInstruction::Push(_)) | PreInstruction::Load(_) => 1,
+            PreInstruction::Data(ref op) => arity(op).1,
+            PreInstruction::Input(n) => n,
+            _ => 0,
+        };
+        let results: Vec<Var> = self.defs.drain(..ndefs).collect();
+
+        match instr.clone() {
+            PreInstruction::Stack(PreStackInstruction::Rotate { from_depth, to_depth }) if from_depth != to_depth || !within_reach(from_depth, self.reach) => {
+                let top_index = self.stack.len() - 1;
                 let from_index = top_index - from_depth;
                 let to_index = top_index - to_depth;
 
-                if from_depth < 16 {
+                if within_reach(from_depth, self.reach) {
                     if from_depth > 0 {
-                        code.push(Instruction::Stack(StackInstruction::Swap(from_depth)));
-                        stack.swap(from_index, top_index);
+                        self.code.push(Instruction::Stack(StackInstruction::Swap(from_depth)));
+                        self.stack.swap(from_index, top_index);
                     }
                 } else {
-                    let from_register = stack[from_index].unwrap();
-                    code.extend(register_load(from_register));
-                    code.push(Instruction::Stack(StackInstruction::Swap(1)));
-                    if from_depth != 0 {
-                        if let Some(top_register) = stack[top_index].take() {
-                            free_registers.push(top_register);
-                            code.extend(register_load(top_register));
-                            code.push(Instruction::Stack(StackInstruction::Swap(1)));
-                            code.extend(register_store(top_register));
+                    let from_register = self.stack[from_index].ok_or(CodegenError::Internal("deep value not in register"))?;
+                    self.register_load(from_register)?;
+                    self.code.push(Instruction::Stack(StackInstruction::Swap(1)));
+                    let top_register = match self.stack[top_index].take() {
+                        Some(top_register) => {
+                            // The top value is already in memory, so its register moves along with it.
+                            self.code.push(Instruction::Data(DataInstruction::Pop));
+                            self.free_register(from_register);
+                            top_register
                         }
-                    }
-                    code.extend(register_store(from_register));
+                        None => {
+                            let top_register = match self.stable_registers {
+                                Some(_) => self.allocate_register(self.vars[top_index]),
+                                None => from_register,
+                            };
+                            self.register_store(top_register)?;
+                            top_register
+                        }
+                    };
+                    self.stack[from_index] = Some(top_register);
                 }
+                self.vars.swap(from_index, top_index);
 
                 if to_depth > 0 {
-                    code.push(Instruction::Stack(StackInstruction::Swap(to_depth)));
-                    stack.swap(top_index, to_index);
+                    self.code.push(Instruction::Stack(StackInstruction::Swap(to_depth)));
+                    self.stack.swap(top_index, to_index);
+                    self.vars.swap(top_index, to_index);
                 }
                 // todo: more efficient spilling
             }
 
-            PreInstruction::Stack(PreStackInstruction::Rotate { from_depth, to_depth }) => {
-                assert_eq!(from_depth, to_depth);
-            }
+            PreInstruction::Stack(PreStackInstruction::Rotate { .. }) => (),
 
             PreInstruction::Stack(PreStackInstruction::Dup(depth)) => {
-                let index = stack.len() - 1 - depth;
-                if let Some(register) = stack[index] {
-                    code.extend(register_load(register));
+                let index = self.stack.len() - 1 - depth;
+                if let Some(register) = self.stack[index] {
+                    self.register_load(register)?;
                 } else {
-                    assert!(depth < 16);
-                    code.push(Instruction::Stack(StackInstruction::Dup(depth)));
+                    if !within_reach(depth, self.reach) {
+                        return Err(CodegenError::DupTooDeep { var: Some(self.vars[index]), depth, statement: Some(origin), span: None });
+                    }
+                    self.code.push(Instruction::Stack(StackInstruction::Dup(depth)));
                 }
-                stack.push(None);
+                self.stack.push(None);
+                self.vars.push(self.vars[index]);
             }
 
             PreInstruction::Stack(PreStackInstruction::Push(c)) => {
-                code.push(Instruction::Stack(StackInstruction::Push(c)));
-                stack.push(None);
+                self.code.push(Instruction::Stack(StackInstruction::Push(c)));
+                self.stack.push(None);
+                self.vars.extend(&results);
+            }
+
+            PreInstruction::Input(n) => {
+                self.stack.extend(repeat_n(None, n));
+                self.vars.extend(&results);
+            }
+
+            PreInstruction::Store => {
+                if self.stack.pop().ok_or(CodegenError::Internal("store from empty stack"))?.is_some() {
+                    return Err(CodegenError::Internal("stored value still in register"));
+                }
+                let var = self.vars.pop().unwrap();
+                let register = self.allocate_register(var);
+                self.stored.insert(var, register);
+                let start = self.code.len();
+                self.register_store(register)?;
+                let gas = self.code[start..].iter().map(|i| i.gas_cost(self.evm_version)).sum();
+                self.spills.push(SpillEvent { statement: origin, var, register, restore: false, gas });
+            }
+
+            PreInstruction::Load(last) => {
+                let var = results[0];
+                let register = *self.stored.get(&var).ok_or(CodegenError::Internal("loaded value not stored"))?;
+                if last {
+                    self.stored.remove(&var);
+                    self.free_register(register);
+                }
+                let start = self.code.len();
+                self.register_load(register)?;
+                let gas = self.code[start..].iter().map(|i| i.gas_cost(self.evm_version)).sum();
+                self.spills.push(SpillEvent { statement: origin, var, register, restore: true, gas });
+                self.stack.push(None);
+                self.vars.push(var);
             }
 
             PreInstruction::Data(op) => {
-                code.push(Instruction::Data(op));
-                let (nargs, nress) = op.arity();
-                for item in stack.drain(stack.len() - nargs..) {
-                    assert!(item.is_none());
+                let (nargs, nress) = arity(&op);
+                let discard = matches!(op, Instruction::Data(DataInstruction::Pop));
+                self.code.push(op);
+                let registers: Vec<_> = self.stack.drain(self.stack.len() - nargs..).flatten().collect();
+                for register in registers {
+                    if !discard {
+                        return Err(CodegenError::Internal("operand still in register"));
+                    }
+                    self.free_register(register);
                 }
-                stack.extend(repeat(None).take(nress));
+                self.stack.extend(repeat_n(None, nress));
+                self.vars.truncate(self.vars.len() - nargs);
+                self.vars.extend(&results);
             }
         }
 
-        for &Spill { location, outward } in instr_spills {
-            let index = stack.len() - 1 - location.depth;
+        for &Spill { location, outward } in spills {
+            let index = self.stack.len() - 1 - location.depth;
 
             let register =
                 if outward {
-                    assert!(stack[index].is_none());
-                    let register = free_registers.pop().unwrap_or_else(|| {
-                        let register = register_count;
-                        register_count += 1;
-                        register
-                    });
-                    stack[index] = Some(register);
+                    if self.stack[index].is_some() {
+                        return Err(CodegenError::Internal("value spilled twice"));
+                    }
+                    let register = self.allocate_register(self.vars[index]);
+                    self.stack[index] = Some(register);
                     register
                 } else {
-                    let register = stack[index].take().unwrap();
-                    free_registers.push(register);
+                    let register = self.stack[index].take().ok_or(CodegenError::Internal("restored value not in register"))?;
+                    self.free_register(register);
                     register
                 };
+            debug!(statement = origin, var = %self.vars[index], register, depth = location.depth, "{}", if outward { "spill" } else { "restore" });
+            let start = self.code.len();
+            self.register_load(register)?;
+            self.code.push(Instruction::Stack(StackInstruction::Swap(location.depth + 1)));
+            self.register_store(register)?;
+            let gas = self.code[start..].iter().map(|i| i.gas_cost(self.evm_version)).sum();
+            self.spills.push(SpillEvent { statement: origin, var: self.vars[index], register, restore: !outward, gas });
+        }
+
+        if let Some(checker) = &mut self.checker {
+            checker.step(&instr, &results, &self.code[code_start..], &self.stack)
+                .map_err(|e| CodegenError::Verification(e.to_string()))?;
+        }
+
+        self.origins.resize(self.code.len(), origin);
+
+        Ok(())
+    }
+}
+
+/// Incremental interface to the stack scheduler, for compilers that want to drive it directly.
+///
+/// Operations are described one at a time in terms of variables. The scheduler tracks where each
+/// variable lives on the stack, and spills values to memory registers when they fall out of reach
+/// of `dup16`/`swap16`.
+pub struct Scheduler {
+    options: Options,
+    machine: Machine,
+    spiller: Spiller,
+    lowering: Lowering,
+    pending: VecDeque<(PreInstruction, usize)>,
+    next_index: usize,
+}
+
+impl Scheduler {
+    pub fn new(options: &Options) -> Scheduler {
+        Scheduler::with_inputs(options, &[])
+    }
+
+    /// A scheduler for code that starts with the variables `inputs` on the stack, the first on top.
+    pub fn with_inputs(options: &Options, inputs: &[Var]) -> Scheduler {
+        let mut scheduler = Scheduler {
+            options: options.clone(),
+            machine: Machine::new(options.reach()),
+            spiller: Spiller::new(options.reach()),
+            lowering: Lowering::new(options),
+            pending: VecDeque::new(),
+            next_index: 0,
+        };
+        if !inputs.is_empty() {
+            scheduler.machine.input(&inputs.iter().rev().copied().collect::<Vec<_>>());
+        }
+        if options.base_on_stack {
+            scheduler.machine.push(Var::RESERVED, options.spill_base);
+        }
+        scheduler
+    }
+
+    /// Sets the origin recorded in `Code::origins` for the instructions generated from now on,
+    /// typically the index of the statement being compiled.
+    pub fn set_origin(&mut self, origin: usize) -> Result<(), CodegenError> {
+        self.flush()?;
+        self.machine.statement = origin;
+        Ok(())
+    }
+
+    /// Returns the current depth of `var`, or `None` if it is not on the stack.
+    pub fn depth(&self, var: Var) -> Option<usize> {
+        self.machine.find(var).ok()
+    }
+
+    /// In `base_on_stack` mode, moves the spill base so that it stays within reach while scheduling
+    /// an operation with `nargs` arguments and `nress` results, and out of the way of the arguments.
+    /// Has no effect in other modes.
+    pub fn keep_base_reachable(&mut self, nargs: usize, nress: usize) -> Result<(), CodegenError> {
+        if !self.options.base_on_stack {
+            return Ok(());
+        }
+        let depth = self.machine.find(Var::RESERVED)?;
+        let target = nargs.min(self.machine.stack.len() - 1);
+        if depth < target || depth + nargs + nress + 2 > self.machine.reach {
+            self.machine.rotate_to(Var::RESERVED, target)?;
+        }
+        Ok(())
+    }
+
+    /// Pushes the constant `value` as a new variable `var`.
+    pub fn push(&mut self, var: Var, value: U256) {
+        self.machine.push(var, value);
+    }
+
+    /// Pushes the immutable `name`, patched in after assembly, as a new variable `var`.
+    pub fn immutable(&mut self, var: Var, name: String) -> Result<(), CodegenError> {
+        self.machine.apply(Instruction::Immutable(name.into()), &[var])
+    }
+
+    /// Moves `var` to `to_depth`. The value previously on top of the stack takes its place.
+    pub fn rotate_to(&mut self, var: Var, to_depth: usize) -> Result<(), CodegenError> {
+        self.machine.rotate_to(var, to_depth)
+    }
+
+    /// Places a copy of `var` at `to_depth`, leaving the original in place.
+    pub fn copy_to(&mut self, var: Var, to_depth: usize) -> Result<(), CodegenError> {
+        self.machine.copy_to(var, to_depth)
+    }
 
-            code.extend(register_load(register));
-            code.push(Instruction::Stack(StackInstruction::Swap(location.depth + 1)));
-            code.extend(register_store(register));
+    /// Applies `op` to the values on top of the stack, replacing them with the variables `results`.
+    pub fn apply(&mut self, op: DataInstruction, results: &[Var]) -> Result<(), CodegenError> {
+        if !self.options.evm_version.supports(op) {
+            return Err(CodegenError::Unsupported { op: Instruction::Data(op).to_string(), evm_version: self.options.evm_version });
+        }
+        let (_, nress) = op.arity();
+        if results.len() != nress {
+            return Err(CodegenError::WrongResultCount { expected: nress, found: results.len() });
+        }
+        self.machine.apply(Instruction::Data(op), results)
+    }
+
+    /// Splices pre-assembled `bytes` into the code as an operation that takes `takes` values from
+    /// the top of the stack and replaces them with the variables `results`.
+    pub fn verbatim(&mut self, bytes: Vec<u8>, takes: u16, results: &[Var]) -> Result<(), CodegenError> {
+        let returns = results.len().try_into().map_err(|_| CodegenError::Internal("too many results"))?;
+        self.machine.apply(Instruction::Verbatim(bytes.into(), takes, returns), results)
+    }
+
+    /// Discards the value on top of the stack.
+    pub fn pop(&mut self) -> Result<(), CodegenError> {
+        self.machine.pop()
+    }
+
+    /// Moves `var`, which must be on top of the stack, into a register of its own until it is
+    /// loaded for the last time.
+    pub fn store(&mut self, var: Var) -> Result<(), CodegenError> {
+        self.machine.store(var)
+    }
+
+    /// Pushes the value of `var` from the register it was stored to, freeing the register if `last`.
+    pub fn load(&mut self, var: Var, last: bool) {
+        self.machine.load(var, last);
+    }
+
+    /// Lowers the operations whose spills are already decided.
+    fn flush(&mut self) -> Result<(), CodegenError> {
+        for (instr, origin) in self.machine.code.drain(..).zip(self.machine.origins.drain(..)) {
+            self.spiller.step(self.next_index, &instr).map_err(|e| e.at(origin))?;
+            self.pending.push_back((instr, origin));
+            self.next_index += 1;
+        }
+        self.lowering.defs.extend(self.machine.defs.drain(..));
+
+        let until = self.spiller.settled(self.next_index);
+        self.lowering.lower_until(until, &mut self.pending, &mut self.spiller)
+    }
+
+    /// Lowers all remaining operations and returns the generated code.
+    pub fn finish(mut self) -> Result<Code, CodegenError> {
+        self.flush()?;
+        self.spiller.restore_remaining()?;
+        self.lowering.lower_until(self.next_index, &mut self.pending, &mut self.spiller)?;
+        if self.options.minimize_swaps {
+            minimize_swaps(&mut self.lowering.code, &mut self.lowering.origins, &mut self.lowering.fuel);
+        }
+        let lowering = self.lowering;
+        let mut fuel = lowering.fuel;
+        let code = layout(lowering.code, lowering.origins, lowering.register_count, &self.options, &mut fuel)?;
+        Ok(Code { pre: lowering.pre.unwrap_or_default(), spills: lowering.spills, ..code })
+    }
+}
+
+/// Places the memory the generated `instructions` use past their `register_count` spill
+/// registers: the constant registers and the pool when enabled, then the heap and the relocated
+/// program memory. Checks that the stack stays within its limit.
+pub(crate) fn layout(mut instructions: Vec<Instruction>, mut origins: Vec<usize>, mut register_count: usize, options: &Options, fuel: &mut Fuel) -> Result<Code, CodegenError> {
+    let base_on_stack = options.base_on_stack;
+    let evm_version = options.evm_version;
+    if let Some(threshold) = options.constant_registers.filter(|_| !base_on_stack) {
+        let first = register_address(options.spill_base, register_count);
+        register_count += register_constants(&mut instructions, &mut origins, first, threshold, evm_version, fuel);
+    }
+    let constant_pool = options.constant_pool.filter(|_| !base_on_stack);
+    let reserved = register_count + usize::from(constant_pool.is_some());
+    let end = register_address(options.spill_base, reserved);
+    let memory_offset = (options.relocate_memory && !base_on_stack).then_some(end);
+    let heap_start = memory_offset.map_or(end.max(U256::from(0x80)), |offset| offset + U256::from(0x80));
+    for instr in &mut instructions {
+        let Instruction::Immutable(name) = instr else { continue };
+        if **name == *HEAP_START {
+            if base_on_stack {
+                return Err(CodegenError::RelocatableHeap);
+            }
+            *instr = Instruction::Stack(StackInstruction::Push(heap_start.into()));
+        } else if let (Some(offset), Some(c)) = (memory_offset, name.strip_prefix(RELOCATED)) {
+            let c: U256 = c.parse().map_err(|_| CodegenError::Internal("bad relocated offset"))?;
+            *instr = Instruction::Stack(StackInstruction::Push(offset.wrapping_add(c).into()));
+        }
+    }
+    if let Some(threshold) = constant_pool {
+        let scratch = register_address(options.spill_base, register_count);
+        if pool_constants(&mut instructions, &mut origins, scratch, threshold, evm_version, fuel) {
+            register_count += 1;
+        }
+    }
+    let (_, height) = crate::eof::stack_bounds(&instructions);
+    if options.reserved_slots + height > 1024 {
+        return Err(CodegenError::StackOverflow { height, reserved: options.reserved_slots });
+    }
+    Ok(Code {
+        instructions,
+        origins,
+        register_count,
+        spill_base: options.spill_base,
+        base_on_stack,
+        reserved_slots: options.reserved_slots,
+        memory_offset,
+        heap_start,
+        evm_version,
+        pre: Vec::new(),
+        spills: Vec::new(),
+    })
+}
+
+/// Moves or copies `args` into place on top of the stack, the first at depth `above`, copying those
+/// that `occurs` says are used again. Arguments that would land out of reach can't be swapped into
+/// position, so then every argument is copied on top instead, deepest first, and this returns true.
+/// Copies of values out of reach are loaded from the register they were spilled to, and the caller
+/// pops the originals of the last uses once the arguments are consumed.
+fn place_arguments(scheduler: &mut Scheduler, occurs: &mut [usize], args: &[Var], above: usize, statement: usize) -> Result<bool, CodegenError> {
+    let mut ndups = 0;
+    let dups: Vec<_> = args.iter()
+        .map(|&a| {
+            occurs[a.index()] -= 1;
+            let dup = occurs[a.index()] > 0;
+            if dup { ndups += 1; }
+            dup
+        })
+        .collect();
+
+    let reach = scheduler.machine.reach;
+    let wide = (0..args.len()).rev().any(|i| !within_reach(above + i - dups[..i].iter().filter(|&&d| d).count(), reach));
+    if wide {
+        debug!(statement, nargs = args.len(), "arguments too deep to swap into place, copying them on top");
+        for &arg in args.iter().rev() {
+            scheduler.copy_to(arg, 0)?;
+        }
+    } else {
+        for (i, (&arg, dup)) in args.iter().zip(dups).enumerate().rev() {
+            if dup { ndups -= 1; }
+            let to_depth = above + i - ndups;
+            if dup {
+                scheduler.copy_to(arg, to_depth)?;
+            } else {
+                scheduler.rotate_to(arg, to_depth)?;
+            }
+        }
+    }
+    Ok(wide)
+}
+
+/// Moves `var` out to memory, leaving its uses to count as loads.
+fn store_result(scheduler: &mut Scheduler, occurs: &mut [usize], loads: &mut [usize], var: Var) -> Result<(), CodegenError> {
+    scheduler.keep_base_reachable(1, 0)?;
+    scheduler.rotate_to(var, 0)?;
+    scheduler.store(var)?;
+    loads[var.index()] = occurs[var.index()];
+    occurs[var.index()] = 0;
+    Ok(())
+}
+
+/// Loads the variables among `args` that are stored in memory, once each, so that their uses in
+/// `args` are the only ones left on the stack.
+fn load_arguments(scheduler: &mut Scheduler, occurs: &mut [usize], loads: &mut [usize], args: &[Var]) {
+    for (i, &arg) in args.iter().enumerate() {
+        if loads[arg.index()] > 0 && !args[..i].contains(&arg) {
+            let n = args.iter().filter(|&&a| a == arg).count();
+            loads[arg.index()] -= n;
+            scheduler.load(arg, loads[arg.index()] == 0);
+            occurs[arg.index()] = n;
+        }
+    }
+}
+
+pub fn generate(rblock: &ResolvedBlock, options: &Options) -> Result<Code, CodegenError> {
+    // A program that uses the heap keeps the first 0x80 bytes as in Solidity, with the free memory
+    // pointer of `alloc` at 0x40, so its registers start past them. Relocated memory is already
+    // past the registers.
+    let heap = rblock.block.0.iter().any(|Statement(_, e)| matches!(e, Expression::Immutable(name) if *name == HEAP_START));
+    let reserved = U256::from(0x80);
+    let moved;
+    let options = if heap && !options.relocate_memory && !options.base_on_stack && options.spill_base < reserved {
+        moved = Options { spill_base: reserved, ..options.clone() };
+        &moved
+    } else {
+        options
+    };
+    let code = if options.treeify { crate::treeify::generate(rblock, options) } else { retry_block(rblock, options) };
+    code.and_then(|code| check_collisions(rblock, options, code)).map_err(|e| e.with_span(&rblock.block.1))
+}
+
+/// Fails if the program stores to a constant address among the registers of `code`.
+fn check_collisions(rblock: &ResolvedBlock, options: &Options, code: Code) -> Result<Code, CodegenError> {
+    let offsets = constant_offsets(rblock);
+    let spill_end = register_address(options.spill_base, code.register_count);
+    let overlaps = |range: &Range<U256>| range.start < spill_end && range.end > options.spill_base;
+    let relocated = |statement: usize| {
+        code.memory_offset.is_some() && matches!(&rblock.block.0[statement].1, Expression::Op(_, args) if offsets[args[0].index()])
+    };
+    if let Some((statement, range)) = constant_stores(rblock).into_iter().find(|(statement, range)| !relocated(*statement) && overlaps(range)) {
+        return Err(CodegenError::SpillCollision { statement, address: range.start, span: None });
+    }
+    Ok(code)
+}
+
+/// Schedules `rblock`, and on a depth error in some statement schedules it again with the
+/// variable live across that statement whose next use is furthest away kept in memory.
+fn retry_block(rblock: &ResolvedBlock, options: &Options) -> Result<Code, CodegenError> {
+    use CodegenError::*;
+    let mut stored = vec![false; rblock.var_count];
+    let mut retry = 0;
+    loop {
+        let error = match generate_block(rblock, options, &stored) {
+            Err(e @ (SwapTooDeep { .. } | DupTooDeep { .. } | UnspillableTooDeep { .. })) if retry < options.retries => e,
+            result => return result,
+        };
+        let (SwapTooDeep { statement: Some(statement), .. } | DupTooDeep { statement: Some(statement), .. } | UnspillableTooDeep { statement: Some(statement), .. }) = error else {
+            return Err(error);
+        };
+        let Some(var) = store_candidate(rblock, options, &stored, statement) else {
+            return Err(error);
+        };
+        debug!(retry, statement, %var, %error, "schedule again with a variable kept in memory");
+        stored[var.index()] = true;
+        retry += 1;
+    }
+}
+
+/// Among the variables defined before `statement` and used after it but not in it, the one not
+/// yet `stored` whose next use is furthest away. Inputs are stored from the top down, so an input
+/// must be within reach once the ones above it that are stored are gone.
+fn store_candidate(rblock: &ResolvedBlock, options: &Options, stored: &[bool], statement: usize) -> Option<Var> {
+    let reach = options.reach() - usize::from(options.base_on_stack);
+    let inputs = rblock.inputs();
+    let mut defined = vec![false; rblock.var_count];
+    for (ress, _) in rblock.flat.iter().take(statement) {
+        for &r in ress {
+            defined[r.index()] = true;
+        }
+    }
+    for (position, &input) in inputs.iter().enumerate() {
+        let above = inputs[..position].iter().filter(|v| stored[v.index()]).count();
+        defined[input.index()] &= position - above < reach;
+    }
+    let mut next_use = vec![None; rblock.var_count];
+    for (i, (_, args)) in rblock.flat.iter().enumerate().skip(statement) {
+        for &a in args {
+            next_use[a.index()].get_or_insert(i);
+        }
+    }
+    (0..rblock.var_count)
+        .filter(|&v| defined[v] && !stored[v] && next_use[v].is_some_and(|i| i > statement))
+        .max_by_key(|&v| (next_use[v], std::cmp::Reverse(v)))
+        .map(Var::new)
+}
+
+fn generate_block(rblock: &ResolvedBlock, options: &Options, stored: &[bool]) -> Result<Code, CodegenError> {
+    let mut occurs = count_occurrences(rblock);
+    // Loads left of each stored variable, once it is in memory.
+    let mut loads = vec![0; rblock.var_count];
+    let mut scheduler = Scheduler::with_inputs(options, rblock.inputs());
+    let offsets = constant_offsets(rblock);
+
+    for (statement, Statement(ress, e)) in rblock.block.0.iter().enumerate() {
+        scheduler.set_origin(statement)?;
+
+        match *e {
+            Expression::Const(c) => {
+                if ress.len() != 1 {
+                    return Err(CodegenError::WrongResultCount { expected: 1, found: ress.len() });
+                }
+                let name = ress[0];
+                scheduler.keep_base_reachable(0, 1)?;
+                if options.relocate_memory && !options.base_on_stack && offsets[name.index()] {
+                    scheduler.immutable(name, format!("{RELOCATED}{c}"))?;
+                } else {
+                    scheduler.push(name, c);
+                }
+            }
+
+            // The outputs are left in place of the arguments of an operation, with the spill base
+            // above them to pop once they are there.
+            Expression::Output(ref args) if statement + 1 == rblock.block.0.len() => {
+                scheduler.keep_base_reachable(args.len(), 0)?;
+                load_arguments(&mut scheduler, &mut occurs, &mut loads, args);
+                let above = usize::from(options.base_on_stack);
+                if place_arguments(&mut scheduler, &mut occurs, args, above, statement)? {
+                    return Err(CodegenError::SwapTooDeep { var: None, depth: above + args.len() - 1, statement: Some(statement), span: None });
+                }
+                if options.base_on_stack {
+                    scheduler.rotate_to(Var::RESERVED, 0)?;
+                    scheduler.pop()?;
+                }
+            }
+            Expression::Output(_) => return Err(CodegenError::Internal("outputs returned before the last statement")),
+
+            Expression::Input if statement == 0 => {
+                for &r in ress {
+                    if stored[r.index()] && occurs[r.index()] > 0 {
+                        store_result(&mut scheduler, &mut occurs, &mut loads, r)?;
+                    }
+                }
+            }
+            Expression::Input => return Err(CodegenError::Internal("inputs taken after the first statement")),
+
+            Expression::Immutable(ref immutable) => {
+                if ress.len() != 1 {
+                    return Err(CodegenError::WrongResultCount { expected: 1, found: ress.len() });
+                }
+                scheduler.keep_base_reachable(0, 1)?;
+                scheduler.immutable(ress[0], immutable.clone())?;
+            }
+
+            Expression::Op(ref op_name, ref args) | Expression::Verbatim(ref op_name, _, ref args) => {
+                let op = match e {
+                    Expression::Op(..) => Some(op_name.parse::<DataInstruction>().map_err(|_| CodegenError::UnknownOperator(op_name.clone()))?),
+                    _ => None,
+                };
+                let (nargs, nres) = op.map_or((args.len(), ress.len()), |op| op.arity());
+
+                if args.len() != nargs {
+                    return Err(CodegenError::WrongArgumentCount { op: op_name.clone(), expected: nargs, found: args.len() });
+                }
+                if ress.len() != nres {
+                    return Err(CodegenError::WrongResultCount { expected: nres, found: ress.len() });
+                }
+
+                scheduler.keep_base_reachable(nargs, nres)?;
+                load_arguments(&mut scheduler, &mut occurs, &mut loads, args);
+
+                let wide = place_arguments(&mut scheduler, &mut occurs, args, 0, statement)?;
+
+                match (op, e) {
+                    (Some(op), _) => scheduler.apply(op, ress)?,
+                    (None, Expression::Verbatim(_, bytes, _)) => {
+                        let takes = nargs.try_into().map_err(|_| CodegenError::Internal("too many arguments"))?;
+                        scheduler.verbatim(bytes.clone(), takes, ress)?;
+                    }
+                    _ => unreachable!(),
+                }
+
+                if wide {
+                    // The shallowest first, since each pop brings the ones below closer.
+                    let mut dead: Vec<_> = args.iter().copied().filter(|a| occurs[a.index()] == 0).collect();
+                    dead.sort_by_key(|a| a.index());
+                    dead.dedup();
+                    while let Some(i) = (0..dead.len()).min_by_key(|&i| scheduler.depth(dead[i])) {
+                        scheduler.rotate_to(dead.swap_remove(i), 0)?;
+                        scheduler.pop()?;
+                    }
+                }
+            }
+        }
+
+        for &r in ress.iter().rev() {
+            if loads[r.index()] > 0 {
+                continue;
+            }
+            if occurs[r.index()] == 0 {
+                scheduler.rotate_to(r, 0)?;
+                scheduler.pop()?;
+            } else if stored[r.index()] {
+                store_result(&mut scheduler, &mut occurs, &mut loads, r)?;
+            }
         }
     }
 
-    Ok(code.into_iter())
+    scheduler.finish()
 }
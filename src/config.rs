@@ -0,0 +1,104 @@
+use std::path::{Path, PathBuf};
+
+use alloy_primitives::U256;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, ValueEnum};
+use eyre::{eyre, Result, WrapErr};
+use serde::Deserialize;
+
+use crate::{CodegenArgs, SpillMode};
+
+pub const FILE_NAME: &str = "evm-spilling.toml";
+
+/// Project defaults for the code generation options. Options given on the command line take
+/// precedence.
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Config {
+    spill_base: Option<toml::Value>,
+    spill_mode: Option<String>,
+    base_on_stack: Option<bool>,
+    reserved_slots: Option<usize>,
+    spill_depth: Option<u8>,
+    treeify: Option<bool>,
+    relocate_memory: Option<bool>,
+    retries: Option<usize>,
+    seed: Option<u64>,
+    evm_version: Option<String>,
+    minimize_swaps: Option<bool>,
+    constant_pool: Option<usize>,
+    constant_registers: Option<usize>,
+    verify: Option<bool>,
+}
+
+/// Looks for `evm-spilling.toml` in the current directory and its ancestors.
+fn find() -> Option<PathBuf> {
+    let cwd = std::env::current_dir().ok()?;
+    cwd.ancestors().map(|dir| dir.join(FILE_NAME)).find(|path| path.is_file())
+}
+
+/// Reads the config at `path`, or the one found by searching upwards from the current directory.
+pub fn load(path: Option<&Path>) -> Result<Option<Config>> {
+    let Some(path) = path.map(Path::to_path_buf).or_else(find) else { return Ok(None) };
+    let text = std::fs::read_to_string(&path).wrap_err_with(|| format!("reading {}", path.display()))?;
+    let config = toml::from_str(&text).wrap_err_with(|| format!("in {}", path.display()))?;
+    Ok(Some(config))
+}
+
+impl Config {
+    /// Sets the options in `args` that `matches` shows weren't given on the command line.
+    pub fn apply(&self, args: &mut CodegenArgs, matches: &ArgMatches) -> Result<()> {
+        let unset = |id: &str| matches.value_source(id) != Some(ValueSource::CommandLine);
+
+        if let (Some(value), true) = (&self.spill_base, unset("spill_base")) {
+            args.spill_base = match value {
+                toml::Value::Integer(n) => U256::try_from(*n).map_err(|_| eyre!("bad spill-base: {n}"))?,
+                toml::Value::String(s) => s.parse().map_err(|_| eyre!("bad spill-base: {s}"))?,
+                _ => return Err(eyre!("bad spill-base: {value}")),
+            };
+        }
+        if let (Some(mode), true) = (&self.spill_mode, unset("spill_mode")) {
+            args.spill_mode = SpillMode::from_str(mode, true).map_err(|e| eyre!("bad spill-mode: {e}"))?;
+        }
+        if let (Some(version), true) = (&self.evm_version, unset("evm_version")) {
+            args.evm_version = version.parse()?;
+        }
+        if let (Some(threshold), true) = (self.constant_pool, unset("constant_pool")) {
+            args.constant_pool = Some(threshold);
+        }
+        if let (Some(threshold), true) = (self.constant_registers, unset("constant_registers")) {
+            args.constant_registers = Some(threshold);
+        }
+        if let (Some(minimize), true) = (self.minimize_swaps, unset("no_minimize_swaps")) {
+            args.no_minimize_swaps = !minimize;
+        }
+        if let (Some(on_stack), true) = (self.base_on_stack, unset("base_on_stack")) {
+            args.base_on_stack = on_stack;
+        }
+        if let (Some(slots), true) = (self.reserved_slots, unset("reserved_slots")) {
+            args.reserved_slots = slots;
+        }
+        if let (Some(depth), true) = (self.spill_depth, unset("spill_depth")) {
+            if !(1..=16).contains(&depth) {
+                return Err(eyre!("bad spill-depth: {depth}"));
+            }
+            args.spill_depth = depth;
+        }
+        if let (Some(treeify), true) = (self.treeify, unset("treeify")) {
+            args.treeify = treeify;
+        }
+        if let (Some(relocate), true) = (self.relocate_memory, unset("relocate_memory")) {
+            args.relocate_memory = relocate;
+        }
+        if let (Some(retries), true) = (self.retries, unset("retries")) {
+            args.retries = retries;
+        }
+        if let (Some(seed), true) = (self.seed, unset("seed")) {
+            args.seed = seed;
+        }
+        if let (Some(verify), true) = (self.verify, unset("verify")) {
+            args.verify = verify;
+        }
+        Ok(())
+    }
+}
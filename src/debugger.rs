@@ -0,0 +1,75 @@
+use std::io::{BufRead, IsTerminal, Write};
+
+use alloy_primitives::U256;
+use eyre::Result;
+
+use evm_spilling::interpreter::Interpreter;
+use evm_spilling::tracker::Tracker;
+use evm_spilling::{compile, equivalence, Options};
+
+/// Steps through the code compiled from `source`, printing the stack and the spill registers
+/// after each instruction. Pauses for input between steps when stdin is a terminal.
+pub fn run(source: &str, options: &Options, execute: bool) -> Result<()> {
+    let output = compile(source, options)?;
+    let code = &output.code;
+
+    let mut tracker = Tracker::new(&output);
+    let mut interpreter = execute.then(|| {
+        let mut interpreter = Interpreter::new(output.bytecode.clone());
+        interpreter.stack = equivalence::inputs(&output.program).into_iter().rev().collect();
+        interpreter
+    });
+
+    let mut pause = std::io::stdin().is_terminal();
+    if pause {
+        println!("enter: step, c: continue, q: quit");
+    }
+
+    let mut last_statement = None;
+    for (i, (instr, &statement)) in code.instructions.iter().zip(&code.origins).enumerate() {
+        if let Some(interpreter) = &mut interpreter {
+            if interpreter.halted {
+                break;
+            }
+            interpreter.step(instr)?;
+        }
+        tracker.step(instr, statement);
+
+        if last_statement != Some(statement) {
+            println!("; {}", output.program.named_statement(statement));
+            last_statement = Some(statement);
+        }
+        println!("{i:>4}  {}", instr.display(code.evm_version));
+
+        let values = interpreter.as_ref().map(|interpreter| &interpreter.stack);
+        let stack: Vec<_> = tracker.stack.iter().enumerate().rev()
+            .map(|(index, slot)| {
+                // The interpreter starts without the reserved values beneath.
+                let value = values.zip(index.checked_sub(code.reserved_slots)).and_then(|(v, i)| v.get(i).copied());
+                slot.show(value)
+            })
+            .collect();
+        println!("      stack: [{}]", stack.join(", "));
+
+        for register in 0..code.register_count {
+            let address = options.codegen.spill_base + U256::from(register * 32);
+            let value = interpreter.as_ref().and_then(|interpreter| address.try_into().ok().map(|a| interpreter.mload(a)));
+            let slot = tracker.memory.get(&address).cloned().unwrap_or_default();
+            println!("      r{register} @ {address}: {}", slot.show(value));
+        }
+
+        if pause {
+            print!("> ");
+            std::io::stdout().flush()?;
+            let mut line = String::new();
+            std::io::stdin().lock().read_line(&mut line)?;
+            match line.trim() {
+                "q" => break,
+                "c" => pause = false,
+                _ => {}
+            }
+        }
+    }
+
+    Ok(())
+}
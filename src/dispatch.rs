@@ -0,0 +1,160 @@
+//! Contracts made of functions declared with their ABI signatures. Each function is a straight-line
+//! program compiled on its own, and the runtime code starts with a dispatcher that reads the
+//! selector from the first 4 bytes of the calldata and jumps to the function it belongs to, or
+//! reverts if there is none. Functions read their arguments from the calldata, so they can't use
+//! `takes` or `returns`.
+
+use alloy_primitives::{keccak256, U256};
+
+use crate::codegen;
+use crate::evm::{ControlInstruction, DataInstruction, EvmVersion, Instruction, InstructionSeq, StackInstruction};
+use crate::program::Interner;
+use crate::{check_limit, evm, metadata, parser, Error, Options, Output, Resource};
+
+#[derive(Debug, thiserror::Error)]
+pub enum DispatchError {
+    #[error("Invalid function signature: {0}")]
+    Signature(String),
+    #[error("Functions {first} and {second} have the same selector 0x{selector:08x}")]
+    Selector { first: String, second: String, selector: u32 },
+}
+
+pub struct Function {
+    pub signature: String,
+    pub selector: u32,
+    pub output: Output,
+}
+
+pub struct Contract {
+    pub functions: Vec<Function>,
+    /// The dispatcher followed by each function, which starts at the label of its index.
+    pub instructions: Vec<Instruction>,
+    pub evm_version: EvmVersion,
+    /// Runtime bytecode.
+    pub bytecode: Vec<u8>,
+}
+
+/// The first 4 bytes of the hash of `signature`, which must be a name followed by the types of
+/// the parameters in parentheses, without spaces.
+pub fn selector(signature: &str) -> Result<u32, DispatchError> {
+    let valid = signature.split_once('(').is_some_and(|(name, params)| {
+        !name.is_empty()
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$')
+            && !name.starts_with(|c: char| c.is_ascii_digit())
+            && params.ends_with(')')
+            && !params.contains(char::is_whitespace)
+    });
+    if !valid {
+        return Err(DispatchError::Signature(signature.to_string()));
+    }
+    let hash = keccak256(signature);
+    Ok(u32::from_be_bytes(hash[..4].try_into().unwrap()))
+}
+
+fn push(c: U256) -> Instruction {
+    Instruction::Stack(StackInstruction::Push(c.into()))
+}
+
+/// Compares the selector in the calldata with each of `selectors` and jumps to the label of the
+/// index of the first match, with the selector left on the stack. Calldata shorter than 4 bytes
+/// reverts, since `calldataload` pads it with zeros that could match a selector ending in them.
+fn dispatcher(selectors: &[u32]) -> Vec<Instruction> {
+    let revert = selectors.len();
+    let mut code = vec![
+        // Without `lt`, the size is shorter than 4 bytes when it's zero after dropping two bits.
+        Instruction::Data(DataInstruction::Calldatasize),
+        push(U256::from(2)),
+        Instruction::Data(DataInstruction::Shr),
+        Instruction::Data(DataInstruction::Iszero),
+        Instruction::Control(ControlInstruction::Jumpi(revert)),
+        push(U256::ZERO),
+        Instruction::Data(DataInstruction::Calldataload),
+        push(U256::from(224)),
+        Instruction::Data(DataInstruction::Shr),
+    ];
+    for (label, &selector) in selectors.iter().enumerate() {
+        code.extend([
+            Instruction::Stack(StackInstruction::Dup(0)),
+            push(U256::from(selector)),
+            Instruction::Data(DataInstruction::Eq),
+            Instruction::Control(ControlInstruction::Jumpi(label)),
+        ]);
+    }
+    code.extend([
+        Instruction::Control(ControlInstruction::Jumpdest(revert)),
+        push(U256::ZERO),
+        push(U256::ZERO),
+        Instruction::Data(DataInstruction::Revert),
+    ]);
+    code
+}
+
+fn halts(code: &[Instruction]) -> bool {
+    matches!(code.last(), Some(Instruction::Data(DataInstruction::Stop | DataInstruction::Return | DataInstruction::Revert)))
+}
+
+/// Compiles a contract, each function through the stages of [`crate::compile`]. A function that
+/// doesn't end in a halt is followed by a `stop`. The constant pool is not used, since each
+/// function's loads would address a pool at the end of its own code.
+pub fn compile(source: &str, options: &Options) -> Result<Contract, Error> {
+    let codegen = codegen::Options { constant_pool: None, ..options.codegen.clone() };
+    let mut functions = Vec::new();
+    for (signature, block) in parser::parse_contract(source)? {
+        let selector = selector(&signature)?;
+        if let Some(first) = functions.iter().find(|f: &&Function| f.selector == selector) {
+            return Err(DispatchError::Selector { first: first.signature.clone(), second: signature, selector }.into());
+        }
+        let mut interner = Interner::default();
+        let block = block.map(|name| interner.intern(&name));
+        let output = crate::compile_block(block, &interner, options, |program| codegen::generate(program, &codegen))?;
+        functions.push(Function { signature, selector, output });
+    }
+
+    let mut instructions = dispatcher(&functions.iter().map(|f| f.selector).collect::<Vec<_>>());
+    for (label, function) in functions.iter().enumerate() {
+        let code = &function.output.code.instructions;
+        instructions.extend([Instruction::Control(ControlInstruction::Jumpdest(label)), Instruction::Data(DataInstruction::Pop)]);
+        instructions.extend(code.iter().cloned());
+        if !halts(code) {
+            instructions.push(Instruction::Data(DataInstruction::Stop));
+        }
+    }
+
+    let evm_version = options.codegen.evm_version;
+    let mut bytecode = InstructionSeq::from(instructions.clone()).to_bytes(evm_version).map_err(Error::Assemble)?;
+    evm::verify_jumps(&bytecode).map_err(Error::Assemble)?;
+    if options.metadata {
+        metadata::append(&mut bytecode, source);
+    }
+    check_limit(Resource::BytecodeSize, options.limits.bytecode_size, bytecode.len())?;
+
+    Ok(Contract { functions, instructions, evm_version, bytecode })
+}
+
+impl Contract {
+    /// The dispatcher, then the assembly of each function under its signature.
+    pub fn assembly(&self) -> String {
+        let version = self.evm_version;
+        let mut out = "; dispatch\n".to_string();
+        for instr in dispatcher(&self.functions.iter().map(|f| f.selector).collect::<Vec<_>>()) {
+            out += &format!("{}\n", instr.display(version));
+        }
+        for (label, function) in self.functions.iter().enumerate() {
+            out += &format!("; function {} (0x{:08x})\n", function.signature, function.selector);
+            out += &format!("{}\n", Instruction::Control(ControlInstruction::Jumpdest(label)).display(version));
+            out += &format!("{}\n", Instruction::Data(DataInstruction::Pop).display(version));
+            out += &function.output.assembly().to_string();
+            if !halts(&function.output.code.instructions) {
+                out += &format!("{}\n", Instruction::Data(DataInstruction::Stop).display(version));
+            }
+        }
+        out
+    }
+
+    /// Code that deploys the runtime bytecode.
+    pub fn init_code(&self) -> eyre::Result<Vec<u8>> {
+        let mut code = evm::constructor(self.bytecode.len(), self.evm_version).to_bytes(self.evm_version)?;
+        code.extend(&self.bytecode);
+        Ok(code)
+    }
+}
@@ -0,0 +1,107 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::analysis::live_ranges;
+use crate::codegen::Code;
+use crate::evm::{ControlInstruction, DataInstruction, Instruction};
+use crate::scope::{ResolvedBlock, Var};
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn ends_block(instr: &Instruction) -> bool {
+    matches!(instr,
+        Instruction::Control(ControlInstruction::Jump(_) | ControlInstruction::Jumpi(_))
+        | Instruction::Data(DataInstruction::Stop | DataInstruction::Return | DataInstruction::Revert))
+}
+
+fn falls_through(instr: &Instruction) -> bool {
+    !matches!(instr,
+        Instruction::Control(ControlInstruction::Jump(_))
+        | Instruction::Data(DataInstruction::Stop | DataInstruction::Return | DataInstruction::Revert))
+}
+
+/// Control flow graph of the generated code, with one node per basic block listing its instructions.
+pub fn cfg(code: &Code) -> String {
+    let mut blocks: Vec<&[Instruction]> = Vec::new();
+    let mut start = 0;
+    for (i, instr) in code.instructions.iter().enumerate() {
+        let starts = matches!(instr, Instruction::Control(ControlInstruction::Jumpdest(_)));
+        if starts && i > start {
+            blocks.push(&code.instructions[start..i]);
+            start = i;
+        }
+        if ends_block(instr) {
+            blocks.push(&code.instructions[start..=i]);
+            start = i + 1;
+        }
+    }
+    if start < code.instructions.len() {
+        blocks.push(&code.instructions[start..]);
+    }
+
+    let entries: HashMap<usize, usize> = blocks.iter().enumerate()
+        .filter_map(|(b, block)| match block.first() {
+            Some(Instruction::Control(ControlInstruction::Jumpdest(label))) => Some((*label, b)),
+            _ => None,
+        })
+        .collect();
+
+    let mut out = String::from("digraph cfg {\n    node [shape=box, fontname=monospace];\n");
+    for (b, block) in blocks.iter().enumerate() {
+        let lines: String = block.iter().map(|i| format!("{}\\l", escape(&i.display(code.evm_version).to_string()))).collect();
+        let _ = writeln!(out, "    b{b} [label=\"{lines}\"];");
+
+        let last = block.last().unwrap();
+        if let Instruction::Control(ControlInstruction::Jump(label) | ControlInstruction::Jumpi(label)) = last {
+            if let Some(target) = entries.get(label) {
+                let _ = writeln!(out, "    b{b} -> b{target} [label=\"jump\"];");
+            }
+        }
+        if falls_through(last) && b + 1 < blocks.len() {
+            let _ = writeln!(out, "    b{b} -> b{};", b + 1);
+        }
+    }
+    out += "}\n";
+    out
+}
+
+/// Interference graph of the program's variables: an edge joins two variables that are live at
+/// the same time. Spilled variables are filled, labelled with the registers they were given.
+pub fn interference(rblock: &ResolvedBlock, code: &Code) -> String {
+    let ranges = live_ranges(rblock);
+
+    let mut registers: HashMap<Var, Vec<usize>> = HashMap::new();
+    for spill in code.spills.iter().filter(|s| !s.restore) {
+        let entry = registers.entry(spill.var).or_default();
+        if !entry.contains(&spill.register) {
+            entry.push(spill.register);
+        }
+    }
+
+    let mut out = String::from("graph interference {\n    node [fontname=monospace];\n");
+    for (index, range) in ranges.iter().enumerate() {
+        let Some(range) = range else { continue };
+        let var = Var::new(index);
+        let name = escape(rblock.name(var));
+        match registers.get(&var) {
+            Some(rs) => {
+                let rs: Vec<_> = rs.iter().map(|r| format!("r{r}")).collect();
+                let _ = writeln!(out, "    {var} [label=\"{name}\\n{}\", style=filled, fillcolor=\"#f4c0c0\"];", rs.join(", "));
+            }
+            None => {
+                let _ = writeln!(out, "    {var} [label=\"{name}\"];");
+            }
+        }
+        for (other, other_range) in ranges.iter().enumerate().skip(index + 1) {
+            let Some(other_range) = other_range else { continue };
+            let overlap = range.start.max(other_range.start) < range.end.min(other_range.end);
+            if overlap || range.start == other_range.start {
+                let _ = writeln!(out, "    {var} -- {};", Var::new(other));
+            }
+        }
+    }
+    out += "}\n";
+    out
+}
@@ -0,0 +1,115 @@
+//! EIP-3540 EOF containers. Code is placed in a single code section with no data, and jumps
+//! become `RJUMP`/`RJUMPI` with offsets relative to the end of the instruction.
+
+use std::collections::HashMap;
+
+use eyre::{bail, ensure, eyre, Result};
+
+use crate::evm::{push_width, ControlInstruction, DataInstruction, EvmVersion, Instruction, InstructionSeq, StackInstruction};
+
+const MAGIC: [u8; 2] = [0xef, 0x00];
+const VERSION: u8 = 0x01;
+const KIND_TYPES: u8 = 0x01;
+const KIND_CODE: u8 = 0x02;
+const KIND_DATA: u8 = 0xff;
+const TERMINATOR: u8 = 0x00;
+
+/// Section outputs marking a function that never returns to a caller.
+const NON_RETURNING: u8 = 0x80;
+
+const STOP: u8 = 0x00;
+const RJUMP: u8 = 0xe0;
+const RJUMPI: u8 = 0xe1;
+
+fn size(instr: &Instruction) -> usize {
+    match instr {
+        Instruction::Stack(StackInstruction::Push(c)) => 1 + push_width(&c.value()),
+        Instruction::Control(ControlInstruction::Jump(_) | ControlInstruction::Jumpi(_)) => 3,
+        // Jump targets need no marker in EOF.
+        Instruction::Control(ControlInstruction::Jumpdest(_)) => 0,
+        Instruction::Truncated(bytes) | Instruction::Verbatim(bytes, _, _) => bytes.len(),
+        Instruction::Immutable(_) => 33,
+        _ => 1,
+    }
+}
+
+/// Number of values the code takes from the stack it starts with, and the stack height it
+/// reaches counting those, when executed in order. Codegen emits straight-line code, so this is
+/// the maximum over all paths.
+pub(crate) fn stack_bounds(code: &[Instruction]) -> (usize, usize) {
+    let mut height: isize = 0;
+    let (mut min, mut max) = (0, 0);
+    for instr in code {
+        if let Some(metadata) = instr.metadata(EvmVersion::LATEST) {
+            height -= metadata.inputs as isize;
+            min = min.min(height);
+            height += metadata.outputs as isize;
+            max = max.max(height);
+        }
+    }
+    (min.unsigned_abs(), (max - min).unsigned_abs())
+}
+
+/// Instructions that EOF code can't contain, since they observe code or gas.
+fn is_banned(op: DataInstruction) -> bool {
+    matches!(op, DataInstruction::Codesize | DataInstruction::Codecopy | DataInstruction::Gas)
+}
+
+impl InstructionSeq {
+    /// Assembles the sequence into an EOF container, terminated with `STOP`. The code can't take
+    /// inputs from the stack, nor copy from the constant pool or itself.
+    pub fn to_eof(&self) -> Result<Vec<u8>> {
+        if let Some(instr) = self.iter().find(|i| matches!(i, Instruction::Data(op) if is_banned(*op))) {
+            bail!("{instr} is not allowed in EOF code");
+        }
+        let (inputs, max_stack_height) = stack_bounds(&self[..]);
+        ensure!(inputs == 0, "EOF code can't take inputs from the stack, but this takes {inputs}");
+
+        let mut labels = HashMap::new();
+        let mut pc = 0;
+        for instr in self {
+            if let Instruction::Control(ControlInstruction::Jumpdest(label)) = instr {
+                ensure!(labels.insert(*label, pc).is_none(), "Duplicate label: {label}");
+            }
+            pc += size(instr);
+        }
+
+        let mut code = Vec::new();
+        for instr in self {
+            match instr {
+                Instruction::Control(ControlInstruction::Jump(label) | ControlInstruction::Jumpi(label)) => {
+                    let target = *labels.get(label).ok_or_else(|| eyre!("Unknown label: {label}"))?;
+                    let offset = i16::try_from(target as isize - (code.len() + 3) as isize)
+                        .map_err(|_| eyre!("Relative jump to label {label} out of range"))?;
+                    let opcode = if matches!(instr, Instruction::Control(ControlInstruction::Jump(_))) { RJUMP } else { RJUMPI };
+                    code.push(opcode);
+                    code.extend_from_slice(&offset.to_be_bytes());
+                }
+                Instruction::Control(ControlInstruction::Jumpdest(_)) => {}
+                // EOF postdates `push0`, so pushes use the narrowest width.
+                _ => instr.encode(&mut code, &labels, EvmVersion::LATEST)?,
+            }
+        }
+        code.push(STOP);
+
+        let code_size = u16::try_from(code.len()).map_err(|_| eyre!("Code section too large"))?;
+        let max_stack_height = u16::try_from(max_stack_height)
+            .map_err(|_| eyre!("Stack height too large"))?;
+
+        let mut out = Vec::new();
+        out.extend_from_slice(&MAGIC);
+        out.push(VERSION);
+        out.push(KIND_TYPES);
+        out.extend_from_slice(&4u16.to_be_bytes());
+        out.push(KIND_CODE);
+        out.extend_from_slice(&1u16.to_be_bytes());
+        out.extend_from_slice(&code_size.to_be_bytes());
+        out.push(KIND_DATA);
+        out.extend_from_slice(&0u16.to_be_bytes());
+        out.push(TERMINATOR);
+        out.extend_from_slice(&[0, NON_RETURNING]);
+        out.extend_from_slice(&max_stack_height.to_be_bytes());
+        out.extend(code);
+        Ok(out)
+    }
+}
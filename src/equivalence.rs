@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+
+use alloy_primitives::{keccak256, U256};
+use eyre::{bail, ensure, eyre, Result};
+
+use crate::codegen::HEAP_START;
+use crate::evm::{DataInstruction, Instruction, StackInstruction};
+use crate::interpreter::Interpreter;
+use crate::program::{Expression, Statement};
+use crate::scope::ResolvedBlock;
+use crate::{analysis, Output};
+
+/// A distinct value for each opcode that reads the environment, so that code mixing two of them up
+/// gets caught.
+fn environment() -> HashMap<String, U256> {
+    (0..=u8::MAX)
+        .filter_map(DataInstruction::from_opcode)
+        .filter(|op| op.arity() == (0, 1))
+        .map(|op| (op.name().to_string(), keccak256(op.name()).into()))
+        .collect()
+}
+
+/// A distinct value for each of the inputs of `program`, the first for the top of the stack.
+pub fn inputs(program: &ResolvedBlock) -> Vec<U256> {
+    (0..program.inputs().len()).map(|i| keccak256(format!("input{i}")).into()).collect()
+}
+
+/// Runs the statements of `program` directly, with each operation applied to its arguments as if
+/// they were the only values on the stack. The values it returns are left on the stack. Constant
+/// memory offsets are moved by `memory_offset`, as codegen does when relocating memory.
+pub fn interpret_program(
+    program: &ResolvedBlock,
+    code: Vec<u8>,
+    immutables: &HashMap<String, U256>,
+    memory_offset: Option<U256>,
+) -> Result<Interpreter> {
+    let mut interpreter = Interpreter::new(code);
+    interpreter.immutables = immutables.clone();
+    interpreter.environment = environment();
+    let mut values = vec![U256::ZERO; program.var_count];
+    let offsets = analysis::constant_offsets(program);
+
+    for Statement(ress, e) in &program.block.0 {
+        if interpreter.halted {
+            break;
+        }
+        let instr = match e {
+            Expression::Input => {
+                for (&r, value) in ress.iter().zip(inputs(program)) {
+                    values[r.index()] = value;
+                }
+                continue;
+            }
+            Expression::Output(args) => {
+                interpreter.stack = args.iter().rev().map(|a| values[a.index()]).collect();
+                continue;
+            }
+            Expression::Const(c) => match memory_offset {
+                Some(offset) if offsets[ress[0].index()] => Instruction::Stack(StackInstruction::Push(offset.wrapping_add(*c).into())),
+                _ => Instruction::Stack(StackInstruction::Push((*c).into())),
+            },
+            Expression::Immutable(name) => Instruction::Immutable(name.as_str().into()),
+            Expression::Op(op, args) => {
+                interpreter.stack.extend(args.iter().rev().map(|a| values[a.index()]));
+                let op: DataInstruction = op.parse().map_err(|_| eyre!("Unknown operator: {op}"))?;
+                Instruction::Data(op)
+            }
+            Expression::Verbatim(name, ..) => bail!("extern op {name} can't be interpreted"),
+        };
+        interpreter.step(&instr)?;
+        ensure!(interpreter.halted || interpreter.stack.len() == ress.len(), "wrong number of results from {e}");
+        for (&r, value) in ress.iter().zip(interpreter.stack.drain(..)) {
+            values[r.index()] = value;
+        }
+    }
+
+    Ok(interpreter)
+}
+
+/// Runs the generated instructions until they halt or run out.
+pub fn interpret_code(output: &Output, immutables: &HashMap<String, U256>) -> Result<Interpreter> {
+    let mut interpreter = Interpreter::new(output.bytecode.clone());
+    interpreter.immutables = immutables.clone();
+    interpreter.environment = environment();
+    interpreter.stack = inputs(&output.program).into_iter().rev().collect();
+    for instr in &output.code.instructions {
+        if interpreter.halted {
+            break;
+        }
+        interpreter.step(instr)?;
+    }
+    Ok(interpreter)
+}
+
+/// Checks that the generated code has the same effects as the program it was generated from:
+/// the same memory outside the spill registers, transient storage, logs and returned data, or only
+/// the same data if both revert.
+pub fn check(output: &Output, immutables: &HashMap<String, U256>) -> Result<()> {
+    let mut immutables = immutables.clone();
+    immutables.insert(HEAP_START.to_string(), output.code.heap_start);
+    let expected = interpret_program(&output.program, output.bytecode.clone(), &immutables, output.code.memory_offset)?;
+    let actual = interpret_code(output, &immutables)?;
+
+    ensure!(expected.reverted == actual.reverted, "only one of the executions reverts");
+    if expected.reverted {
+        ensure!(expected.output == actual.output, "revert data differs");
+        return Ok(());
+    }
+
+    let code = &output.code;
+    let spill_start: usize = code.spill_base.try_into().unwrap_or(usize::MAX);
+    let spill_end = spill_start.saturating_add(code.register_count * 32);
+    let len = expected.memory.len().max(actual.memory.len());
+    if let Some(offset) = (0..len)
+        .filter(|offset| !(spill_start..spill_end).contains(offset))
+        .find(|&offset| expected.memory.get(offset).unwrap_or(&0) != actual.memory.get(offset).unwrap_or(&0))
+    {
+        return Err(eyre!("memory differs at offset {offset}"));
+    }
+
+    ensure!(expected.transient == actual.transient, "transient storage differs");
+    ensure!(expected.logs == actual.logs, "logs differ");
+    ensure!(expected.output == actual.output, "returned data differs");
+    if !expected.halted && !output.program.outputs().is_empty() {
+        ensure!(expected.stack == actual.stack, "values left on the stack differ");
+    }
+    Ok(())
+}
@@ -0,0 +1,150 @@
+//! Debug info in the ethdebug format: for each instruction, its source range and where the live
+//! variables are just before it runs, either in a stack slot or in a memory register once spilled.
+
+use std::collections::HashSet;
+
+use alloy_primitives::{hex, U256};
+use eyre::{eyre, Result};
+use serde_json::{json, Value};
+
+use crate::evm::{ControlInstruction, Instruction, InstructionSeq, StackInstruction};
+use crate::program::Span;
+use crate::tracker::Tracker;
+use crate::{analysis, Output};
+
+fn hex_string(value: U256) -> String {
+    let bytes = value.to_be_bytes_trimmed_vec();
+    hex::encode_prefixed(if bytes.is_empty() { vec![0] } else { bytes })
+}
+
+fn hex_value(value: U256) -> Value {
+    json!({ "type": "hex", "value": hex_string(value) })
+}
+
+fn range(span: &Span) -> Value {
+    json!({ "source": { "id": 0 }, "range": { "offset": span.start, "length": span.len() } })
+}
+
+/// The operation of each opcode `instr` assembles to, with its offset. Jumps are a push of the
+/// target followed by the jump, and verbatim bytes are left out.
+fn operations(instr: &Instruction, offset: usize, size: usize, target: Option<usize>, output: &Output) -> Vec<(usize, Value)> {
+    let version = output.code.evm_version;
+    let operation = |mnemonic: &str, arguments: Vec<Value>| {
+        let mnemonic = mnemonic.to_uppercase();
+        if arguments.is_empty() { json!({ "mnemonic": mnemonic }) } else { json!({ "mnemonic": mnemonic, "arguments": arguments }) }
+    };
+    match instr {
+        Instruction::Control(ControlInstruction::Jump(_) | ControlInstruction::Jumpi(_)) => {
+            let name = instr.metadata(version).unwrap().name;
+            let target = target.map(U256::from).unwrap_or_default();
+            vec![
+                (offset, operation(&format!("push{}", size - 2), vec![hex_value(target)])),
+                (offset + size - 1, operation(&name, vec![])),
+            ]
+        }
+        Instruction::Verbatim(..) => vec![],
+        Instruction::Stack(StackInstruction::Push(c)) if size > 1 => {
+            vec![(offset, operation(&format!("push{}", size - 1), vec![hex_value(c.value())]))]
+        }
+        // The value of an immutable is filled in at deployment.
+        Instruction::Immutable(_) => vec![(offset, operation("push32", vec![]))],
+        _ => {
+            let name = instr.metadata(version).map_or_else(|| "invalid".to_string(), |m| m.name);
+            vec![(offset, operation(&name, vec![]))]
+        }
+    }
+}
+
+/// Debug info for the code in `output`, compiled from `source`. A variable is listed from its
+/// definition to its last use, at the topmost stack slot holding it, or otherwise at the memory
+/// register it was spilled to.
+pub fn info(output: &Output, source: &str) -> Result<Value> {
+    let program = &output.program;
+    let code = &output.code;
+    let spans = &program.block.1;
+    let seq = InstructionSeq::from(code.instructions.clone());
+    let offsets = seq.offsets(code.evm_version)?;
+    let labels = seq.labels(code.evm_version)?;
+    let ranges = analysis::live_ranges(program);
+    let registers = code.spill_region();
+
+    let live = |name: &str, statement: usize| {
+        program.names.iter().zip(&ranges)
+            .any(|(n, r)| n == name && r.as_ref().is_some_and(|r| (r.start..=r.end).contains(&statement)))
+    };
+    let declaration = |name: &str| {
+        let var = program.names.iter().position(|n| n == name)?;
+        let statement = program.flat.iter().position(|(vs, _)| vs.iter().any(|v| v.index() == var))?;
+        spans.get(statement).map(range)
+    };
+
+    let mut tracker = Tracker::new(output);
+    let mut instructions = Vec::new();
+    for (i, (instr, &statement)) in code.instructions.iter().zip(&code.origins).enumerate() {
+        let mut seen = HashSet::new();
+        let mut variables = Vec::new();
+        let mut variable = |name: &str, pointer: Value| {
+            if live(name, statement) && seen.insert(name.to_string()) {
+                let mut variable = json!({ "identifier": name, "pointer": pointer });
+                if let Some(declaration) = declaration(name) {
+                    variable["declaration"] = declaration;
+                }
+                variables.push(variable);
+            }
+        };
+        for (slot, item) in tracker.stack.iter().rev().enumerate() {
+            if let Some(name) = &item.name {
+                variable(name, json!({ "location": "stack", "slot": slot }));
+            }
+        }
+        let mut spilled: Vec<_> = tracker.memory.iter().filter(|(&address, _)| registers.contains(&address)).collect();
+        spilled.sort_by_key(|(&address, _)| address);
+        for (&address, item) in spilled {
+            if let Some(name) = &item.name {
+                variable(name, json!({ "location": "memory", "offset": hex_string(address), "length": 32 }));
+            }
+        }
+
+        let mut context = Vec::new();
+        if let Some(span) = spans.get(statement) {
+            context.push(json!({ "code": range(span) }));
+        }
+        if !variables.is_empty() {
+            context.push(json!({ "variables": variables }));
+        }
+        if let Instruction::Immutable(name) = instr {
+            context.push(json!({ "remark": format!("immutable {name}") }));
+        }
+
+        let size = instr.size(&labels, code.evm_version);
+        let target = match instr {
+            Instruction::Control(ControlInstruction::Jump(l) | ControlInstruction::Jumpi(l)) => {
+                Some(*labels.get(l).ok_or_else(|| eyre!("Unknown label: {l}"))?)
+            }
+            _ => None,
+        };
+        for (offset, operation) in operations(instr, offsets[i], size, target, output) {
+            let mut entry = json!({ "offset": offset, "operation": operation });
+            match context.len() {
+                0 => {}
+                1 => entry["context"] = context[0].clone(),
+                _ => entry["context"] = json!({ "gather": context }),
+            }
+            instructions.push(entry);
+        }
+
+        tracker.step(instr, statement);
+    }
+
+    Ok(json!({
+        "compilation": {
+            "compiler": { "name": env!("CARGO_PKG_NAME"), "version": env!("CARGO_PKG_VERSION") },
+            "sources": [{ "id": 0, "path": "source", "contents": source }],
+        },
+        "programs": [{
+            "contract": { "name": "Program", "definition": { "source": { "id": 0 } } },
+            "environment": "call",
+            "instructions": instructions,
+        }],
+    }))
+}
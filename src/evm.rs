@@ -1,6 +1,6 @@
-use std::{fmt::Display, str::FromStr};
+use std::{collections::{BTreeMap, HashMap}, fmt::Display, ops::Range, str::FromStr};
 
-use eyre::{eyre, Error, Ok, Result, Report};
+use eyre::{ensure, eyre, Error, Ok, Result, Report};
 use alloy_primitives::U256;
 
 #[derive(Debug, Clone)]
@@ -8,82 +8,972 @@ pub enum Instruction {
     Stack(StackInstruction),
     Control(ControlInstruction),
     Data(DataInstruction),
+    /// An opcode that is not modeled, found when disassembling.
+    Unknown(u8),
+    /// A push whose immediate runs past the end of the code, kept as raw bytes.
+    Truncated(Box<[u8]>),
+    /// Pre-assembled bytes inserted as is, taking and returning the given numbers of stack items.
+    Verbatim(Box<[u8]>, u16, u16),
+    /// A named value patched in after assembly, assembled as a `push32` of zero.
+    Immutable(Box<str>),
 }
 
 #[derive(Debug, Clone)]
 pub enum StackInstruction {
     Dup(usize),
     Swap(usize),
-    Push(Box<U256>),
+    Push(Word),
 }
 
+/// A pushed constant, stored inline when it fits in 64 bits as most do, so that pushes don't
+/// allocate and instructions stay small.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Word {
+    Small(u64),
+    Large(Box<U256>),
+}
+
+impl Word {
+    pub fn value(&self) -> U256 {
+        match self {
+            Word::Small(c) => U256::from(*c),
+            Word::Large(c) => **c,
+        }
+    }
+}
+
+impl From<U256> for Word {
+    fn from(c: U256) -> Word {
+        u64::try_from(c).map_or_else(|_| Word::Large(Box::new(c)), Word::Small)
+    }
+}
+
+impl Display for Word {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Word::Small(c) => write!(f, "{c}"),
+            Word::Large(c) => write!(f, "{c}"),
+        }
+    }
+}
+
+/// Jumps refer to labels, which are resolved to program counters when assembling.
 #[derive(Debug, Clone)]
 pub enum ControlInstruction {
     Jump(usize),
     Jumpi(usize),
-    Jumpdest,
+    Jumpdest(usize),
 }
 
-#[derive(Debug, Clone, Copy)]
-pub enum DataInstruction {
-    Pop, // considered data no-op
-    Mstore,
-    Mload,
-    Add,
+/// Hard forks of the EVM, in chronological order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum EvmVersion {
+    London,
+    Paris,
+    Shanghai,
+    #[default]
+    Cancun,
+    Prague,
+    Osaka,
 }
 
-impl DataInstruction {
-    pub fn arity(&self) -> (usize, usize) {
-        use DataInstruction::*;
-        match self {
-            Pop => (1, 0),
-            Mstore => (2, 0),
-            Mload => (1, 1),
-            Add => (2, 1),
+impl FromStr for EvmVersion {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        use EvmVersion::*;
+        match s {
+            "london" => Ok(London),
+            "paris" => Ok(Paris),
+            "shanghai" => Ok(Shanghai),
+            "cancun" => Ok(Cancun),
+            "prague" => Ok(Prague),
+            "osaka" => Ok(Osaka),
+            _ => Err(eyre!("Unknown EVM version: {s}")),
         }
     }
 }
 
+impl Display for EvmVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use EvmVersion::*;
+        f.write_str(match self {
+            London => "london",
+            Paris => "paris",
+            Shanghai => "shanghai",
+            Cancun => "cancun",
+            Prague => "prague",
+            Osaka => "osaka",
+        })
+    }
+}
+
+impl EvmVersion {
+    pub const LATEST: EvmVersion = EvmVersion::Osaka;
+
+    pub fn has_push0(self) -> bool {
+        self >= EvmVersion::Shanghai
+    }
+
+    pub fn has_transient_storage(self) -> bool {
+        self >= EvmVersion::Cancun
+    }
+
+    pub fn has_mcopy(self) -> bool {
+        self >= EvmVersion::Cancun
+    }
+
+    /// `dupn`/`swapn` were proposed along with EOF, which none of these versions include, so stack
+    /// access is limited to `dup16`/`swap16` everywhere.
+    pub fn has_dupn_swapn(self) -> bool {
+        false
+    }
+
+    pub fn supports(self, op: DataInstruction) -> bool {
+        self >= op.since()
+    }
+
+    /// Number of immediate bytes of the narrowest push for `value` available in this version.
+    pub fn push_width(self, value: &U256) -> usize {
+        if self.has_push0() { push_width(value) } else { push_width(value).max(1) }
+    }
+}
+
+// Generated from `opcodes.json` by the build script. New data instructions only need an entry there.
+include!(concat!(env!("OUT_DIR"), "/data_instructions.rs"));
+
 impl FromStr for DataInstruction {
     type Err = Report;
 
     fn from_str(op: &str) -> Result<Self> {
+        DataInstruction::from_name(op).ok_or_else(|| eyre!("Unknown operator: {op}"))
+    }
+}
+
+impl DataInstruction {
+    /// Whether the instruction does nothing but compute its results, so that it can be left out
+    /// when they are unused. Loads from memory count as effects, since they can expand it.
+    pub fn is_pure(self) -> bool {
+        use DataInstruction::*;
+        !matches!(self, Stop | Codecopy | Mload | Mstore | Tstore | Mcopy | Log0 | Log1 | Log2 | Log3 | Log4 | Return | Revert)
+    }
+
+    /// Indices of the arguments that are memory offsets, counted from the top of the stack.
+    pub fn memory_offsets(self) -> &'static [usize] {
         use DataInstruction::*;
-        match op {
-            "pop" => Ok(Pop),
-            "mstore" => Ok(Mstore),
-            "mload" => Ok(Mload),
-            "add" => Ok(Add),
-            _ => Err(eyre!("Unknown operator: {op}")),
+        match self {
+            Codecopy | Mload | Mstore | Log0 | Log1 | Log2 | Log3 | Log4 | Return | Revert => &[0],
+            Mcopy => &[0, 1],
+            _ => &[],
         }
     }
 }
 
-impl Display for Instruction {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        use DataInstruction::*;
+/// Static gas cost classes, named after the yellow paper's fee schedule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GasTier {
+    Zero,
+    Jumpdest,
+    Base,
+    VeryLow,
+    Low,
+    Mid,
+    High,
+    WarmAccess,
+    /// `log0` to `log4`, before the cost of the data: one `G_log` and a `G_logtopic` per topic.
+    Log0,
+    Log1,
+    Log2,
+    Log3,
+    Log4,
+}
+
+impl GasTier {
+    pub fn cost(self) -> u64 {
+        match self {
+            GasTier::Zero => 0,
+            GasTier::Jumpdest => 1,
+            GasTier::Base => 2,
+            GasTier::VeryLow => 3,
+            GasTier::Low => 5,
+            GasTier::Mid => 8,
+            GasTier::High => 10,
+            GasTier::WarmAccess => 100,
+            GasTier::Log0 => 375,
+            GasTier::Log1 => 750,
+            GasTier::Log2 => 1125,
+            GasTier::Log3 => 1500,
+            GasTier::Log4 => 1875,
+        }
+    }
+}
+
+/// Static properties of an instruction as assembled for some EVM version.
+///
+/// Jumps take their target from a label and are assembled with a push of it, so their inputs
+/// don't include the target and their immediate size doesn't include the push, whose width
+/// depends on layout.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Metadata {
+    pub name: String,
+    pub inputs: usize,
+    pub outputs: usize,
+    pub immediate_size: usize,
+    /// `None` for verbatim bytes, whose cost isn't known.
+    pub gas_tier: Option<GasTier>,
+}
+
+impl Instruction {
+    /// Metadata for this instruction in `version`, or `None` for unknown opcodes.
+    pub fn metadata(&self, version: EvmVersion) -> Option<Metadata> {
         use StackInstruction::*;
+        use ControlInstruction::*;
+        let metadata = |name: String, inputs, outputs, immediate_size, gas_tier| {
+            Some(Metadata { name, inputs, outputs, immediate_size, gas_tier: Some(gas_tier) })
+        };
         match self {
-            Instruction::Stack(Dup(i)) => write!(f, "dup{}", i + 1),
-            Instruction::Stack(Swap(i)) => write!(f, "swap{i}"),
-            Instruction::Stack(Push(c)) if c.is_zero() => write!(f, "push0"),
-            Instruction::Stack(Push(c)) => write!(f, "push{} {c}", c.byte_len()),
-            Instruction::Data(Pop) => write!(f, "pop"),
-            Instruction::Data(Mstore) => write!(f, "mstore"),
-            Instruction::Data(Mload) => write!(f, "mload"),
-            Instruction::Data(Add) => write!(f, "add"),
-            Instruction::Control(_) => todo!(),
+            Instruction::Stack(Dup(i)) => metadata(format!("dup{}", i + 1), i + 1, i + 2, 0, GasTier::VeryLow),
+            Instruction::Stack(Swap(i)) => metadata(format!("swap{i}"), i + 1, i + 1, 0, GasTier::VeryLow),
+            Instruction::Stack(Push(c)) => {
+                let width = version.push_width(&c.value());
+                let tier = if width == 0 { GasTier::Base } else { GasTier::VeryLow };
+                metadata(format!("push{width}"), 0, 1, width, tier)
+            }
+            Instruction::Data(op) => {
+                let (inputs, outputs) = op.arity();
+                metadata(op.name().to_string(), inputs, outputs, 0, op.gas_tier())
+            }
+            Instruction::Control(Jump(_)) => metadata("jump".to_string(), 0, 0, 0, GasTier::Mid),
+            Instruction::Control(Jumpi(_)) => metadata("jumpi".to_string(), 1, 0, 0, GasTier::High),
+            Instruction::Control(Jumpdest(_)) => metadata("jumpdest".to_string(), 0, 0, 0, GasTier::Jumpdest),
+            Instruction::Unknown(_) => None,
+            Instruction::Truncated(bytes) => {
+                metadata(format!("push{}", bytes[0] - 0x5f), 0, 1, bytes.len() - 1, GasTier::VeryLow)
+            }
+            Instruction::Immutable(_) => metadata("immutable".to_string(), 0, 1, 32, GasTier::VeryLow),
+            Instruction::Verbatim(bytes, takes, returns) => Some(Metadata {
+                name: "verbatim".to_string(),
+                inputs: *takes as usize,
+                outputs: *returns as usize,
+                immediate_size: bytes.len(),
+                gas_tier: None,
+            }),
         }
     }
+
+    /// Static gas cost in `fork`, not including memory expansion or the per-word part of copies.
+    /// Jumps include the push of their target. No modeled instruction was repriced from London on,
+    /// so the fork only matters for a zero push, priced as `push1 0` before Shanghai. Instructions
+    /// not available in `fork` are priced as in the fork that introduced them, and unknown opcodes
+    /// and verbatim bytes are priced at zero.
+    pub fn gas_cost(&self, fork: EvmVersion) -> u64 {
+        let push = match self {
+            Instruction::Control(ControlInstruction::Jump(_) | ControlInstruction::Jumpi(_)) => GasTier::VeryLow.cost(),
+            _ => 0,
+        };
+        push + self.metadata(fork).and_then(|m| m.gas_tier).map_or(0, GasTier::cost)
+    }
+
+    /// Number of opcodes the instruction assembles to.
+    pub fn opcode_count(&self) -> usize {
+        match self {
+            Instruction::Control(ControlInstruction::Jump(_) | ControlInstruction::Jumpi(_)) => 2,
+            Instruction::Verbatim(bytes, _, _) => {
+                let mut count = 0;
+                let mut pc = 0;
+                while pc < bytes.len() {
+                    if let 0x60..=0x7f = bytes[pc] {
+                        pc += (bytes[pc] - 0x5f) as usize;
+                    }
+                    pc += 1;
+                    count += 1;
+                }
+                count
+            }
+            _ => 1,
+        }
+    }
+
+    /// Size in bytes of the encoded instruction, given the program counter of each jump label.
+    pub fn size(&self, labels: &HashMap<usize, usize>, version: EvmVersion) -> usize {
+        match self {
+            Instruction::Stack(StackInstruction::Push(c)) => 1 + version.push_width(&c.value()),
+            Instruction::Control(ControlInstruction::Jump(label) | ControlInstruction::Jumpi(label)) => {
+                2 + version.push_width(&U256::from(labels.get(label).copied().unwrap_or(0)))
+            }
+            Instruction::Truncated(bytes) | Instruction::Verbatim(bytes, _, _) => bytes.len(),
+            Instruction::Immutable(_) => 33,
+            _ => 1,
+        }
+    }
+
+    pub fn encode(&self, out: &mut Vec<u8>, labels: &HashMap<usize, usize>, version: EvmVersion) -> Result<()> {
+        use StackInstruction::*;
+        use ControlInstruction::*;
+        match self {
+            Instruction::Stack(Dup(i)) => out.push(0x80 + *i as u8),
+            Instruction::Stack(Swap(i)) => out.push(0x8f + *i as u8),
+            Instruction::Stack(Push(c)) => push(out, &c.value(), version),
+            Instruction::Data(op) => {
+                ensure!(version.supports(*op), "{self} is not available in {version:?}");
+                out.push(op.opcode());
+            }
+            Instruction::Control(Jump(label)) => {
+                push(out, &jump_target(labels, *label)?, version);
+                out.push(0x56);
+            }
+            Instruction::Control(Jumpi(label)) => {
+                push(out, &jump_target(labels, *label)?, version);
+                out.push(0x57);
+            }
+            Instruction::Control(Jumpdest(_)) => out.push(0x5b),
+            Instruction::Unknown(opcode) => out.push(*opcode),
+            Instruction::Truncated(bytes) | Instruction::Verbatim(bytes, _, _) => out.extend_from_slice(bytes),
+            Instruction::Immutable(_) => {
+                out.push(0x7f);
+                out.extend_from_slice(&[0; 32]);
+            }
+        }
+        Ok(())
+    }
 }
 
-pub struct InstructionSeq(pub Vec<Instruction>);
+fn jump_target(labels: &HashMap<usize, usize>, label: usize) -> Result<U256> {
+    labels.get(&label).map(|&pc| U256::from(pc)).ok_or_else(|| eyre!("Unknown label: {label}"))
+}
 
-impl Display for InstructionSeq {
+/// Number of immediate bytes of the narrowest push for `value`, where 0 stands for `push0`.
+pub fn push_width(value: &U256) -> usize {
+    value.byte_len()
+}
+
+fn push(out: &mut Vec<u8>, c: &U256, version: EvmVersion) {
+    let width = version.push_width(c);
+    out.push(0x5f + width as u8);
+    out.extend_from_slice(&c.to_be_bytes::<32>()[32 - width..]);
+}
+
+/// Gas to expand memory from empty to `size` bytes.
+pub fn memory_cost(size: u64) -> u64 {
+    let words = size.div_ceil(32) as u128;
+    (3 * words + words * words / 512).try_into().unwrap_or(u64::MAX)
+}
+
+/// Constructor code that copies the `runtime_size` bytes following it to memory and returns them.
+pub fn constructor(runtime_size: usize, version: EvmVersion) -> InstructionSeq {
+    use DataInstruction::*;
+
+    let constructor = |offset: usize| InstructionSeq::builder()
+        .push(U256::from(runtime_size))
+        .dup(0)
+        .push(U256::from(offset))
+        .push(U256::ZERO)
+        .op(Codecopy)
+        .push(U256::ZERO)
+        .op(Return)
+        .build();
+
+    // The runtime starts right after the constructor, whose size depends on the offset pushed.
+    let mut offset = 0;
+    let mut code = constructor(offset);
+    loop {
+        let size = code.iter().map(|i| i.size(&HashMap::new(), version)).sum();
+        if size == offset {
+            break;
+        }
+        offset = size;
+        code = constructor(offset);
+    }
+    code
+}
+
+/// Wraps `runtime` in constructor code, producing init code suitable for a contract creation
+/// transaction.
+pub fn init_code(runtime: &InstructionSeq, version: EvmVersion) -> Result<Vec<u8>> {
+    let runtime = runtime.to_bytes(version)?;
+    let mut code = constructor(runtime.len(), version).to_bytes(version)?;
+    code.extend(runtime);
+    Ok(code)
+}
+
+/// Checks that every static jump in `code`, a push immediately followed by `jump` or `jumpi`,
+/// targets a `jumpdest` that is not inside push data. Data appended to the code is scanned like
+/// code, so it should be left out.
+pub fn verify_jumps(code: &[u8]) -> Result<()> {
+    let mut jumpdests = vec![false; code.len()];
+    let mut jumps = Vec::new();
+    let mut last_push = None;
+    let mut pc = 0;
+    while pc < code.len() {
+        let opcode = code[pc];
+        match opcode {
+            0x5b => jumpdests[pc] = true,
+            0x56 | 0x57 => {
+                if let Some(target) = last_push {
+                    jumps.push((pc, target));
+                }
+            }
+            _ => {}
+        }
+        last_push = None;
+        if let 0x5f..=0x7f = opcode {
+            let width = (opcode - 0x5f) as usize;
+            let end = (pc + 1 + width).min(code.len());
+            last_push = Some(U256::from_be_slice(&code[pc + 1..end]));
+            pc += width;
+        }
+        pc += 1;
+    }
+
+    for (pc, target) in jumps {
+        let valid = usize::try_from(target).is_ok_and(|t| t < code.len() && jumpdests[t]);
+        ensure!(valid, "Jump at {pc:#x} targets 0x{target:x}, which is not a jumpdest");
+    }
+    Ok(())
+}
+
+/// Parses bytecode back into instructions. Opcodes outside the modeled set become
+/// [`Instruction::Unknown`], and a push cut short by the end of the code becomes
+/// [`Instruction::Truncated`]. A push of a `jumpdest` offset followed by `jump` or `jumpi` is
+/// recovered as a labeled jump, where each label is the program counter of its `jumpdest`.
+///
+/// Reassembling yields the same bytes as long as pushes use the narrowest width, as this crate
+/// emits them.
+pub fn disassemble(code: &[u8]) -> InstructionSeq {
+    use StackInstruction::*;
+    use ControlInstruction::*;
+
+    let mut decoded = Vec::new();
+    let mut pc = 0;
+    while pc < code.len() {
+        let opcode = code[pc];
+        let instr = match opcode {
+            0x5f..=0x7f => {
+                let width = (opcode - 0x5f) as usize;
+                if pc + 1 + width > code.len() {
+                    decoded.push((pc, Instruction::Truncated(code[pc..].into())));
+                    break;
+                }
+                let c = U256::from_be_slice(&code[pc + 1..pc + 1 + width]);
+                pc += width;
+                Instruction::Stack(Push(c.into()))
+            }
+            0x80..=0x8f => Instruction::Stack(Dup((opcode - 0x80) as usize)),
+            0x90..=0x9f => Instruction::Stack(Swap((opcode - 0x8f) as usize)),
+            0x5b => Instruction::Control(Jumpdest(pc)),
+            _ => DataInstruction::from_opcode(opcode).map_or(Instruction::Unknown(opcode), Instruction::Data),
+        };
+        decoded.push((pc, instr));
+        pc += 1;
+    }
+
+    let jumpdests: Vec<usize> = decoded.iter()
+        .filter_map(|(pc, instr)| matches!(instr, Instruction::Control(Jumpdest(_))).then_some(*pc))
+        .collect();
+
+    let mut code = Vec::new();
+    for (_, instr) in decoded {
+        let target = match (&instr, code.last()) {
+            (Instruction::Unknown(0x56 | 0x57), Some(Instruction::Stack(Push(c)))) => {
+                usize::try_from(c.value()).ok().filter(|t| jumpdests.contains(t))
+            }
+            _ => None,
+        };
+        match (instr, target) {
+            (Instruction::Unknown(0x56), Some(label)) => {
+                code.pop();
+                code.push(Instruction::Control(Jump(label)));
+            }
+            (Instruction::Unknown(_), Some(label)) => {
+                code.pop();
+                code.push(Instruction::Control(Jumpi(label)));
+            }
+            (instr, _) => code.push(instr),
+        }
+    }
+    InstructionSeq(code)
+}
+
+/// Displays an instruction or sequence as assembled for a particular EVM version.
+pub struct Versioned<'a, T>(&'a T, EvmVersion);
+
+impl Instruction {
+    pub fn display(&self, version: EvmVersion) -> Versioned<'_, Instruction> {
+        Versioned(self, version)
+    }
+}
+
+impl Display for Instruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.display(EvmVersion::default()).fmt(f)
+    }
+}
+
+impl Display for Versioned<'_, Instruction> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Versioned(instr, version) = *self;
+        let Some(metadata) = instr.metadata(version) else {
+            let Instruction::Unknown(opcode) = instr else { unreachable!() };
+            return write!(f, "unknown 0x{opcode:02x}");
+        };
+        let name = metadata.name;
+        match instr {
+            Instruction::Stack(StackInstruction::Push(c)) if metadata.immediate_size > 0 => write!(f, "{name} {c}"),
+            Instruction::Control(ControlInstruction::Jump(label) | ControlInstruction::Jumpi(label) | ControlInstruction::Jumpdest(label)) => {
+                write!(f, "{name} L{label}")
+            }
+            Instruction::Truncated(bytes) => write!(f, "{name} 0x{} (truncated)", alloy_primitives::hex::encode(&bytes[1..])),
+            Instruction::Verbatim(bytes, takes, returns) => {
+                write!(f, "{name} 0x{} {takes} {returns}", alloy_primitives::hex::encode(bytes))
+            }
+            Instruction::Immutable(immutable) => write!(f, "{name} {immutable}"),
+            _ => write!(f, "{name}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct InstructionSeq(Vec<Instruction>);
+
+impl InstructionSeq {
+    pub fn builder() -> SeqBuilder {
+        SeqBuilder::default()
+    }
+
+    pub fn into_inner(self) -> Vec<Instruction> {
+        self.0
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Instruction> {
+        self.0.iter()
+    }
+
+    pub fn push(&mut self, instr: Instruction) {
+        self.0.push(instr);
+    }
+
+    /// Replaces the instructions in `range` with `replace_with`, returning the ones removed.
+    pub fn splice<R, I>(&mut self, range: R, replace_with: I) -> Vec<Instruction>
+    where
+        R: std::ops::RangeBounds<usize>,
+        I: IntoIterator<Item = Instruction>,
+    {
+        self.0.splice(range, replace_with).collect()
+    }
+
+    pub fn retain(&mut self, f: impl FnMut(&Instruction) -> bool) {
+        self.0.retain(f);
+    }
+}
+
+impl<I: std::slice::SliceIndex<[Instruction]>> std::ops::Index<I> for InstructionSeq {
+    type Output = I::Output;
+
+    fn index(&self, index: I) -> &I::Output {
+        &self.0[index]
+    }
+}
+
+impl<I: std::slice::SliceIndex<[Instruction]>> std::ops::IndexMut<I> for InstructionSeq {
+    fn index_mut(&mut self, index: I) -> &mut I::Output {
+        &mut self.0[index]
+    }
+}
+
+impl IntoIterator for InstructionSeq {
+    type Item = Instruction;
+    type IntoIter = std::vec::IntoIter<Instruction>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a InstructionSeq {
+    type Item = &'a Instruction;
+    type IntoIter = std::slice::Iter<'a, Instruction>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+impl From<Vec<Instruction>> for InstructionSeq {
+    fn from(instructions: Vec<Instruction>) -> Self {
+        InstructionSeq(instructions)
+    }
+}
+
+impl FromIterator<Instruction> for InstructionSeq {
+    fn from_iter<T: IntoIterator<Item = Instruction>>(iter: T) -> Self {
+        InstructionSeq(iter.into_iter().collect())
+    }
+}
+
+impl Extend<Instruction> for InstructionSeq {
+    fn extend<T: IntoIterator<Item = Instruction>>(&mut self, iter: T) {
+        self.0.extend(iter);
+    }
+}
+
+/// Builds an [`InstructionSeq`] one instruction at a time, as in
+/// `InstructionSeq::builder().push(U256::ZERO).dup(0).op(DataInstruction::Add).build()`.
+#[derive(Debug, Clone, Default)]
+pub struct SeqBuilder(Vec<Instruction>);
+
+impl SeqBuilder {
+    pub fn instr(mut self, instr: Instruction) -> Self {
+        self.0.push(instr);
+        self
+    }
+
+    pub fn push(self, value: U256) -> Self {
+        self.instr(Instruction::Stack(StackInstruction::Push(value.into())))
+    }
+
+    pub fn dup(self, depth: usize) -> Self {
+        self.instr(Instruction::Stack(StackInstruction::Dup(depth)))
+    }
+
+    pub fn swap(self, depth: usize) -> Self {
+        self.instr(Instruction::Stack(StackInstruction::Swap(depth)))
+    }
+
+    pub fn op(self, op: DataInstruction) -> Self {
+        self.instr(Instruction::Data(op))
+    }
+
+    pub fn jump(self, label: usize) -> Self {
+        self.instr(Instruction::Control(ControlInstruction::Jump(label)))
+    }
+
+    pub fn jumpi(self, label: usize) -> Self {
+        self.instr(Instruction::Control(ControlInstruction::Jumpi(label)))
+    }
+
+    pub fn jumpdest(self, label: usize) -> Self {
+        self.instr(Instruction::Control(ControlInstruction::Jumpdest(label)))
+    }
+
+    pub fn build(self) -> InstructionSeq {
+        InstructionSeq(self.0)
+    }
+}
+
+/// Number of instructions of each kind in a sequence, as returned by [`InstructionSeq::counts`].
+/// Immutables count as pushes, and jumps as control flow along with the push of their target.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Counts {
+    pub pushes: usize,
+    pub dups: usize,
+    pub swaps: usize,
+    pub pops: usize,
+    /// Loads, stores and copies into memory, including from the code.
+    pub memory: usize,
+    pub control: usize,
+    pub other: usize,
+}
+
+impl InstructionSeq {
+    /// Assigns a program counter to every jump label. Jump targets are pushed with the narrowest
+    /// push that fits, which in turn shifts later offsets, so sizes are recomputed until they settle.
+    /// Offsets only grow from one round to the next, so this terminates.
+    pub fn labels(&self, version: EvmVersion) -> Result<HashMap<usize, usize>> {
+        let mut labels = HashMap::new();
+        loop {
+            let mut next = HashMap::new();
+            let mut pc = 0;
+            for instr in &self.0 {
+                if let Instruction::Control(ControlInstruction::Jumpdest(label)) = instr {
+                    ensure!(next.insert(*label, pc).is_none(), "Duplicate label: {label}");
+                }
+                pc += instr.size(&labels, version);
+            }
+            if next == labels {
+                return Ok(labels);
+            }
+            labels = next;
+        }
+    }
+
+    pub fn to_bytes(&self, version: EvmVersion) -> Result<Vec<u8>> {
+        let labels = self.labels(version)?;
+        let mut out = Vec::new();
         for instr in &self.0 {
-            write!(f, "{instr}\n")?
+            instr.encode(&mut out, &labels, version)?;
+        }
+        Ok(out)
+    }
+
+    /// Size in bytes of the encoded sequence.
+    pub fn size(&self, version: EvmVersion) -> Result<usize> {
+        let labels = self.labels(version)?;
+        Ok(self.0.iter().map(|i| i.size(&labels, version)).sum())
+    }
+
+    /// Gas spent executing every instruction once, as [`Instruction::gas_cost`] prices them.
+    pub fn gas(&self, version: EvmVersion) -> u64 {
+        self.0.iter().map(|i| i.gas_cost(version)).sum()
+    }
+
+    /// Number of instructions in each category of [`Counts`]. Verbatim bytes and unknown opcodes
+    /// count as one other instruction each.
+    pub fn counts(&self) -> Counts {
+        use DataInstruction::*;
+        let mut counts = Counts::default();
+        for instr in &self.0 {
+            let count = match instr {
+                Instruction::Stack(StackInstruction::Push(_)) | Instruction::Immutable(_) => &mut counts.pushes,
+                Instruction::Stack(StackInstruction::Dup(_)) => &mut counts.dups,
+                Instruction::Stack(StackInstruction::Swap(_)) => &mut counts.swaps,
+                Instruction::Data(Pop) => &mut counts.pops,
+                Instruction::Data(Mload | Mstore | Mcopy | Codecopy) => &mut counts.memory,
+                Instruction::Control(_) => &mut counts.control,
+                _ => &mut counts.other,
+            };
+            *count += 1;
+        }
+        counts
+    }
+
+    /// Number of loads and stores addressed by a push of an address in `registers`, such as the
+    /// spill traffic to [`crate::codegen::Code::spill_region`]. Accesses through an address
+    /// computed at runtime, as in `base_on_stack` mode, aren't counted.
+    pub fn register_traffic(&self, registers: Range<U256>) -> usize {
+        use DataInstruction::*;
+        self.0.windows(2)
+            .filter(|w| matches!(w, [Instruction::Stack(StackInstruction::Push(c)), Instruction::Data(Mload | Mstore)] if registers.contains(&c.value())))
+            .count()
+    }
+
+    /// Program counter at which each instruction starts once encoded.
+    pub fn offsets(&self, version: EvmVersion) -> Result<Vec<usize>> {
+        let labels = self.labels(version)?;
+        Ok(self.0.iter()
+            .scan(0, |pc, instr| {
+                let offset = *pc;
+                *pc += instr.size(&labels, version);
+                Some(offset)
+            })
+            .collect())
+    }
+
+    pub fn to_hex(&self, version: EvmVersion) -> Result<String> {
+        Ok(alloy_primitives::hex::encode(self.to_bytes(version)?))
+    }
+
+    /// Source for etk-asm, where jump targets are labels pushed with `%push` and immutables are
+    /// zeroed `push32`s as in the bytecode.
+    pub fn to_etk(&self, version: EvmVersion) -> Result<String> {
+        let mut out = String::new();
+        for instr in &self.0 {
+            match instr {
+                Instruction::Stack(StackInstruction::Push(c)) if c.value() != U256::ZERO || !version.has_push0() => {
+                    let name = instr.metadata(version).unwrap().name;
+                    let bytes = c.value().to_be_bytes_trimmed_vec();
+                    let hex = if bytes.is_empty() { "00".to_string() } else { alloy_primitives::hex::encode(bytes) };
+                    out += &format!("{name} 0x{hex}\n");
+                }
+                Instruction::Control(ControlInstruction::Jump(label)) => out += &format!("%push(L{label})\njump\n"),
+                Instruction::Control(ControlInstruction::Jumpi(label)) => out += &format!("%push(L{label})\njumpi\n"),
+                Instruction::Control(ControlInstruction::Jumpdest(label)) => out += &format!("L{label}:\njumpdest\n"),
+                Instruction::Immutable(_) => out += "push32 0x00\n",
+                Instruction::Stack(_) | Instruction::Data(_) => out += &format!("{}\n", instr.display(version)),
+                Instruction::Unknown(_) | Instruction::Truncated(_) | Instruction::Verbatim(..) => {
+                    return Err(eyre!("{} has no etk equivalent", instr.display(version)));
+                }
+            }
+        }
+        Ok(out)
+    }
+
+    /// Source for geas, the go-ethereum assembler: a `#pragma target` for the EVM version, jumps
+    /// taking their `@label` as an argument, and bytes it has no mnemonic for in `#bytes`.
+    /// Immutables are zeroed `push32`s as in the bytecode, with their names in comments.
+    pub fn to_geas(&self, version: EvmVersion) -> String {
+        let hex = |bytes: &[u8]| alloy_primitives::hex::encode(bytes);
+        let mut out = format!("#pragma target \"{version}\"\n");
+        for instr in &self.0 {
+            match instr {
+                Instruction::Stack(StackInstruction::Push(c)) if c.value() != U256::ZERO || !version.has_push0() => {
+                    let name = instr.metadata(version).unwrap().name;
+                    let bytes = c.value().to_be_bytes_trimmed_vec();
+                    out += &format!("{name} 0x{}\n", if bytes.is_empty() { "00".to_string() } else { hex(&bytes) });
+                }
+                Instruction::Control(ControlInstruction::Jump(label)) => out += &format!("jump @L{label}\n"),
+                Instruction::Control(ControlInstruction::Jumpi(label)) => out += &format!("jumpi @L{label}\n"),
+                Instruction::Control(ControlInstruction::Jumpdest(label)) => out += &format!("L{label}:\njumpdest\n"),
+                Instruction::Immutable(name) => out += &format!("push32 0 ; {name}\n"),
+                Instruction::Verbatim(bytes, ..) | Instruction::Truncated(bytes) => out += &format!("#bytes 0x{}\n", hex(bytes)),
+                Instruction::Unknown(opcode) => out += &format!("#bytes 0x{opcode:02x}\n"),
+                Instruction::Stack(_) | Instruction::Data(_) => out += &format!("{}\n", instr.display(version)),
+            }
+        }
+        out
+    }
+
+    /// Byte ranges of the zeroed slots left for each immutable, to be patched before deployment.
+    pub fn link_references(&self, version: EvmVersion) -> Result<BTreeMap<String, Vec<Range<usize>>>> {
+        let mut references: BTreeMap<String, Vec<Range<usize>>> = BTreeMap::new();
+        for (instr, pc) in self.0.iter().zip(self.offsets(version)?) {
+            if let Instruction::Immutable(name) = instr {
+                references.entry(name.to_string()).or_default().push(pc + 1..pc + 33);
+            }
+        }
+        Ok(references)
+    }
+
+    pub fn display(&self, version: EvmVersion) -> Versioned<'_, InstructionSeq> {
+        Versioned(self, version)
+    }
+
+    /// Disassembles hex-encoded bytecode, with or without a `0x` prefix.
+    pub fn from_hex(hex: &str) -> Result<Self> {
+        Ok(disassemble(&alloy_primitives::hex::decode(hex.trim())?))
+    }
+}
+
+impl Display for InstructionSeq {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.display(EvmVersion::default()).fmt(f)
+    }
+}
+
+/// The alternate form shows jump targets as the program counters their labels resolve to.
+impl Display for Versioned<'_, InstructionSeq> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Versioned(seq, version) = *self;
+        let labels = if f.alternate() { seq.labels(version).ok() } else { None };
+        for instr in &seq.0 {
+            match (instr, &labels) {
+                (Instruction::Control(control), Some(labels)) => {
+                    let (mnemonic, label) = match control {
+                        ControlInstruction::Jump(label) => ("jump", label),
+                        ControlInstruction::Jumpi(label) => ("jumpi", label),
+                        ControlInstruction::Jumpdest(label) => ("jumpdest", label),
+                    };
+                    match labels.get(label) {
+                        Some(pc) => writeln!(f, "{mnemonic} 0x{pc:x}")?,
+                        None => writeln!(f, "{}", instr.display(version))?,
+                    }
+                }
+                _ => writeln!(f, "{}", instr.display(version))?,
+            }
         }
         std::fmt::Result::Ok(())
     }
 }
+
+fn parse_label(label: Option<&str>) -> Result<usize> {
+    let label = label.ok_or_else(|| eyre!("Missing label"))?;
+    label.strip_prefix('L').and_then(|n| n.parse().ok()).ok_or_else(|| eyre!("Invalid label: {label}"))
+}
+
+impl FromStr for Instruction {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        use StackInstruction::*;
+        use ControlInstruction::*;
+
+        let mut words = s.split_whitespace();
+        let mnemonic = words.next().ok_or_else(|| eyre!("Empty instruction"))?;
+        if mnemonic == "verbatim" {
+            let (Some(bytes), Some(takes), Some(returns), None) = (words.next(), words.next(), words.next(), words.next()) else {
+                return Err(eyre!("Expected bytes, inputs and outputs: {s}"));
+            };
+            let bytes = alloy_primitives::hex::decode(bytes)?;
+            return Ok(Instruction::Verbatim(bytes.into(), takes.parse()?, returns.parse()?));
+        }
+        let operand = words.next();
+        let rest: Vec<_> = words.collect();
+        if let (Some(n), Some(bytes), ["(truncated)"]) = (mnemonic.strip_prefix("push"), operand, &rest[..]) {
+            let n: u8 = n.parse().ok().filter(|&n| (1..=32).contains(&n)).ok_or_else(|| eyre!("Unknown instruction: {mnemonic}"))?;
+            let bytes = alloy_primitives::hex::decode(bytes)?;
+            ensure!(bytes.len() < n as usize, "Push not truncated: {s}");
+            return Ok(Instruction::Truncated([0x5f + n].into_iter().chain(bytes).collect()));
+        }
+        ensure!(rest.is_empty(), "Too many operands: {s}");
+
+        let instr = if let Some(n) = mnemonic.strip_prefix("push") {
+            let n: usize = n.parse().map_err(|_| eyre!("Unknown instruction: {mnemonic}"))?;
+            ensure!(n <= 32, "Unknown instruction: {mnemonic}");
+            let c = match operand {
+                Some(c) => c.parse::<U256>().map_err(|_| eyre!("Invalid push value: {c}"))?,
+                None if n == 0 => U256::ZERO,
+                None => return Err(eyre!("Missing push value: {s}")),
+            };
+            ensure!(push_width(&c) <= n, "Push value too wide: {s}");
+            return Ok(Instruction::Stack(Push(c.into())));
+        } else if let Some(n) = mnemonic.strip_prefix("dup").and_then(|n| n.parse::<usize>().ok()) {
+            ensure!((1..=16).contains(&n), "Unknown instruction: {mnemonic}");
+            Instruction::Stack(Dup(n - 1))
+        } else if let Some(n) = mnemonic.strip_prefix("swap").and_then(|n| n.parse::<usize>().ok()) {
+            ensure!((1..=16).contains(&n), "Unknown instruction: {mnemonic}");
+            Instruction::Stack(Swap(n))
+        } else {
+            match mnemonic {
+                "jump" => return Ok(Instruction::Control(Jump(parse_label(operand)?))),
+                "jumpi" => return Ok(Instruction::Control(Jumpi(parse_label(operand)?))),
+                "jumpdest" => return Ok(Instruction::Control(Jumpdest(parse_label(operand)?))),
+                "immutable" => {
+                    let name = operand.ok_or_else(|| eyre!("Missing immutable name: {s}"))?;
+                    return Ok(Instruction::Immutable(name.into()));
+                }
+                "unknown" => {
+                    let opcode = operand.ok_or_else(|| eyre!("Missing opcode: {s}"))?;
+                    let opcode = opcode.strip_prefix("0x").and_then(|o| u8::from_str_radix(o, 16).ok())
+                        .ok_or_else(|| eyre!("Invalid opcode: {opcode}"))?;
+                    return Ok(Instruction::Unknown(opcode));
+                }
+                _ => Instruction::Data(mnemonic.parse()?),
+            }
+        };
+        ensure!(operand.is_none(), "Unexpected operand: {s}");
+        Ok(instr)
+    }
+}
+
+/// Parses one instruction per line in the format of `Display`. Text after `;` is a comment, and a
+/// `jumpdest` without a label gets a fresh one.
+impl FromStr for InstructionSeq {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut code = Vec::new();
+        let mut unlabeled = Vec::new();
+        for (n, line) in s.lines().enumerate() {
+            let line = line.split(';').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            if line == "jumpdest" {
+                unlabeled.push(code.len());
+                code.push(Instruction::Control(ControlInstruction::Jumpdest(0)));
+                continue;
+            }
+            code.push(line.parse().map_err(|e: Report| e.wrap_err(format!("Line {}", n + 1)))?);
+        }
+
+        let fresh = code.iter()
+            .filter_map(|instr| match instr {
+                Instruction::Control(ControlInstruction::Jump(l) | ControlInstruction::Jumpi(l) | ControlInstruction::Jumpdest(l)) => Some(l + 1),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0);
+        for (index, label) in unlabeled.into_iter().zip(fresh..) {
+            code[index] = Instruction::Control(ControlInstruction::Jumpdest(label));
+        }
+
+        Ok(InstructionSeq(code))
+    }
+}
@@ -0,0 +1,158 @@
+//! Assembly with the reason for each instruction: the operation or constant of a statement, a
+//! shuffle of its operands, or the memory traffic of a spill or restore, named after the variables
+//! involved as the [`Tracker`] follows them.
+
+use std::collections::VecDeque;
+
+use alloy_primitives::U256;
+
+use crate::codegen::SpillEvent;
+use crate::evm::{DataInstruction, Instruction, StackInstruction};
+use crate::program::{Expression, Statement};
+use crate::tracker::{Slot, Tracker};
+use crate::Output;
+
+struct Explainer<'a> {
+    output: &'a Output,
+    /// Spills and restores not yet matched to the store or load that moves the value.
+    events: VecDeque<&'a SpillEvent>,
+    /// Index of the last load from a register.
+    last_load: Option<usize>,
+}
+
+impl Explainer<'_> {
+    /// The register at `address`, if it is one.
+    fn register(&self, address: Option<U256>) -> Option<usize> {
+        let code = &self.output.code;
+        let offset = address.filter(|a| code.spill_region().contains(a))? - code.spill_base;
+        let (register, rest) = offset.div_rem(U256::from(32));
+        if rest.is_zero() { usize::try_from(register).ok() } else { None }
+    }
+
+    /// Takes the pending spill or restore of `name` to or from `register` in `statement`.
+    fn event(&mut self, statement: usize, name: Option<&str>, register: usize, restore: bool) -> bool {
+        let program = &self.output.program;
+        let position = self.events.iter().position(|e| {
+            e.statement == statement && e.register == register && e.restore == restore && Some(program.name(e.var)) == name
+        });
+        position.and_then(|i| self.events.remove(i)).is_some()
+    }
+
+    fn reason(&mut self, tracker: &Tracker, index: usize, statement: usize) -> String {
+        use DataInstruction::*;
+
+        let code = &self.output.code;
+        let instructions = &code.instructions;
+        let Statement(ress, e) = &self.output.program.block.0[statement];
+        let defined = || ress.iter().map(|&r| self.output.program.name(r)).collect::<Vec<_>>().join(", ");
+        let slot = |depth: usize| tracker.stack.len().checked_sub(depth + 1).map(|i| &tracker.stack[i]);
+        let name = |depth: usize| slot(depth).map_or("?".to_string(), |s| s.name.clone().unwrap_or_else(|| s.show(None)));
+        let addressing = |i: usize| matches!(instructions.get(i), Some(Instruction::Data(Mload | Mstore)));
+        let constant = |depth: usize| slot(depth).and_then(|s| s.constant);
+        // Addresses the program computes are named after its variables, those of registers aren't,
+        // except the spill base itself.
+        let register = self.register(constant(0).filter(|_| slot(0).is_some_and(|s| s.name.as_deref().is_none_or(|n| n == "spill_base"))));
+
+        match &instructions[index] {
+            Instruction::Stack(StackInstruction::Push(_)) if index == 0 && code.base_on_stack => "spill base".to_string(),
+            Instruction::Stack(StackInstruction::Push(c)) => {
+                if addressing(index + 1) {
+                    if let Some(register) = self.register(Some(c.value())) {
+                        return format!("address of register {register}");
+                    }
+                }
+                if matches!(instructions.get(index + 1), Some(Instruction::Data(Add))) && addressing(index + 2) {
+                    let address = constant(0).map(|base| base.wrapping_add(c.value()));
+                    if let Some(register) = self.register(address) {
+                        return format!("offset of register {register} from the spill base");
+                    }
+                }
+                match e {
+                    Expression::Const(_) => format!("constant {}", defined()),
+                    _ => "constant".to_string(),
+                }
+            }
+            Instruction::Immutable(name) => match e {
+                Expression::Immutable(_) => format!("immutable {}", defined()),
+                Expression::Const(_) => format!("memory offset {}, relocated", defined()),
+                _ => format!("immutable {name}"),
+            },
+            Instruction::Stack(StackInstruction::Dup(depth)) => {
+                let source = name(*depth);
+                if source == "spill_base" {
+                    "copy of the spill base, to address a register".to_string()
+                } else {
+                    format!("copy {source} for statement {statement}")
+                }
+            }
+            Instruction::Stack(StackInstruction::Swap(depth)) => {
+                if self.last_load.is_some_and(|i| i + 1 == index) {
+                    format!("exchange {} with {} in the stack", name(0), name(*depth))
+                } else {
+                    format!("shuffle for statement {statement}: {} with {}", name(0), name(*depth))
+                }
+            }
+            Instruction::Data(Add) if addressing(index + 1) => match self.register(constant(0).zip(constant(1)).map(|(a, b)| a.wrapping_add(b))) {
+                Some(register) => format!("address of register {register}"),
+                None => format!("operation of statement {statement}"),
+            },
+            Instruction::Data(Mload) if register.is_some() => {
+                let register = register.unwrap();
+                self.last_load = Some(index);
+                let value = constant(0).and_then(|a| tracker.memory.get(&a)).cloned().unwrap_or_default();
+                match &value.name {
+                    Some(name) if self.event(statement, Some(name), register, true) => format!("restore {name} from register {register}"),
+                    Some(name) => format!("load {name} from register {register}"),
+                    None if value.constant.is_some() => format!("load constant {} from register {register}", value.show(None)),
+                    None => format!("load register {register}"),
+                }
+            }
+            Instruction::Data(Mstore) if register.is_some() => {
+                let register = register.unwrap();
+                let value: Slot = slot(1).cloned().unwrap_or_default();
+                match &value.name {
+                    Some(name) if self.event(statement, Some(name), register, false) => format!("spill {name} to register {register}"),
+                    Some(name) => format!("store {name} to register {register}"),
+                    None if value.constant.is_some() => format!("store constant {} to register {register}", value.show(None)),
+                    None => format!("store to register {register}"),
+                }
+            }
+            Instruction::Data(Pop) => format!("pop {}, no longer used", name(0)),
+            Instruction::Data(Codecopy) if !matches!(e, Expression::Op(op, _) if op == "codecopy") => "copy from the constant pool".to_string(),
+            Instruction::Data(_) | Instruction::Verbatim(..) => match e {
+                Expression::Op(..) | Expression::Verbatim(..) if !ress.is_empty() => format!("operation of statement {statement}, defining {}", defined()),
+                Expression::Op(..) | Expression::Verbatim(..) => format!("operation of statement {statement}"),
+                _ => "operation".to_string(),
+            },
+            Instruction::Control(_) => "control flow".to_string(),
+            Instruction::Unknown(_) | Instruction::Truncated(_) => "unknown".to_string(),
+        }
+    }
+}
+
+/// The assembly of `output` grouped under its statements like [`Output::assembly`], with the reason
+/// for each instruction in a comment.
+pub fn render(output: &Output) -> String {
+    let code = &output.code;
+    let mut explainer = Explainer { output, events: code.spills.iter().collect(), last_load: None };
+    let mut tracker = Tracker::new(output);
+    let lines: Vec<_> = code.instructions.iter().zip(&code.origins).enumerate()
+        .map(|(i, (instr, &statement))| {
+            let reason = explainer.reason(&tracker, i, statement);
+            tracker.step(instr, statement);
+            (instr.display(code.evm_version).to_string(), statement, reason)
+        })
+        .collect();
+
+    let width = lines.iter().map(|(instr, ..)| instr.len()).max().unwrap_or(0).min(24);
+    let mut out = String::new();
+    let mut last = None;
+    for (instr, statement, reason) in lines {
+        if last != Some(statement) {
+            out += &format!("; {}\n", output.program.named_statement(statement));
+            last = Some(statement);
+        }
+        out += &format!("{instr:<width$} ; {reason}\n");
+    }
+    out
+}
@@ -0,0 +1,234 @@
+use std::collections::HashMap;
+
+use alloy_primitives::U256;
+use eyre::{bail, ensure, eyre, Result};
+
+use crate::evm::{ControlInstruction, DataInstruction, Instruction, StackInstruction};
+
+/// Reference interpreter for straight-line code, executing one instruction at a time, or for code
+/// with jumps through [`Interpreter::run`].
+///
+/// Immutables read their values from `immutables`, or zero if missing, and so do the opcodes that
+/// read the environment from `environment` by their names. `gas` is one of them, since gas isn't
+/// metered here.
+pub struct Interpreter {
+    pub stack: Vec<U256>,
+    pub immutables: HashMap<String, U256>,
+    pub environment: HashMap<String, U256>,
+    /// Input read by `calldataload`, zero past its end.
+    pub calldata: Vec<u8>,
+    pub memory: Vec<u8>,
+    pub transient: HashMap<U256, U256>,
+    /// Topics and data of each log emitted, in order.
+    pub logs: Vec<(Vec<U256>, Vec<u8>)>,
+    /// Data returned by `return` or `revert`, once the code has halted through it.
+    pub output: Vec<u8>,
+    pub halted: bool,
+    pub reverted: bool,
+    code: Vec<u8>,
+}
+
+fn usize_of(value: U256) -> Result<usize> {
+    value.try_into().map_err(|_| eyre!("offset {value} out of range"))
+}
+
+impl Interpreter {
+    /// An interpreter whose `codecopy` reads from `code`.
+    pub fn new(code: Vec<u8>) -> Interpreter {
+        Interpreter {
+            stack: Vec::new(),
+            immutables: HashMap::new(),
+            environment: HashMap::new(),
+            calldata: Vec::new(),
+            memory: Vec::new(),
+            transient: HashMap::new(),
+            logs: Vec::new(),
+            output: Vec::new(),
+            halted: false,
+            reverted: false,
+            code,
+        }
+    }
+
+    /// Reads the 32-byte word at `offset`, zero where memory hasn't been written.
+    pub fn mload(&self, offset: usize) -> U256 {
+        let mut word = [0; 32];
+        for (i, byte) in word.iter_mut().enumerate() {
+            *byte = self.memory.get(offset + i).copied().unwrap_or(0);
+        }
+        U256::from_be_bytes(word)
+    }
+
+    fn expand(&mut self, offset: usize, size: usize) {
+        if size > 0 && offset + size > self.memory.len() {
+            self.memory.resize((offset + size).div_ceil(32) * 32, 0);
+        }
+    }
+
+    fn pop(&mut self) -> Result<U256> {
+        self.stack.pop().ok_or_else(|| eyre!("stack underflow"))
+    }
+
+    /// Executes `code` from the start until it halts or runs past the end, following each taken
+    /// jump to the `jumpdest` of its label. Gas isn't metered, so code that loops never returns.
+    pub fn run(&mut self, code: &[Instruction]) -> Result<()> {
+        let labels: HashMap<usize, usize> = code.iter().enumerate()
+            .filter_map(|(i, instr)| match instr {
+                Instruction::Control(ControlInstruction::Jumpdest(label)) => Some((*label, i)),
+                _ => None,
+            })
+            .collect();
+        let mut pc = 0;
+        while let Some(instr) = code.get(pc).filter(|_| !self.halted) {
+            pc += 1;
+            let target = match instr {
+                Instruction::Control(ControlInstruction::Jump(label)) => Some(*label),
+                Instruction::Control(ControlInstruction::Jumpi(label)) => (!self.pop()?.is_zero()).then_some(*label),
+                _ => {
+                    self.step(instr)?;
+                    None
+                }
+            };
+            if let Some(label) = target {
+                pc = *labels.get(&label).ok_or_else(|| eyre!("unknown label {label}"))?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn step(&mut self, instr: &Instruction) -> Result<()> {
+        use DataInstruction::*;
+        use StackInstruction::*;
+
+        ensure!(!self.halted, "execution already halted");
+
+        let len = self.stack.len();
+        match instr {
+            Instruction::Stack(Push(c)) => self.stack.push(c.value()),
+
+            Instruction::Stack(Dup(depth)) => {
+                ensure!(*depth < len, "stack underflow at {instr}");
+                self.stack.push(self.stack[len - 1 - depth]);
+            }
+
+            Instruction::Stack(Swap(depth)) => {
+                ensure!(*depth < len, "stack underflow at {instr}");
+                self.stack.swap(len - 1, len - 1 - depth);
+            }
+
+            Instruction::Data(Stop) => self.halted = true,
+
+            Instruction::Data(Add) => {
+                let (a, b) = (self.pop()?, self.pop()?);
+                self.stack.push(a.wrapping_add(b));
+            }
+
+            Instruction::Data(Mul) => {
+                let (a, b) = (self.pop()?, self.pop()?);
+                self.stack.push(a.wrapping_mul(b));
+            }
+
+            Instruction::Data(Eq) => {
+                let (a, b) = (self.pop()?, self.pop()?);
+                self.stack.push(U256::from(a == b));
+            }
+
+            Instruction::Data(Iszero) => {
+                let a = self.pop()?;
+                self.stack.push(U256::from(a.is_zero()));
+            }
+
+            Instruction::Data(Xor) => {
+                let (a, b) = (self.pop()?, self.pop()?);
+                self.stack.push(a ^ b);
+            }
+
+            Instruction::Data(Shr) => {
+                let (shift, value) = (self.pop()?, self.pop()?);
+                self.stack.push(value.checked_shr(usize_of(shift).unwrap_or(usize::MAX)).unwrap_or_default());
+            }
+
+            Instruction::Data(Calldataload) => {
+                let offset = self.pop()?;
+                let mut word = [0; 32];
+                if let Ok(offset) = usize_of(offset) {
+                    for (i, byte) in word.iter_mut().enumerate() {
+                        *byte = self.calldata.get(offset.saturating_add(i)).copied().unwrap_or(0);
+                    }
+                }
+                self.stack.push(U256::from_be_bytes(word));
+            }
+
+            Instruction::Data(
+                op @ (Address | Origin | Caller | Callvalue | Calldatasize | Codesize | Gasprice | Returndatasize | Coinbase
+                | Timestamp | Number | Prevrandao | Gaslimit | Chainid | Selfbalance | Basefee | Blobbasefee | Gas),
+            ) => self.stack.push(self.environment.get(op.name()).copied().unwrap_or_default()),
+
+            // Counts the spill registers too, so it can differ between a program and its code.
+            Instruction::Data(Msize) => self.stack.push(U256::from(self.memory.len())),
+
+            Instruction::Data(Pop) => {
+                self.pop()?;
+            }
+
+            Instruction::Data(Mload) => {
+                let offset = usize_of(self.pop()?)?;
+                self.expand(offset, 32);
+                self.stack.push(self.mload(offset));
+            }
+
+            Instruction::Data(Mstore) => {
+                let (offset, value) = (usize_of(self.pop()?)?, self.pop()?);
+                self.expand(offset, 32);
+                self.memory[offset..offset + 32].copy_from_slice(&value.to_be_bytes::<32>());
+            }
+
+            Instruction::Data(Tload) => {
+                let key = self.pop()?;
+                self.stack.push(self.transient.get(&key).copied().unwrap_or_default());
+            }
+
+            Instruction::Data(Tstore) => {
+                let (key, value) = (self.pop()?, self.pop()?);
+                self.transient.insert(key, value);
+            }
+
+            Instruction::Data(Codecopy) => {
+                let (dest, offset, size) = (usize_of(self.pop()?)?, usize_of(self.pop()?)?, usize_of(self.pop()?)?);
+                self.expand(dest, size);
+                for i in 0..size {
+                    self.memory[dest + i] = self.code.get(offset + i).copied().unwrap_or(0);
+                }
+            }
+
+            Instruction::Data(Mcopy) => {
+                let (dest, src, size) = (usize_of(self.pop()?)?, usize_of(self.pop()?)?, usize_of(self.pop()?)?);
+                self.expand(dest.max(src), size);
+                self.memory.copy_within(src..src + size, dest);
+            }
+
+            Instruction::Data(op @ (Log0 | Log1 | Log2 | Log3 | Log4)) => {
+                let (offset, size) = (usize_of(self.pop()?)?, usize_of(self.pop()?)?);
+                let topics = (0..op.arity().0 - 2).map(|_| self.pop()).collect::<Result<_>>()?;
+                self.expand(offset, size);
+                self.logs.push((topics, self.memory[offset..offset + size].to_vec()));
+            }
+
+            Instruction::Data(op @ (Return | Revert)) => {
+                let (offset, size) = (usize_of(self.pop()?)?, usize_of(self.pop()?)?);
+                self.expand(offset, size);
+                self.output = self.memory[offset..offset + size].to_vec();
+                self.halted = true;
+                self.reverted = matches!(op, Revert);
+            }
+
+            Instruction::Immutable(name) => self.stack.push(self.immutables.get(&**name).copied().unwrap_or_default()),
+
+            Instruction::Control(ControlInstruction::Jumpdest(_)) => (),
+
+            _ => bail!("cannot interpret {instr}"),
+        }
+
+        Ok(())
+    }
+}
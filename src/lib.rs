@@ -0,0 +1,424 @@
+#![allow(unused)]
+
+//! Compiler from a small straight-line language to EVM code, scheduling the stack and spilling
+//! values to memory when they fall out of reach.
+
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use eyre::Result;
+use serde_json::json;
+
+use crate::codegen::{Annotated, Code};
+use crate::evm::InstructionSeq;
+use crate::program::{Block, Expression, Interner, Span, Statement, Symbol};
+use crate::scope::ResolvedBlock;
+
+pub mod program;
+pub mod parser;
+pub mod yul;
+pub mod asm_json;
+pub mod scope;
+pub mod analysis;
+pub mod codegen;
+pub mod dispatch;
+pub mod peephole;
+mod pool;
+mod treeify;
+pub mod evm;
+pub mod eof;
+pub mod metadata;
+pub mod interpreter;
+pub mod equivalence;
+pub mod tracker;
+pub mod ethdebug;
+pub mod explain;
+pub mod stress;
+pub mod cache;
+#[cfg(feature = "smt")]
+pub mod smt;
+pub mod timeline;
+pub mod dot;
+#[cfg(feature = "proptest")]
+pub mod arbitrary;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+/// Failure in one of the stages of `compile`.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Parse(#[from] parser::ParseError),
+    #[error(transparent)]
+    Resolve(#[from] scope::ResolveError),
+    #[error(transparent)]
+    Codegen(#[from] codegen::CodegenError),
+    #[error(transparent)]
+    Limit(#[from] LimitError),
+    #[error(transparent)]
+    Dispatch(#[from] dispatch::DispatchError),
+    /// Encoding the instructions, or checking the jumps in the result.
+    #[error("{0:#}")]
+    Assemble(eyre::Report),
+}
+
+/// Bounds on what a compilation may use, for compiling untrusted input. Each is unbounded if `None`.
+#[derive(Clone, Debug, Default)]
+pub struct Limits {
+    pub statements: Option<usize>,
+    /// Variables live at once, which bounds the stack and the spill registers needed.
+    pub live_variables: Option<usize>,
+    pub registers: Option<usize>,
+    pub bytecode_size: Option<usize>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Resource {
+    Statements,
+    LiveVariables,
+    Registers,
+    BytecodeSize,
+}
+
+impl std::fmt::Display for Resource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Resource::Statements => "statements",
+            Resource::LiveVariables => "live variables",
+            Resource::Registers => "spill registers",
+            Resource::BytecodeSize => "bytecode size",
+        })
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Limit on {resource} exceeded: {found}, at most {limit} allowed")]
+pub struct LimitError {
+    pub resource: Resource,
+    pub limit: usize,
+    pub found: usize,
+}
+
+fn check_limit(resource: Resource, limit: Option<usize>, found: usize) -> Result<(), LimitError> {
+    match limit {
+        Some(limit) if found > limit => Err(LimitError { resource, limit, found }),
+        _ => Ok(()),
+    }
+}
+
+/// Language of the source text.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Syntax {
+    #[default]
+    Dsl,
+    /// The straight-line subset of Yul read by [`yul::parse`].
+    Yul,
+}
+
+impl Syntax {
+    pub fn parse(self, source: &str) -> Result<Block<String>, parser::ParseError> {
+        match self {
+            Syntax::Dsl => parser::parse(source),
+            Syntax::Yul => yul::parse(source),
+        }
+    }
+
+    /// Parses `source` with its variable names interned in `interner`.
+    pub fn parse_interned(self, source: &str, interner: &mut Interner) -> Result<Block<Symbol>, parser::ParseError> {
+        match self {
+            Syntax::Dsl => parser::parse_interned(source, interner),
+            Syntax::Yul => Ok(yul::parse(source)?.map(|name| interner.intern(&name))),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Options {
+    pub syntax: Syntax,
+    pub codegen: codegen::Options,
+    /// Append a metadata trailer identifying the compiler and the source to the bytecode.
+    pub metadata: bool,
+    pub limits: Limits,
+}
+
+/// A likely mistake in a program that compiles anyway.
+#[derive(Debug)]
+pub struct Warning {
+    pub statement: usize,
+    pub span: Option<Span>,
+    pub message: String,
+}
+
+/// The values live across a statement, and how many of them it spilled.
+#[derive(Debug)]
+pub struct Pressure {
+    pub statement: usize,
+    pub live: usize,
+    pub spills: usize,
+}
+
+/// A spill with what it costs and the variables it made room for.
+#[derive(Debug)]
+pub struct SpillCause {
+    pub spill: codegen::SpillEvent,
+    /// Restores from the register of this spill, before another spill to it.
+    pub restores: usize,
+    /// Gas of the spill and of its restores.
+    pub gas: u64,
+    /// The variables defined after the spilled one and live at its next use, which kept it out of
+    /// reach.
+    pub live: Vec<scope::Var>,
+}
+
+pub struct Output {
+    pub program: ResolvedBlock,
+    pub code: Code,
+    /// Runtime bytecode.
+    pub bytecode: Vec<u8>,
+}
+
+impl Output {
+    /// The generated instructions, grouped under the statements they were generated for.
+    pub fn assembly(&self) -> Annotated<'_> {
+        self.code.annotated(&self.program)
+    }
+
+    /// Code that deploys the runtime bytecode.
+    pub fn init_code(&self) -> Result<Vec<u8>> {
+        let version = self.code.evm_version;
+        let mut code = evm::constructor(self.bytecode.len(), version).to_bytes(version)?;
+        code.extend(&self.bytecode);
+        Ok(code)
+    }
+
+    /// Statements whose results are all unused, and which do nothing else.
+    pub fn warnings(&self) -> Vec<Warning> {
+        let program = &self.program;
+        analysis::unused_results(program).into_iter()
+            .map(|statement| {
+                let Statement(vs, e) = &program.block.0[statement];
+                let names: Vec<_> = vs.iter().map(|&v| program.name(v)).collect();
+                let what = match e {
+                    Expression::Op(op, _) => op.as_str(),
+                    Expression::Immutable(_) => "immutable",
+                    _ => "const",
+                };
+                let message = format!("Unused result of {what}: {}", names.join(", "));
+                Warning { statement, span: program.block.1.get(statement).cloned(), message }
+            })
+            .collect()
+    }
+
+    /// Each statement with the values live across it and the spills after it, from the highest
+    /// pressure down.
+    pub fn pressure(&self) -> Vec<Pressure> {
+        let mut pressure: Vec<_> = analysis::live_counts(&self.program).into_iter().enumerate()
+            .map(|(statement, live)| {
+                let spills = self.code.spills.iter().filter(|s| s.statement == statement && !s.restore).count();
+                Pressure { statement, live, spills }
+            })
+            .collect();
+        pressure.sort_by_key(|p| (std::cmp::Reverse(p.live), std::cmp::Reverse(p.spills)));
+        pressure
+    }
+
+    /// Each spill, in order, with its restores and the variables live alongside it.
+    pub fn spill_causes(&self) -> Vec<SpillCause> {
+        let ranges = analysis::live_ranges(&self.program);
+        let spills = &self.code.spills;
+        spills.iter().enumerate()
+            .filter(|(_, s)| !s.restore)
+            .map(|(i, &spill)| {
+                let restores: Vec<_> = spills[i + 1..].iter()
+                    .filter(|s| s.register == spill.register)
+                    .take_while(|s| s.restore)
+                    .collect();
+                // What was pushed after the value and is still live at its next use buried it.
+                let var = spill.var.index();
+                let defined = ranges.get(var).cloned().flatten().map_or(0, |r| r.start);
+                let used = self.program.flat.iter().enumerate().skip(spill.statement + 1)
+                    .find(|(_, (_, args))| args.contains(&spill.var))
+                    .map_or(spill.statement, |(i, _)| i);
+                let live = ranges.iter().enumerate()
+                    .filter(|(v, r)| *v != var && r.as_ref().is_some_and(|r| defined <= r.start && r.start < used && used <= r.end))
+                    .map(|(v, _)| scope::Var::new(v))
+                    .collect();
+                SpillCause { spill, restores: restores.len(), gas: spill.gas + restores.iter().map(|s| s.gas).sum::<u64>(), live }
+            })
+            .collect()
+    }
+
+    /// Gas spent executing every instruction once.
+    pub fn gas(&self) -> u64 {
+        self.code.instructions.iter().map(|i| i.gas_cost(self.code.evm_version)).sum()
+    }
+
+    /// HTML page plotting the stack depth and spills over the instructions.
+    pub fn timeline(&self) -> String {
+        timeline::render(&self.code, &self.program)
+    }
+
+    pub fn source_map(&self) -> Option<String> {
+        self.code.source_map(&self.program)
+    }
+
+    /// The source of each statement followed by the instructions generated for it, with their
+    /// offsets and bytes, like `objdump -S`. A statement without instructions of its own is listed
+    /// along with the next one that has some.
+    pub fn listing(&self, source: &str) -> Result<String> {
+        let version = self.code.evm_version;
+        let offsets = InstructionSeq::from(self.code.instructions.clone()).offsets(version)?;
+        let spans = &self.program.block.1;
+        let statement = |i: usize| match spans.get(i).and_then(|span| source.get(span.clone())) {
+            Some(text) => text.lines().map(|line| format!("; {}\n", line.trim())).collect(),
+            None => format!("; {}\n", self.program.named_statement(i)),
+        };
+
+        let mut out = String::new();
+        let mut next = 0;
+        let mut last = None;
+        for (i, (instr, &origin)) in self.code.instructions.iter().zip(&self.code.origins).enumerate() {
+            if last != Some(origin) {
+                for s in next..=origin {
+                    out += &statement(s);
+                }
+                next = next.max(origin + 1);
+                last = Some(origin);
+            }
+            let end = offsets.get(i + 1).copied().unwrap_or(self.bytecode.len()).min(self.bytecode.len());
+            let bytes = alloy_primitives::hex::encode(&self.bytecode[offsets[i]..end]);
+            out += &format!("{:04x}  {bytes:<12} {}\n", offsets[i], instr.display(version));
+        }
+        for s in next..self.program.block.0.len() {
+            out += &statement(s);
+        }
+        Ok(out)
+    }
+
+    pub fn link_references(&self) -> Result<BTreeMap<String, Vec<Range<usize>>>> {
+        InstructionSeq::from(self.code.instructions.clone()).link_references(self.code.evm_version)
+    }
+
+    /// Artifact in the layout forge writes to `out/`, so that forge and cast can deploy the code.
+    /// There is no ABI, and immutables are listed by name under `immutableReferences`.
+    pub fn foundry_artifact(&self, source: &str) -> Result<serde_json::Value> {
+        use alloy_primitives::hex;
+        let immutables: BTreeMap<_, Vec<_>> = self.link_references()?.into_iter()
+            .map(|(name, ranges)| (name, ranges.iter().map(|r| json!({ "start": r.start, "length": r.len() })).collect()))
+            .collect();
+        let metadata = json!({
+            "compiler": { "version": env!("CARGO_PKG_VERSION") },
+            "language": env!("CARGO_PKG_NAME"),
+            "output": { "abi": [], "devdoc": {}, "userdoc": {} },
+            "settings": { "evmVersion": self.code.evm_version.to_string() },
+            "sources": { "source": { "keccak256": alloy_primitives::keccak256(source).to_string() } },
+            "version": 1,
+        });
+        Ok(json!({
+            "abi": [],
+            "bytecode": { "object": hex::encode_prefixed(self.init_code()?), "sourceMap": "", "linkReferences": {} },
+            "deployedBytecode": {
+                "object": hex::encode_prefixed(&self.bytecode),
+                "sourceMap": self.source_map().unwrap_or_default(),
+                "linkReferences": {},
+                "immutableReferences": immutables,
+            },
+            "methodIdentifiers": {},
+            "rawMetadata": metadata.to_string(),
+            "metadata": metadata,
+        }))
+    }
+}
+
+pub fn compile(source: &str, options: &Options) -> Result<Output, Error> {
+    compile_with(source, options, |program| codegen::generate(program, &options.codegen))
+}
+
+/// The stages of `compile`, with code generation done by `generate`.
+pub(crate) fn compile_with(
+    source: &str,
+    options: &Options,
+    generate: impl FnOnce(&ResolvedBlock) -> Result<Code, codegen::CodegenError>,
+) -> Result<Output, Error> {
+    let mut interner = Interner::default();
+    let block = options.syntax.parse_interned(source, &mut interner)?;
+    let mut output = compile_block(block, &interner, options, generate)?;
+    if options.metadata {
+        metadata::append(&mut output.bytecode, source);
+    }
+    check_limit(Resource::BytecodeSize, options.limits.bytecode_size, output.bytecode.len())?;
+    Ok(output)
+}
+
+/// The stages of `compile` after parsing, up to the bytecode without metadata.
+pub(crate) fn compile_block(
+    block: Block<Symbol>,
+    interner: &Interner,
+    options: &Options,
+    generate: impl FnOnce(&ResolvedBlock) -> Result<Code, codegen::CodegenError>,
+) -> Result<Output, Error> {
+    let limits = &options.limits;
+    check_limit(Resource::Statements, limits.statements, block.0.len())?;
+    let mut program = scope::resolve_interned(block, interner)?;
+    if options.codegen.relocate_memory && !options.codegen.base_on_stack {
+        program = analysis::split_offsets(program)?;
+    }
+    if limits.live_variables.is_some() {
+        check_limit(Resource::LiveVariables, limits.live_variables, analysis::max_live(&program))?;
+    }
+    let code = generate(&program)?;
+    check_limit(Resource::Registers, limits.registers, code.register_count)?;
+
+    let bytecode = InstructionSeq::from(code.instructions.clone()).to_bytes(code.evm_version).map_err(Error::Assemble)?;
+    evm::verify_jumps(&bytecode).map_err(Error::Assemble)?;
+    Ok(Output { program, code, bytecode })
+}
+
+/// Compiles independent programs, each through all the stages of [`compile`], returning the
+/// results in the order of `sources`. With the `parallel` feature the programs are spread over
+/// rayon's thread pool.
+pub fn compile_all<S: AsRef<str> + Sync>(sources: &[S], options: &Options) -> Vec<Result<Output, Error>> {
+    #[cfg(feature = "parallel")]
+    {
+        use rayon::prelude::*;
+        sources.par_iter().map(|source| compile(source.as_ref(), options)).collect()
+    }
+    #[cfg(not(feature = "parallel"))]
+    sources.iter().map(|source| compile(source.as_ref(), options)).collect()
+}
+
+/// Compiles `source` into a JSON document with the instructions, bytecode, register count, spills
+/// and warnings under `diagnostics`, or with the error there instead.
+pub fn compile_json(source: &str, options: &Options) -> serde_json::Value {
+    let output = match compile(source, options) {
+        Ok(output) => output,
+        Err(e) => return json!({ "diagnostics": [{ "severity": "error", "message": format!("{e:#}") }] }),
+    };
+    let code = &output.code;
+    let instructions: Vec<_> = code.instructions.iter().zip(&code.origins)
+        .map(|(instr, origin)| json!({
+            "instruction": instr.display(code.evm_version).to_string(),
+            "statement": origin,
+        }))
+        .collect();
+    let spills: Vec<_> = code.spills.iter()
+        .map(|s| json!({
+            "statement": s.statement,
+            "variable": output.program.name(s.var),
+            "register": s.register,
+            "restore": s.restore,
+            "gas": s.gas,
+        }))
+        .collect();
+    let warnings: Vec<_> = output.warnings().into_iter()
+        .map(|w| json!({ "severity": "warning", "message": w.message, "statement": w.statement }))
+        .collect();
+    json!({
+        "instructions": instructions,
+        "bytecode": alloy_primitives::hex::encode_prefixed(&output.bytecode),
+        "registerCount": code.register_count,
+        "spillBytes": code.spill_size(),
+        "spills": spills,
+        "diagnostics": warnings,
+    })
+}
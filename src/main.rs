@@ -1,56 +1,886 @@
-#![allow(unused)]
-
-use crate::evm::InstructionSeq;
-
-mod program;
-mod parser;
-mod scope;
-mod analysis;
-mod codegen;
-mod evm;
-
-fn main() {
-    let input = "
-        let p00 = const 10;
-        let p01 = const 11;
-        let p02 = const 12;
-        let p03 = const 13;
-        let p04 = const 14;
-        let p05 = const 15;
-        let p06 = const 16;
-        let p07 = const 17;
-        let p08 = const 18;
-        let p09 = const 19;
-        let p10 = const 20;
-        let p11 = const 21;
-        let p12 = const 22;
-        let p13 = const 23;
-        let p14 = const 24;
-        let p15 = const 25;
-        let p16 = const 26;
-        let y = mload p00;
-        pop p15;
-        pop p14;
-        pop p13;
-        pop p12;
-        pop p11;
-        pop p10;
-        pop p09;
-        pop p08;
-        pop p07;
-        pop p06;
-        pop p05;
-        pop p04;
-        pop p03;
-        pop p02;
-        pop p01;
-        pop p16;
-    ";
-
-    let ast = parser::parse(input).unwrap();
-    let rblock = scope::resolve(ast).unwrap();
-    let code = codegen::generate(&rblock).unwrap();
-    let code = InstructionSeq(code.map(|i| i.into()).collect());
-
-    println!("{code}");
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use alloy_primitives::U256;
+use clap::{ArgAction, Args, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use eyre::Result;
+use tracing::Level;
+
+use evm_spilling::evm::{self, EvmVersion, InstructionSeq};
+use evm_spilling::cache::Cache;
+use evm_spilling::codegen::Code;
+use evm_spilling::{analysis, codegen, compile, compile_json, dispatch, dot, parser, scope, stress, Options, Output, Syntax};
+
+mod config;
+mod debugger;
+#[cfg(feature = "solc")]
+mod solc;
+
+#[derive(Clone, Copy, Default, PartialEq, ValueEnum)]
+enum Stage {
+    /// Tokens from the lexer.
+    Tokens,
+    /// Syntax tree from the parser.
+    Ast,
+    /// Program with variables resolved.
+    Resolved,
+    /// Pre-instructions from the stack scheduler, with the spills decided after each.
+    Pre,
+    /// Final assembly.
+    #[default]
+    Asm,
+    /// Hex bytecode.
+    Bin,
+    /// Hex bytecode with a `0x` prefix and no trailing newline, for `cast` and JSON fixtures. With
+    /// `--deploy`, the init code.
+    Hex,
+    /// Source for the etk assembler.
+    Etk,
+    /// Source for the geas assembler.
+    Geas,
+    /// HTML page plotting the stack depth and spills over the instructions.
+    Timeline,
+    /// Control flow graph of the generated code, in Graphviz DOT.
+    #[value(name = "cfg.dot")]
+    CfgDot,
+    /// Interference graph of the variables, in Graphviz DOT.
+    #[value(name = "interference.dot")]
+    InterferenceDot,
+    /// Values live across each statement and the spills it forced, from the highest pressure down.
+    Pressure,
+    /// Each spill with the gas of it and its restores, and the variables that pushed the value out
+    /// of reach.
+    Spills,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, ValueEnum)]
+enum Format {
+    #[default]
+    Text,
+    /// A document with the instructions, bytecode, spills and diagnostics.
+    Json,
+    /// Artifact in the layout of forge's `out/` directory, for forge and cast.
+    Foundry,
+    /// Debug info in the ethdebug format, with the locations of variables at each instruction.
+    Ethdebug,
+    /// Each source statement followed by the instructions generated for it, with their offsets
+    /// and bytes.
+    Listing,
+}
+
+#[derive(Clone, Copy, Default, ValueEnum)]
+enum SpillMode {
+    /// Reuse registers once the values in them are dead.
+    #[default]
+    Reuse,
+    /// Give each spilled variable its own register.
+    Stable,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum InputSyntax {
+    /// The statement language of this compiler.
+    Dsl,
+    /// Straight-line Yul, without functions or control flow.
+    Yul,
+}
+
+#[derive(Args)]
+struct CodegenArgs {
+    /// Read default options from this file instead of the nearest `evm-spilling.toml`.
+    #[arg(long, value_name = "PATH")]
+    config: Option<PathBuf>,
+
+    /// Keep chains of swaps as generated.
+    #[arg(long)]
+    no_minimize_swaps: bool,
+
+    /// Pool constants in a data segment where that saves at least this many bytes.
+    #[arg(long, value_name = "BYTES")]
+    constant_pool: Option<usize>,
+
+    /// Keep constants in memory registers set at the start where that saves at least this many bytes.
+    #[arg(long, value_name = "BYTES")]
+    constant_registers: Option<usize>,
+
+    /// How spilled values are assigned to memory registers.
+    #[arg(long, value_enum, default_value_t)]
+    spill_mode: SpillMode,
+
+    /// Memory address where the spill registers start, at least 0x80 in programs that use the heap.
+    #[arg(long, default_value_t = U256::ZERO)]
+    spill_base: U256,
+
+    /// Keep the spill base address on the stack.
+    #[arg(long)]
+    base_on_stack: bool,
+
+    /// Number of values beneath the program's stack that it must leave in place.
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    reserved_slots: usize,
+
+    /// Depth from which values count as out of reach and are spilled, to leave headroom below 16.
+    #[arg(long, value_name = "N", default_value_t = 16, value_parser = clap::value_parser!(u8).range(1..=16))]
+    spill_depth: u8,
+
+    /// Generate code without swaps, recomputing constants and storing other values in registers.
+    #[arg(long)]
+    treeify: bool,
+
+    /// Move the program's memory past the spill registers, adding their end to constant memory
+    /// offsets.
+    #[arg(long)]
+    relocate_memory: bool,
+
+    /// Schedule again up to this many times when a value is needed too deep, each time keeping
+    /// one more variable in memory.
+    #[arg(long, value_name = "N", default_value_t = 16)]
+    retries: usize,
+
+    /// Target EVM version.
+    #[arg(long, default_value = "cancun")]
+    evm_version: EvmVersion,
+
+    /// Check the generated code against the stack model.
+    #[arg(long)]
+    verify: bool,
+
+    /// Apply at most this many optional optimizations, to bisect which one breaks a program.
+    #[arg(long, value_name = "N")]
+    fuel: Option<usize>,
+
+    /// Seed for randomized heuristics, to reproduce their results.
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    seed: u64,
+}
+
+impl CodegenArgs {
+    fn options(&self) -> codegen::Options {
+        codegen::Options {
+            verify: self.verify || cfg!(debug_assertions),
+            stable_registers: matches!(self.spill_mode, SpillMode::Stable),
+            minimize_swaps: !self.no_minimize_swaps,
+            spill_base: self.spill_base,
+            base_on_stack: self.base_on_stack,
+            evm_version: self.evm_version,
+            constant_pool: self.constant_pool,
+            constant_registers: self.constant_registers,
+            reserved_slots: self.reserved_slots,
+            spill_depth: self.spill_depth.into(),
+            treeify: self.treeify,
+            relocate_memory: self.relocate_memory,
+            retries: self.retries,
+            record_pre: false,
+            fuel: self.fuel,
+            seed: self.seed,
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Reformat a program: one statement per line, aligned `let` bindings, decimal constants.
+    Fmt {
+        /// Program to format, or `-` to read it from stdin.
+        input: PathBuf,
+
+        /// Rewrite the file in place instead of printing it.
+        #[arg(short, long)]
+        write: bool,
+    },
+
+    /// Step through the generated instructions, showing the stack and spill registers after each.
+    Debug {
+        /// Program to debug, or `-` to read it from stdin.
+        input: PathBuf,
+
+        /// Run the reference interpreter alongside to show actual values.
+        #[arg(long)]
+        execute: bool,
+
+        #[command(flatten)]
+        codegen: CodegenArgs,
+    },
+
+    /// Time each stage of compiling a program, and report the quality of the output.
+    Bench {
+        /// Program to compile, or `-` to read it from stdin.
+        input: PathBuf,
+
+        /// Number of times to compile the program.
+        #[arg(short = 'n', long, default_value_t = 100)]
+        iterations: u32,
+
+        #[command(flatten)]
+        codegen: CodegenArgs,
+    },
+
+    /// Compile a program with every combination of backend, spill mode, swap minimization and
+    /// constant handling, on top of the given options, and tabulate the quality of each.
+    Matrix {
+        /// Program to compile, or `-` to read it from stdin.
+        input: PathBuf,
+
+        /// Language of the input. Defaults to Yul for `.yul` files.
+        #[arg(long, value_enum)]
+        syntax: Option<InputSyntax>,
+
+        #[command(flatten)]
+        codegen: CodegenArgs,
+    },
+
+    /// Compare the code generated for two programs, or for one program with two sets of options.
+    Diff {
+        /// Program to compile, or the old program when comparing two.
+        old: PathBuf,
+
+        /// New program to compare against the old one.
+        new: Option<PathBuf>,
+
+        /// Codegen options for the old compilation, such as "--spill-mode stable".
+        #[arg(long, default_value = "", allow_hyphen_values = true)]
+        old_options: String,
+
+        /// Codegen options for the new compilation.
+        #[arg(long, default_value = "", allow_hyphen_values = true)]
+        new_options: String,
+    },
+
+    /// Generate programs that stress the spiller, and check the code generated for each.
+    Stress {
+        /// Number of seeds, each giving one program per pattern.
+        #[arg(short = 'n', long, default_value_t = 100)]
+        count: u64,
+
+        /// First seed of the generated programs.
+        #[arg(long, value_name = "N", default_value_t = 0)]
+        first_seed: u64,
+
+        /// Write the programs into this directory instead of checking them.
+        #[arg(long, value_name = "DIR")]
+        write: Option<PathBuf>,
+
+        #[command(flatten)]
+        codegen: CodegenArgs,
+    },
+
+    /// Cut solc's JSON assembly (`solc --asm-json`) into straight-line programs, printed one after
+    /// another with a blank line between them.
+    Import {
+        /// Assembly to import, or `-` to read it from stdin.
+        input: PathBuf,
+
+        /// Write each program into its own file in this directory instead.
+        #[arg(long, value_name = "DIR")]
+        write: Option<PathBuf>,
+    },
+
+    /// Apply the peephole rules to assembly, one instruction per line, and print the result.
+    Peephole {
+        /// Assembly to optimize, or `-` to read it from stdin.
+        input: PathBuf,
+
+        /// Read hex bytecode and disassemble it instead.
+        #[arg(long)]
+        hex: bool,
+    },
+
+    /// Compare gas, size and memory accesses with solc's Yul compiler on the same programs.
+    #[cfg(feature = "solc")]
+    Solc {
+        /// Programs to compile.
+        #[arg(required = true)]
+        inputs: Vec<PathBuf>,
+
+        /// Path to the solc executable.
+        #[arg(long, default_value = "solc")]
+        solc: PathBuf,
+
+        #[command(flatten)]
+        codegen: CodegenArgs,
+    },
+}
+
+#[derive(Parser)]
+#[command(version, about, args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Program to compile, or `-` to read it from stdin.
+    #[arg(required_unless_present = "disassemble")]
+    input: Option<PathBuf>,
+
+    /// Language of the input. Defaults to Yul for `.yul` files.
+    #[arg(long, value_enum)]
+    syntax: Option<InputSyntax>,
+
+    /// Write the output to this file instead of stdout. `-` also stands for stdout.
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// Pipeline stage whose output to print.
+    #[arg(long, value_enum, default_value_t)]
+    emit: Stage,
+
+    /// Prepend constructor code that deploys the program.
+    #[arg(long)]
+    deploy: bool,
+
+    /// Output format.
+    #[arg(long, value_enum, default_value_t, conflicts_with_all = ["emit", "eof"])]
+    format: Format,
+
+    /// Print hex bytecode in an EOF container, which has no room for a constant pool.
+    #[arg(long, conflicts_with_all = ["emit", "deploy", "constant_pool"])]
+    eof: bool,
+
+    /// Print the assembly with the reason for each instruction in a comment.
+    #[arg(long, conflicts_with_all = ["emit", "format", "eof", "deploy"])]
+    explain: bool,
+
+    /// Print the source map before the code.
+    #[arg(long)]
+    source_map: bool,
+
+    /// Print the offsets of immutables before the code.
+    #[arg(long)]
+    link_references: bool,
+
+    /// Append a metadata trailer to the bytecode.
+    #[arg(long)]
+    metadata: bool,
+
+    /// Disassemble hex bytecode instead of compiling.
+    #[arg(long, value_name = "HEX", conflicts_with = "input")]
+    disassemble: Option<String>,
+
+    #[command(flatten)]
+    codegen: CodegenArgs,
+
+    /// Recompile whenever the input file changes, reporting the instruction count and gas.
+    #[arg(long, conflicts_with_all = ["disassemble", "output"])]
+    watch: bool,
+
+    /// Prove the output equivalent to the program with an SMT solver command, such as "z3 -in".
+    #[cfg(feature = "smt")]
+    #[arg(long, value_name = "SOLVER")]
+    smt: Option<String>,
+
+    /// Log scheduling decisions to stderr: `-v` for spills and registers, `-vv` for every stack move.
+    #[arg(short, long, action = ArgAction::Count)]
+    verbose: u8,
+}
+
+fn run(cli: Cli) -> Result<String> {
+    if let Some(hex) = &cli.disassemble {
+        return Ok(InstructionSeq::from_hex(hex)?.to_string());
+    }
+
+    let input = read_input(cli.input.as_ref().unwrap())?;
+    let input_syntax = syntax(cli.syntax, cli.input.as_deref());
+    if input_syntax == Syntax::Dsl && parser::is_contract(&input) {
+        return contract(&cli, &input);
+    }
+
+    match cli.emit {
+        Stage::Tokens => {
+            eyre::ensure!(input_syntax == Syntax::Dsl, "tokens are only available for the DSL");
+            return Ok(parser::tokens(&input)?.join("\n") + "\n");
+        }
+        Stage::Ast => return Ok(format!("{:#?}\n", input_syntax.parse(&input)?)),
+        Stage::Resolved => {
+            let program = scope::resolve(input_syntax.parse(&input)?)?;
+            return Ok(program.block.0.iter().map(|s| format!("{s}\n")).collect());
+        }
+        _ => {}
+    }
+
+    let options = options(&cli);
+    if cli.format == Format::Json {
+        return Ok(format!("{:#}\n", compile_json(&input, &options)));
+    }
+
+    let output = compile(&input, &options)?;
+    warn(&output, &input);
+    if cli.format == Format::Foundry {
+        return Ok(format!("{:#}\n", output.foundry_artifact(&input)?));
+    }
+    if cli.format == Format::Ethdebug {
+        return Ok(format!("{:#}\n", evm_spilling::ethdebug::info(&output, &input)?));
+    }
+    if cli.format == Format::Listing {
+        return output.listing(&input);
+    }
+
+    #[cfg(feature = "smt")]
+    if let Some(solver) = &cli.smt {
+        evm_spilling::smt::verify(&output, solver)?;
+    }
+
+    let mut out = String::new();
+
+    if cli.link_references {
+        for (name, ranges) in output.link_references()? {
+            let ranges: Vec<_> = ranges.iter().map(|r| format!("{}..{}", r.start, r.end)).collect();
+            out += &format!("{name}: {}\n", ranges.join(", "));
+        }
+    }
+
+    if cli.source_map {
+        out += &format!("{}\n", output.source_map().unwrap_or_default());
+    }
+
+    if cli.explain {
+        out += &evm_spilling::explain::render(&output);
+    } else if cli.emit == Stage::Timeline {
+        out += &output.timeline();
+    } else if cli.emit == Stage::CfgDot {
+        out += &dot::cfg(&output.code);
+    } else if cli.emit == Stage::InterferenceDot {
+        out += &dot::interference(&output.program, &output.code);
+    } else if cli.emit == Stage::Pressure {
+        out += "live spills statement\n";
+        for p in output.pressure() {
+            out += &format!("{:>4} {:>6} {}: {}\n", p.live, p.spills, p.statement, output.program.named_statement(p.statement));
+        }
+    } else if cli.emit == Stage::Spills {
+        let program = &output.program;
+        for cause in output.spill_causes() {
+            let spill = cause.spill;
+            let live: Vec<_> = cause.live.iter().map(|&v| program.name(v)).collect();
+            out += &format!(
+                "{} to r{} at {}: {}\n  restores: {}, gas: {}\n  buried by: {}\n",
+                program.name(spill.var), spill.register, spill.statement, program.named_statement(spill.statement),
+                cause.restores, cause.gas, live.join(", "),
+            );
+        }
+    } else if cli.emit == Stage::Pre {
+        out += &output.code.pre.join("\n");
+        out += "\n";
+    } else if cli.eof {
+        let runtime = InstructionSeq::from(output.code.instructions);
+        out += &format!("{}\n", alloy_primitives::hex::encode(runtime.to_eof()?));
+    } else if cli.emit == Stage::Etk {
+        eyre::ensure!(!cli.deploy, "etk output is for the runtime code only");
+        out += &InstructionSeq::from(output.code.instructions).to_etk(output.code.evm_version)?;
+    } else if cli.emit == Stage::Geas {
+        eyre::ensure!(!cli.deploy, "geas output is for the runtime code only");
+        out += &InstructionSeq::from(output.code.instructions).to_geas(output.code.evm_version);
+    } else if cli.emit == Stage::Bin {
+        let bytes = if cli.deploy { output.init_code()? } else { output.bytecode };
+        out += &format!("{}\n", alloy_primitives::hex::encode(bytes));
+    } else if cli.emit == Stage::Hex {
+        eyre::ensure!(out.is_empty(), "hex output is the bytecode alone");
+        let bytes = if cli.deploy { output.init_code()? } else { output.bytecode };
+        out += &alloy_primitives::hex::encode_prefixed(bytes);
+    } else {
+        if cli.deploy {
+            let version = output.code.evm_version;
+            out += &evm::constructor(output.bytecode.len(), version).display(version).to_string();
+        }
+        out += &format!("{}\n", output.assembly());
+    }
+
+    Ok(out)
+}
+
+/// Prints the warnings about `output` to stderr, with the line of each in `input`.
+fn warn(output: &Output, input: &str) {
+    for warning in output.warnings() {
+        match warning.span {
+            Some(span) => eprintln!("warning: {} (line {})", warning.message, input[..span.start].matches('\n').count() + 1),
+            None => eprintln!("warning: {}", warning.message),
+        }
+    }
+}
+
+/// Compiles a contract, with its functions behind a dispatcher, to its assembly or bytecode.
+fn contract(cli: &Cli, input: &str) -> Result<String> {
+    eyre::ensure!(
+        cli.format == Format::Text && matches!(cli.emit, Stage::Asm | Stage::Bin | Stage::Hex) && !cli.eof && !cli.explain && !cli.source_map && !cli.link_references,
+        "only the assembly and the bytecode are available for contracts"
+    );
+    let contract = dispatch::compile(input, &options(cli))?;
+    for function in &contract.functions {
+        warn(&function.output, input);
+    }
+    if matches!(cli.emit, Stage::Bin | Stage::Hex) {
+        let bytes = if cli.deploy { contract.init_code()? } else { contract.bytecode };
+        if cli.emit == Stage::Hex {
+            return Ok(alloy_primitives::hex::encode_prefixed(bytes));
+        }
+        return Ok(format!("{}\n", alloy_primitives::hex::encode(bytes)));
+    }
+    let mut out = String::new();
+    if cli.deploy {
+        let version = contract.evm_version;
+        out += &evm::constructor(contract.bytecode.len(), version).display(version).to_string();
+    }
+    out += &contract.assembly();
+    Ok(out)
+}
+
+fn read_input(path: &Path) -> Result<String> {
+    if path.as_os_str() == "-" {
+        Ok(std::io::read_to_string(std::io::stdin())?)
+    } else {
+        Ok(std::fs::read_to_string(path)?)
+    }
+}
+
+fn syntax(selected: Option<InputSyntax>, input: Option<&Path>) -> Syntax {
+    match selected {
+        Some(InputSyntax::Dsl) => Syntax::Dsl,
+        Some(InputSyntax::Yul) => Syntax::Yul,
+        None if input.is_some_and(|path| path.extension().is_some_and(|ext| ext == "yul")) => Syntax::Yul,
+        None => Syntax::Dsl,
+    }
+}
+
+fn options(cli: &Cli) -> Options {
+    Options {
+        syntax: syntax(cli.syntax, cli.input.as_deref()),
+        codegen: codegen::Options {
+            record_pre: cli.emit == Stage::Pre,
+            constant_pool: cli.codegen.constant_pool.filter(|_| !cli.eof),
+            ..cli.codegen.options()
+        },
+        metadata: cli.metadata,
+        ..Options::default()
+    }
+}
+
+fn bench(source: &str, options: &codegen::Options, iterations: u32) -> Result<String> {
+    eyre::ensure!(iterations > 0, "need at least one iteration");
+
+    let mut times = [Duration::ZERO; 5];
+    let mut time = |stage: usize, start: Instant| times[stage] += start.elapsed();
+    let mut code = None;
+    for _ in 0..iterations {
+        let start = Instant::now();
+        let block = parser::parse(source)?;
+        time(0, start);
+
+        let start = Instant::now();
+        let program = scope::resolve(block)?;
+        time(1, start);
+
+        let start = Instant::now();
+        std::hint::black_box((analysis::count_occurrences(&program), analysis::live_ranges(&program)));
+        time(2, start);
+
+        let start = Instant::now();
+        let generated = codegen::generate(&program, options)?;
+        time(3, start);
+
+        let start = Instant::now();
+        let bytecode = InstructionSeq::from(generated.instructions.clone()).to_bytes(generated.evm_version)?;
+        time(4, start);
+
+        code = Some((generated, bytecode));
+    }
+
+    let mut out = String::new();
+    let total: Duration = times.iter().sum();
+    for (stage, elapsed) in ["parse", "resolve", "analyze", "codegen", "assemble"].iter().zip(times) {
+        out += &format!("{stage:<10} {:>10.1?} per iteration\n", elapsed / iterations);
+    }
+    out += &format!("{:<10} {:>10.1?} per iteration, {iterations} iterations\n\n", "total", total / iterations);
+
+    let (code, bytecode) = code.unwrap();
+    for (name, value) in metrics(&code, bytecode.len()) {
+        out += &format!("{name:<12} {value}\n");
+    }
+    Ok(out)
+}
+
+/// Measures of the quality of generated code.
+fn metrics(code: &Code, size: usize) -> [(&'static str, u64); 7] {
+    let gas = code.instructions.iter().map(|i| i.gas_cost(code.evm_version)).sum();
+    let spills = code.spills.iter().filter(|s| !s.restore).count();
+    [
+        ("instructions", code.instructions.len() as u64),
+        ("bytes", size as u64),
+        ("gas", gas),
+        ("registers", code.register_count as u64),
+        ("spill bytes", code.spill_size() as u64),
+        ("spills", spills as u64),
+        ("restores", (code.spills.len() - spills) as u64),
+    ]
+}
+
+/// Gas, size, spills and registers of `source` compiled with each combination of options varied
+/// from `base`, followed by the combinations with the least gas and the fewest bytes. Gas includes
+/// expanding memory to the spill registers.
+fn matrix(source: &str, base: &Options) -> String {
+    let mut out = format!("{:<40} {:>8} {:>8} {:>8} {:>9}\n", "", "gas", "bytes", "spills", "registers");
+    let mut best: [Option<(u64, String)>; 2] = [None, None];
+    for treeify in [false, true] {
+        for stable_registers in [false, true] {
+            // Treeify leaves no swaps to minimize.
+            for minimize_swaps in if treeify { &[true][..] } else { &[true, false] } {
+                for constants in ["push", "pool", "registers"] {
+                    let codegen = codegen::Options {
+                        treeify,
+                        stable_registers,
+                        minimize_swaps: *minimize_swaps,
+                        constant_pool: (constants == "pool").then_some(1),
+                        constant_registers: (constants == "registers").then_some(1),
+                        ..base.codegen.clone()
+                    };
+                    let name = format!(
+                        "{} {} {} {constants}",
+                        if treeify { "treeify" } else { "schedule" },
+                        if stable_registers { "stable" } else { "reuse" },
+                        if *minimize_swaps { "minimized" } else { "unminimized" },
+                    );
+                    let output = match compile(source, &Options { syntax: base.syntax, codegen, ..Options::default() }) {
+                        Ok(output) => output,
+                        Err(e) => {
+                            out += &format!("{name:<40} {e}\n");
+                            continue;
+                        }
+                    };
+                    let code = &output.code;
+                    let gas = output.gas() + code.spill_memory_cost();
+                    let bytes = output.bytecode.len() as u64;
+                    let spills = code.spills.iter().filter(|s| !s.restore).count();
+                    out += &format!("{name:<40} {gas:>8} {bytes:>8} {spills:>8} {:>9}\n", code.register_count);
+                    for (best, value) in best.iter_mut().zip([gas, bytes]) {
+                        if best.as_ref().is_none_or(|(v, _)| value < *v) {
+                            *best = Some((value, name.clone()));
+                        }
+                    }
+                }
+            }
+        }
+    }
+    if let [Some((_, gas)), Some((_, bytes))] = best {
+        out += &format!("\nleast gas: {gas}\nfewest bytes: {bytes}\n");
+    }
+    out
+}
+
+/// Codegen options given as a single string, like `--spill-mode stable --spill-base 64`.
+#[derive(Parser)]
+#[command(no_binary_name = true)]
+struct OptionString {
+    #[command(flatten)]
+    codegen: CodegenArgs,
+}
+
+fn diff(old: (&str, &str), new: (&str, &str)) -> Result<String> {
+    let compile_with = |(source, options): (&str, &str)| -> Result<Output> {
+        let matches = OptionString::command().try_get_matches_from(options.split_whitespace())?;
+        let mut args = OptionString::from_arg_matches(&matches)?.codegen;
+        apply_config(&mut args, &matches)?;
+        Ok(compile(source, &Options { codegen: args.options(), ..Options::default() })?)
+    };
+    let (old, new) = (compile_with(old)?, compile_with(new)?);
+
+    let mut out = format!("{:<12} {:>8} {:>8} {:>8}\n", "", "old", "new", "delta");
+    let old_metrics = metrics(&old.code, old.bytecode.len());
+    let new_metrics = metrics(&new.code, new.bytecode.len());
+    for ((name, a), (_, b)) in old_metrics.into_iter().zip(new_metrics) {
+        out += &format!("{name:<12} {a:>8} {b:>8} {:>+8}\n", b as i64 - a as i64);
+    }
+    out += "\n";
+
+    let lines = |output: &Output| -> Vec<String> {
+        output.code.instructions.iter().map(|i| i.display(output.code.evm_version).to_string()).collect()
+    };
+    let (a, b) = (lines(&old), lines(&new));
+
+    // Align the streams on a longest common subsequence.
+    let mut lcs = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] { lcs[i + 1][j + 1] + 1 } else { lcs[i + 1][j].max(lcs[i][j + 1]) };
+        }
+    }
+    let width = a.iter().map(String::len).max().unwrap_or(0).max(3);
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            out += &format!("  {:<width$}   {}\n", a[i], b[j]);
+            (i, j) = (i + 1, j + 1);
+        } else if j == b.len() || (i < a.len() && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            out += &format!("- {}\n", a[i]);
+            i += 1;
+        } else {
+            out += &format!("+ {:<width$}   {}\n", "", b[j]);
+            j += 1;
+        }
+    }
+    Ok(out)
+}
+
+fn watch(path: &Path, options: &Options) -> Result<()> {
+    eyre::ensure!(path.as_os_str() != "-", "cannot watch stdin");
+
+    let mut modified = None;
+    let mut previous: Option<(usize, u64)> = None;
+    let mut cache = Cache::new();
+    loop {
+        let current = std::fs::metadata(path)?.modified()?;
+        if modified != Some(current) {
+            modified = Some(current);
+            let hits = cache.hits;
+            match cache.compile(&std::fs::read_to_string(path)?, options) {
+                Ok(output) => {
+                    let (count, gas) = (output.code.instructions.len(), output.gas());
+                    print!("{count} instructions, {gas} gas");
+                    if let Some((previous_count, previous_gas)) = previous {
+                        print!(" ({:+} instructions, {:+} gas)", count as i64 - previous_count as i64, gas as i64 - previous_gas as i64);
+                    }
+                    if cache.hits > hits {
+                        print!(", cached");
+                    }
+                    println!();
+                    previous = Some((count, gas));
+                }
+                Err(e) => eprintln!("error: {e:#}"),
+            }
+        }
+        std::thread::sleep(Duration::from_millis(200));
+    }
+}
+
+fn apply_config(args: &mut CodegenArgs, matches: &clap::ArgMatches) -> Result<()> {
+    if let Some(config) = config::load(args.config.as_deref())? {
+        config.apply(args, matches)?;
+    }
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let matches = Cli::command().get_matches();
+    let mut cli = Cli::from_arg_matches(&matches)?;
+    match (&mut cli.command, matches.subcommand()) {
+        (
+            Some(Command::Debug { codegen, .. } | Command::Bench { codegen, .. } | Command::Matrix { codegen, .. } | Command::Stress { codegen, .. }),
+            Some((_, matches)),
+        ) => apply_config(codegen, matches)?,
+        #[cfg(feature = "solc")]
+        (Some(Command::Solc { codegen, .. }), Some((_, matches))) => apply_config(codegen, matches)?,
+        (Some(_), _) => {}
+        (None, _) => apply_config(&mut cli.codegen, &matches)?,
+    }
+    let level = match cli.verbose {
+        0 => Level::WARN,
+        1 => Level::DEBUG,
+        _ => Level::TRACE,
+    };
+    tracing_subscriber::fmt().with_writer(std::io::stderr).with_max_level(level).without_time().with_target(false).init();
+    if let Some(Command::Fmt { input, write }) = &cli.command {
+        let formatted = parser::format(&read_input(input)?)?;
+        if *write && input.as_os_str() != "-" {
+            std::fs::write(input, formatted)?;
+        } else {
+            print!("{formatted}");
+        }
+        return Ok(());
+    }
+    if let Some(Command::Debug { input, execute, codegen }) = &cli.command {
+        let options = Options { codegen: codegen.options(), ..Options::default() };
+        return debugger::run(&read_input(input)?, &options, *execute);
+    }
+    if let Some(Command::Bench { input, iterations, codegen }) = &cli.command {
+        print!("{}", bench(&read_input(input)?, &codegen.options(), *iterations)?);
+        return Ok(());
+    }
+    if let Some(Command::Matrix { input, syntax: selected, codegen }) = &cli.command {
+        let options = Options { syntax: syntax(*selected, Some(input)), codegen: codegen.options(), ..Options::default() };
+        print!("{}", matrix(&read_input(input)?, &options));
+        return Ok(());
+    }
+    if let Some(Command::Diff { old, new, old_options, new_options }) = &cli.command {
+        let old_source = read_input(old)?;
+        let new_source = match new {
+            Some(new) => read_input(new)?,
+            None => old_source.clone(),
+        };
+        print!("{}", diff((&old_source, old_options), (&new_source, new_options))?);
+        return Ok(());
+    }
+    if let Some(Command::Peephole { input, hex }) = &cli.command {
+        let input = read_input(input)?;
+        let mut seq = if *hex { InstructionSeq::from_hex(&input)? } else { input.parse()? };
+        let removed = evm_spilling::peephole::optimize(&mut seq);
+        eprintln!("{removed} instructions removed");
+        print!("{seq}");
+        return Ok(());
+    }
+    if let Some(Command::Import { input, write }) = &cli.command {
+        let segments = evm_spilling::asm_json::import(&read_input(input)?)?;
+        if let Some(dir) = write {
+            std::fs::create_dir_all(dir)?;
+        }
+        for (i, segment) in segments.iter().enumerate() {
+            let source: String = segment.block.0.iter().map(|s| format!("{s}\n")).collect();
+            match write {
+                Some(dir) => std::fs::write(dir.join(format!("segment-{i}.dsl")), source)?,
+                None if i == 0 => print!("{source}"),
+                None => print!("\n{source}"),
+            }
+        }
+        return Ok(());
+    }
+    if let Some(Command::Stress { count, first_seed, write, codegen }) = &cli.command {
+        let seeds = *first_seed..first_seed + count;
+        if let Some(dir) = write {
+            std::fs::create_dir_all(dir)?;
+            for seed in seeds {
+                for pattern in stress::Pattern::ALL {
+                    let name = format!("{}-{seed}.dsl", format!("{pattern:?}").to_lowercase());
+                    std::fs::write(dir.join(name), stress::generate(pattern, seed))?;
+                }
+            }
+            return Ok(());
+        }
+        let checked = stress::run(seeds, Options { codegen: codegen.options(), ..Options::default() })?;
+        println!("{checked} programs checked");
+        return Ok(());
+    }
+    #[cfg(feature = "solc")]
+    if let Some(Command::Solc { inputs, solc, codegen }) = &cli.command {
+        let programs = inputs.iter()
+            .map(|path| Ok((path.display().to_string(), read_input(path)?)))
+            .collect::<Result<Vec<_>>>()?;
+        let options = Options { codegen: codegen.options(), ..Options::default() };
+        print!("{}", solc::compare(&programs, solc, &options)?);
+        return Ok(());
+    }
+    if cli.watch {
+        return watch(cli.input.as_ref().unwrap(), &options(&cli));
+    }
+    let output = cli.output.clone();
+    let out = run(cli)?;
+    match output {
+        Some(path) if path.as_os_str() != "-" => std::fs::write(path, out)?,
+        _ => print!("{out}"),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Catches clashing argument names, which clap only reports when a subcommand is parsed.
+    #[test]
+    fn cli() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn eof_without_constant_pool() {
+        assert!(Cli::try_parse_from(["evm-spilling", "a.dsl", "--eof", "--constant-pool", "1"]).is_err());
+
+        // As a config file would set it.
+        let mut cli = Cli::try_parse_from(["evm-spilling", "a.dsl", "--eof"]).unwrap();
+        cli.codegen.constant_pool = Some(1);
+        assert_eq!(options(&cli).codegen.constant_pool, None);
+    }
 }
@@ -0,0 +1,45 @@
+//! Metadata trailer in the style of solc: a CBOR map followed by its length as two big-endian
+//! bytes, so that tools can find it by reading the end of the code.
+
+use alloy_primitives::keccak256;
+
+fn cbor_header(out: &mut Vec<u8>, major: u8, len: usize) {
+    match len {
+        0..=23 => out.push(major << 5 | len as u8),
+        24..=0xff => out.extend([major << 5 | 24, len as u8]),
+        _ => {
+            out.push(major << 5 | 25);
+            out.extend_from_slice(&u16::try_from(len).unwrap().to_be_bytes());
+        }
+    }
+}
+
+fn cbor_text(out: &mut Vec<u8>, text: &str) {
+    cbor_header(out, 3, text.len());
+    out.extend_from_slice(text.as_bytes());
+}
+
+fn cbor_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    cbor_header(out, 2, bytes.len());
+    out.extend_from_slice(bytes);
+}
+
+/// Encodes the compiler name and version and the keccak256 hash of `source`.
+pub fn trailer(source: &str) -> Vec<u8> {
+    let mut map = Vec::new();
+    cbor_header(&mut map, 5, 2);
+    cbor_text(&mut map, "compiler");
+    cbor_text(&mut map, concat!(env!("CARGO_PKG_NAME"), " ", env!("CARGO_PKG_VERSION")));
+    cbor_text(&mut map, "source");
+    cbor_bytes(&mut map, keccak256(source).as_slice());
+
+    let len = u16::try_from(map.len()).unwrap();
+    map.extend_from_slice(&len.to_be_bytes());
+    map
+}
+
+/// Appends the trailer to assembled code, after a `stop` so that execution never reaches it.
+pub fn append(code: &mut Vec<u8>, source: &str) {
+    code.push(0x00);
+    code.extend(trailer(source));
+}
@@ -1,26 +1,51 @@
+use std::collections::HashMap;
+
+use crate::codegen::HEAP_START;
+use crate::evm::DataInstruction;
 use crate::program::*;
-use alloy_primitives::U256;
-use winnow::{ascii::{alphanumeric1, multispace0}, combinator::{empty, fail, peek, preceded, repeat, separated, terminated}, dispatch, error::{ErrMode, ParserError}, prelude::*, seq, stream::AsChar, token::any};
-use eyre::eyre;
+use alloy_primitives::{hex, keccak256, U256};
+use winnow::{ascii::{alphanumeric1, multispace0, multispace1, till_line_ending}, combinator::{alt, delimited, empty, fail, peek, preceded, repeat, separated, terminated}, dispatch, error::{ErrMode, ParserError}, prelude::*, seq, stream::AsChar, token::{any, take_till}};
 
-enum Token<S> {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Token<S> {
     Let,
+    Extern,
     Const,
+    Immutable,
     Eq,
     Semi,
     Comma,
+    Question,
+    Colon,
+    LParen,
+    RParen,
+    LBrace,
+    RBrace,
     Identifier(S),
     Literal(S),
+    /// A string in double quotes, without the quotes.
+    String(S),
+}
+
+/// A `//` comment, up to the end of the line.
+fn comment<'a>(input: &mut &'a str) -> PResult<&'a str> {
+    ("//", till_line_ending).recognize().map(str::trim_end).parse_next(input)
+}
+
+fn ws(input: &mut &str) -> PResult<()> {
+    repeat(0.., alt((multispace1.void(), comment.void()))).parse_next(input)
 }
 
 fn token<'a>(input: &mut &'a str) -> PResult<Token<&'a str>> {
     dispatch! {
-        preceded(multispace0, peek(any));
+        preceded(ws, peek(any));
 
         t if AsChar::is_alpha(t) => alphanumeric1.map(|id| {
             match id {
                 "let" => Token::Let,
+                "extern" => Token::Extern,
                 "const" => Token::Const,
+                "immutable" => Token::Immutable,
                 _ => Token::Identifier(id),
             }
         }),
@@ -30,6 +55,13 @@ fn token<'a>(input: &mut &'a str) -> PResult<Token<&'a str>> {
         '=' => any.map(|_| Token::Eq),
         ';' => any.map(|_| Token::Semi),
         ',' => any.map(|_| Token::Comma),
+        '?' => any.map(|_| Token::Question),
+        ':' => any.map(|_| Token::Colon),
+        '(' => any.map(|_| Token::LParen),
+        ')' => any.map(|_| Token::RParen),
+        '{' => any.map(|_| Token::LBrace),
+        '}' => any.map(|_| Token::RBrace),
+        '"' => delimited('"', take_till(0.., ['"', '\n']), '"').map(Token::String),
 
         _ => fail,
     }
@@ -48,52 +80,502 @@ macro_rules! token {
     };
 }
 
-fn identifier(input: &mut &str) -> PResult<String> {
-    token!(Identifier(id) => id.into()).parse_next(input)
+fn identifier<'a>(input: &mut &'a str) -> PResult<&'a str> {
+    token!(Identifier(id) => id).parse_next(input)
 }
 
 fn constant(input: &mut &str) -> PResult<U256> {
     let c = token!(Literal(c) => c).parse_next(input)?;
-    U256::from_str_radix(&c, 10).map_err(|_| ErrMode::assert(input, "bad literal"))
+    U256::from_str_radix(c, 10).map_err(|_| ErrMode::assert(input, "bad literal"))
 }
 
-fn expression(input: &mut &str) -> PResult<Expression<String>> {
+fn expression<'a>(input: &mut &'a str) -> PResult<Expression<&'a str>> {
     use Expression::*;
 
     dispatch! {
         token;
 
         Token::Const => seq!(Const(constant)),
+        Token::Immutable => seq!(Immutable(identifier.map(String::from))),
         Token::Identifier(op) => seq!(Op(empty.value(op.into()), repeat(0.., identifier))),
         _ => fail,
     }.parse_next(input)
 }
 
-fn statement(input: &mut &str) -> PResult<Statement<String>> {
+/// A statement as written, before `lower` desugars it into statements of the IR.
+enum Parsed<V> {
+    Statement(Statement<V>),
+    /// `let (a, b) = op ...;`, the same statement with its results in parentheses.
+    Tuple(Statement<V>),
+    /// `let var = cond ? then : otherwise;`
+    Select { var: V, cond: V, then: V, otherwise: V },
+    /// `extern op name takes 3 returns 1 = 0xf7;`, after which `name` is an operation that
+    /// assembles to the bytes.
+    Extern { name: String, takes: u16, returns: u16, bytes: Vec<u8> },
+    /// `log topic1, topic2 (offset, size);`, with up to four topics.
+    Log { topics: Vec<V>, offset: V, size: V },
+    /// `revert "reason";`
+    Revert { reason: String },
+    /// `let var = const keccak("text");`, or `const selector("f(uint256)")` for the first 4 bytes
+    /// of the hash.
+    Hash { var: V, text: String, selector: bool },
+    /// `takes (a, b, c);`, before any other statement, for values on the stack at the start with
+    /// `a` on top.
+    Takes(Vec<V>),
+    /// `returns (x, y);`, after every other statement, for values left on the stack at the end with
+    /// `x` on top.
+    Returns(Vec<V>),
+}
+
+/// A word that is only a keyword where it's expected, and otherwise an identifier.
+fn keyword<'a>(word: &'static str) -> impl Parser<&'a str, (), winnow::error::ContextError> {
+    identifier.verify(move |id: &str| id == word).void()
+}
+
+fn count(input: &mut &str) -> PResult<u16> {
+    token!(Literal(c) => c).try_map(str::parse).parse_next(input)
+}
+
+fn bytes(input: &mut &str) -> PResult<Vec<u8>> {
+    token!(Literal(c) => c)
+        .verify_map(|c: &str| c.strip_prefix("0x").and_then(|h| hex::decode(h).ok()).filter(|b| !b.is_empty()))
+        .parse_next(input)
+}
+
+fn log<'a>(input: &mut &'a str) -> PResult<Parsed<&'a str>> {
+    let data = delimited(token!(LParen), (identifier, token!(Comma), identifier), token!(RParen));
+    (keyword("log"), separated(0..=4, identifier, token!(Comma)), data)
+        .map(|(_, topics, (offset, _, size))| Parsed::Log { topics, offset, size })
+        .parse_next(input)
+}
+
+fn revert<'a>(input: &mut &'a str) -> PResult<Parsed<&'a str>> {
+    preceded(keyword("revert"), token!(String(reason) => reason))
+        .map(|reason| Parsed::Revert { reason: reason.to_string() })
+        .parse_next(input)
+}
+
+fn hash<'a>(input: &mut &'a str) -> PResult<Parsed<&'a str>> {
+    let function = alt((keyword("selector").value(true), keyword("keccak").value(false)));
+    let text = delimited(token!(LParen), token!(String(text) => text), token!(RParen));
+    (token!(Let), identifier, token!(Eq), token!(Const), function, text)
+        .map(|(_, var, _, _, selector, text)| Parsed::Hash { var, text: text.to_string(), selector })
+        .parse_next(input)
+}
+
+fn takes<'a>(input: &mut &'a str) -> PResult<Parsed<&'a str>> {
+    preceded(keyword("takes"), delimited(token!(LParen), separated(1.., identifier, token!(Comma)), token!(RParen)))
+        .map(Parsed::Takes)
+        .parse_next(input)
+}
+
+fn returns<'a>(input: &mut &'a str) -> PResult<Parsed<&'a str>> {
+    preceded(keyword("returns"), delimited(token!(LParen), separated(1.., identifier, token!(Comma)), token!(RParen)))
+        .map(Parsed::Returns)
+        .parse_next(input)
+}
+
+fn extern_op<'a>(input: &mut &'a str) -> PResult<Parsed<&'a str>> {
+    (token!(Extern), keyword("op"), identifier, keyword("takes"), count, keyword("returns"), count, token!(Eq), bytes)
+        .map(|(_, _, name, _, takes, _, returns, _, bytes)| Parsed::Extern { name: name.to_string(), takes, returns, bytes })
+        .parse_next(input)
+}
+
+fn statement<'a>(input: &mut &'a str) -> PResult<Parsed<&'a str>> {
     terminated(
         dispatch! {
             peek(token);
 
-            Token::Let => seq!(Statement(
-                _: token!(Let),
-                separated(1.., identifier, token!(Comma)),
-                _: token!(Eq),
-                expression,
+            Token::Let => alt((
+                hash,
+                (token!(Let), identifier, token!(Eq), identifier, token!(Question), identifier, token!(Colon), identifier)
+                    .map(|(_, var, _, cond, _, then, _, otherwise)| Parsed::Select { var, cond, then, otherwise }),
+                seq!(Statement(
+                    _: token!(Let),
+                    delimited(token!(LParen), separated(1.., identifier, token!(Comma)), token!(RParen)),
+                    _: token!(Eq),
+                    expression,
+                )).map(Parsed::Tuple),
+                seq!(Statement(
+                    _: token!(Let),
+                    separated(1.., identifier, token!(Comma)),
+                    _: token!(Eq),
+                    expression,
+                )).map(Parsed::Statement),
             )),
-            _ => seq!(Statement(empty.value(vec![]), expression)),
+            Token::Extern => extern_op,
+            _ => alt((log, revert, takes, returns, seq!(Statement(empty.value(vec![]), expression)).map(Parsed::Statement))),
         },
         token!(Semi),
     ).parse_next(input)
 }
 
-fn block(input: &mut &str) -> PResult<Block<String>> {
-    seq!(Block(repeat(0.., statement))).parse_next(input)
+/// A statement with the comments before it, and its text or its span in the input.
+type Commented<'a, T> = (Vec<&'a str>, Parsed<&'a str>, T);
+
+fn comments<'a>(input: &mut &'a str) -> PResult<Vec<&'a str>> {
+    preceded(multispace0, repeat(0.., terminated(comment, multispace0))).parse_next(input)
+}
+
+/// Each statement with the comments on the lines before it. Comments anywhere else are skipped.
+fn block<'a>(input: &mut &'a str) -> PResult<Vec<Commented<'a, &'a str>>> {
+    repeat(0.., (comments, statement.with_recognized()).map(|(c, (s, text))| (c, s, text))).parse_next(input)
+}
+
+fn file<'a>(input: &mut &'a str) -> PResult<Vec<Commented<'a, &'a str>>> {
+    terminated(block, ws).parse_next(input)
+}
+
+/// `function "transfer(address,uint256)" { ... }`, with the ABI signature of the function.
+fn function<'a>(input: &mut &'a str) -> PResult<(&'a str, Vec<Commented<'a, &'a str>>)> {
+    (keyword("function"), token!(String(signature) => signature), delimited(token!(LBrace), block, token!(RBrace)))
+        .map(|(_, signature, body)| (signature, body))
+        .parse_next(input)
+}
+
+/// An error in the input at a byte offset, with winnow's rendering of it.
+#[derive(Debug, thiserror::Error)]
+pub enum ParseError {
+    #[error("lexer error: {message}")]
+    Lexer { offset: usize, message: String },
+    #[error("parser error: {message}")]
+    Syntax { offset: usize, message: String },
+    #[error("Wrong number of results for {op}: expected {expected}, found {found}")]
+    Arity { offset: usize, op: String, expected: usize, found: usize },
+    #[error("Wrong number of arguments for {op}: expected {expected}, found {found}")]
+    Arguments { offset: usize, op: String, expected: usize, found: usize },
+}
+
+impl ParseError {
+    pub fn offset(&self) -> usize {
+        match *self {
+            ParseError::Lexer { offset, .. } | ParseError::Syntax { offset, .. }
+            | ParseError::Arity { offset, .. } | ParseError::Arguments { offset, .. } => offset,
+        }
+    }
+}
+
+/// Splits `input` into tokens, along with their spans.
+pub fn spanned_tokens(input: &str) -> Result<Vec<(Token<&str>, Span)>, ParseError> {
+    let tokens: Vec<(_, &str)> = terminated(repeat(0.., preceded(ws, token.with_recognized())), ws)
+        .parse(input)
+        .map_err(|e| ParseError::Lexer { offset: e.offset(), message: e.to_string() })?;
+    Ok(tokens.into_iter()
+        .map(|(t, text)| {
+            let start = text.as_ptr() as usize - input.as_ptr() as usize;
+            (t, start..start + text.len())
+        })
+        .collect())
+}
+
+/// Splits `input` into tokens, printed for debugging.
+pub fn tokens(input: &str) -> Result<Vec<String>, ParseError> {
+    Ok(spanned_tokens(input)?.iter().map(|(t, _)| format!("{t:?}")).collect())
+}
+
+/// A statement as `format` prints it, with the comments around it.
+struct Line<'a> {
+    /// Comments on the lines before, each with whether a blank line comes before it.
+    leading: Vec<(bool, &'a str)>,
+    /// Whether a blank line comes before the statement itself.
+    blank: bool,
+    head: Option<String>,
+    expression: String,
+    trailing: Option<&'a str>,
 }
 
-fn file(input: &mut &str) -> PResult<Block<String>> {
-    terminated(block, multispace0).parse_next(input)
+/// Reprints `input` with one statement per line and the `=` of consecutive `let` statements
+/// aligned. Blank lines between statements are kept, and separate alignment groups. Comments are
+/// kept on their lines, after a statement or on lines of their own, except those inside a statement.
+pub fn format(input: &str) -> Result<String, ParseError> {
+    let statements = parse_borrowed(input)?;
+    let offset = |text: &str| text.as_ptr() as usize - input.as_ptr() as usize;
+    let blank = |from: usize, to: usize| input[from..to].matches('\n').count() > 1;
+
+    let mut groups: Vec<Vec<Line>> = Vec::new();
+    let mut end = 0;
+    for (comments, s, span) in &statements {
+        let mut comments = &comments[..];
+        if let (Some(&first), Some(last)) = (comments.first(), groups.last_mut().and_then(|g| g.last_mut())) {
+            if !input[end..offset(first)].contains('\n') {
+                last.trailing = Some(first);
+                end = offset(first) + first.len();
+                comments = &comments[1..];
+            }
+        }
+        let starts_group = !groups.is_empty() && blank(end, comments.first().map_or(span.start, |c| offset(c)));
+
+        let mut leading = Vec::new();
+        for (i, &c) in comments.iter().enumerate() {
+            leading.push((i > 0 && blank(end, offset(c)), c));
+            end = offset(c) + c.len();
+        }
+        let (head, expression) = match s {
+            Parsed::Statement(Statement(vs, e)) => ((!vs.is_empty()).then(|| format!("let {}", vs.join(", "))), e.to_string()),
+            Parsed::Tuple(Statement(vs, e)) => (Some(format!("let ({})", vs.join(", "))), e.to_string()),
+            Parsed::Select { var, cond, then, otherwise } => (Some(format!("let {var}")), format!("{cond} ? {then} : {otherwise}")),
+            Parsed::Extern { name, takes, returns, bytes } => {
+                (None, format!("extern op {name} takes {takes} returns {returns} = {}", hex::encode_prefixed(bytes)))
+            }
+            Parsed::Log { topics, offset, size } if topics.is_empty() => (None, format!("log ({offset}, {size})")),
+            Parsed::Log { topics, offset, size } => (None, format!("log {} ({offset}, {size})", topics.join(", "))),
+            Parsed::Revert { reason } => (None, format!("revert \"{reason}\"")),
+            Parsed::Takes(vs) => (None, format!("takes ({})", vs.join(", "))),
+            Parsed::Returns(vs) => (None, format!("returns ({})", vs.join(", "))),
+            Parsed::Hash { var, text, selector } => {
+                (Some(format!("let {var}")), format!("const {}(\"{text}\")", if *selector { "selector" } else { "keccak" }))
+            }
+        };
+        let line = Line { leading, blank: !comments.is_empty() && blank(end, span.start), head, expression, trailing: None };
+        end = span.end;
+        match groups.last_mut() {
+            Some(group) if !starts_group => group.push(line),
+            _ => groups.push(vec![line]),
+        }
+    }
+    let rest = comments.parse_next(&mut &input[end..]).unwrap_or_default();
+
+    let mut out = String::new();
+    for group in groups {
+        if !out.is_empty() {
+            out += "\n";
+        }
+        let width = group.iter().flat_map(|l| &l.head).map(|h| h.len()).max().unwrap_or(0);
+        for line in group {
+            for (blank, comment) in line.leading {
+                out += &format!("{}{comment}\n", if blank { "\n" } else { "" });
+            }
+            if line.blank {
+                out += "\n";
+            }
+            let e = line.expression;
+            match line.head {
+                Some(head) => out += &format!("{head:<width$} = {e};"),
+                None => out += &format!("{e};"),
+            }
+            if let Some(comment) = line.trailing {
+                out += &format!(" {comment}");
+            }
+            out += "\n";
+        }
+    }
+    for comment in rest {
+        out += &format!("{comment}\n");
+    }
+    Ok(out)
+}
+
+/// Parses `input` into statements as written, with their spans. Uses of extern ops become
+/// verbatim expressions, and must have the arguments and results they were declared with. A `let`
+/// of an operation this crate knows must bind as many variables as the operation returns.
+fn parse_borrowed(input: &str) -> Result<Vec<Commented<'_, Span>>, ParseError> {
+    let statements = file.parse(input)
+        .map_err(|e| ParseError::Syntax { offset: e.offset(), message: e.to_string() })?;
+    check(input, statements)
+}
+
+/// Checks the statements parsed from `input` against the ops they use, that a `takes` comes first
+/// and a `returns` last, and replaces their text with their span.
+fn check<'a>(input: &str, statements: Vec<Commented<'a, &'a str>>) -> Result<Vec<Commented<'a, Span>>, ParseError> {
+    let mut externs = HashMap::new();
+    let mut started = false;
+    let mut returned = false;
+    statements.into_iter()
+        .map(|(comments, mut s, text)| {
+            let offset = text.as_ptr() as usize - input.as_ptr() as usize;
+            if let Parsed::Takes(_) = s {
+                if started {
+                    return Err(ParseError::Syntax { offset, message: "takes must come before the other statements".to_string() });
+                }
+            }
+            if returned {
+                return Err(ParseError::Syntax { offset, message: "returns must come after the other statements".to_string() });
+            }
+            returned = matches!(s, Parsed::Returns(_));
+            started |= !matches!(s, Parsed::Extern { .. });
+            match &mut s {
+                Parsed::Extern { name, takes, returns, bytes } => {
+                    externs.insert(name.clone(), (*takes as usize, *returns as usize, bytes.clone()));
+                }
+                Parsed::Statement(Statement(vs, e @ Expression::Op(..))) | Parsed::Tuple(Statement(vs, e @ Expression::Op(..))) => {
+                    let Expression::Op(op, args) = e else { unreachable!() };
+                    if let Some((takes, returns, bytes)) = externs.get(op) {
+                        if args.len() != *takes {
+                            return Err(ParseError::Arguments { offset, op: op.clone(), expected: *takes, found: args.len() });
+                        }
+                        if vs.len() != *returns {
+                            return Err(ParseError::Arity { offset, op: op.clone(), expected: *returns, found: vs.len() });
+                        }
+                        *e = Expression::Verbatim(op.clone(), bytes.clone(), std::mem::take(args));
+                    } else if op == "alloc" {
+                        if args.len() != 1 {
+                            return Err(ParseError::Arguments { offset, op: op.clone(), expected: 1, found: args.len() });
+                        }
+                        if vs.len() != 1 {
+                            return Err(ParseError::Arity { offset, op: op.clone(), expected: 1, found: vs.len() });
+                        }
+                    } else if let Ok(known) = op.parse::<DataInstruction>() {
+                        let expected = known.arity().1;
+                        if !vs.is_empty() && vs.len() != expected {
+                            return Err(ParseError::Arity { offset, op: op.clone(), expected, found: vs.len() });
+                        }
+                    }
+                }
+                _ => {}
+            }
+            Ok((comments, s, offset..offset + text.len()))
+        })
+        .collect()
+}
+
+/// Desugars the statements into a block, naming variables by `name`. A log becomes the `log0` to
+/// `log4` for its number of topics, which takes the offset and size of the data before them. A
+/// select becomes a branchless computation through temporaries `var%0`, `var%1`, ..., which can't
+/// clash with names in the source: `otherwise ^ ((then ^ otherwise) * (cond != 0))`. The start of
+/// the heap lies past every spill register, so codegen fills it in. A revert with a message writes
+/// its `Error(string)` encoding from there on, and `alloc n` bumps the free memory pointer at 0x40
+/// by `n`, after the first one sets it to the start of the heap.
+fn lower<V: Clone>(statements: Vec<Commented<'_, Span>>, mut name: impl FnMut(&str) -> V) -> Block<V> {
+    let mut block = Block(Vec::with_capacity(statements.len()), Vec::with_capacity(statements.len()), Vec::new());
+    // Whether the free memory pointer has been set to the start of the heap.
+    let mut heap = false;
+    for (comments, s, span) in statements {
+        block.2.extend(comments.into_iter().map(|c| (block.0.len(), c.to_string())));
+        match s {
+            Parsed::Statement(Statement(vs, Expression::Op(op, args))) | Parsed::Tuple(Statement(vs, Expression::Op(op, args)))
+                if op == "alloc" =>
+            {
+                let mut statements = Vec::new();
+                let op = |op: &str, args: Vec<V>| Expression::Op(op.to_string(), args);
+                let (pointer, start, end) = (name("alloc%0"), name("alloc%1"), name("alloc%2"));
+                statements.push(Statement(vec![pointer.clone()], Expression::Const(U256::from(0x40))));
+                if !heap {
+                    statements.push(Statement(vec![start.clone()], Expression::Immutable(HEAP_START.to_string())));
+                    statements.push(Statement(vec![], op("mstore", vec![pointer.clone(), start])));
+                    heap = true;
+                }
+                let (result, size) = (name(vs[0]), name(args[0]));
+                statements.push(Statement(vec![result.clone()], op("mload", vec![pointer.clone()])));
+                statements.push(Statement(vec![end.clone()], op("add", vec![result, size])));
+                statements.push(Statement(vec![], op("mstore", vec![pointer, end])));
+
+                block.1.extend(std::iter::repeat_n(span, statements.len()));
+                block.0.extend(statements);
+            }
+            Parsed::Statement(s) | Parsed::Tuple(s) => {
+                block.0.push(s.map(&mut name));
+                block.1.push(span);
+            }
+            Parsed::Extern { .. } => {}
+            Parsed::Takes(vs) => {
+                block.0.push(Statement(vs.into_iter().map(&mut name).collect(), Expression::Input));
+                block.1.push(span);
+            }
+            Parsed::Returns(vs) => {
+                block.0.push(Statement(vec![], Expression::Output(vs.into_iter().map(&mut name).collect())));
+                block.1.push(span);
+            }
+            Parsed::Hash { var, text, selector } => {
+                let hash = keccak256(text);
+                let value = if selector { U256::from_be_slice(&hash[..4]) } else { U256::from_be_bytes(hash.0) };
+                block.0.push(Statement(vec![name(var)], Expression::Const(value)));
+                block.1.push(span);
+            }
+            Parsed::Revert { reason } => {
+                let mut statements = Vec::new();
+                let mut temps = 0;
+                let mut define = |statements: &mut Vec<Statement<V>>, e: Expression<V>| {
+                    let v = name(&format!("revert%{temps}"));
+                    temps += 1;
+                    statements.push(Statement(vec![v.clone()], e));
+                    v
+                };
+                let op = |op: &str, args: Vec<V>| Expression::Op(op.to_string(), args);
+
+                // The selector in the last 4 bytes of the first word, then the offset of the
+                // string, its length, and its bytes padded to whole words.
+                let words = reason.as_bytes().chunks(32).map(|chunk| {
+                    let mut word = [0; 32];
+                    word[..chunk.len()].copy_from_slice(chunk);
+                    U256::from_be_bytes(word)
+                });
+                let words: Vec<_> = [U256::from(0x08c379a0), U256::from(32), U256::from(reason.len())].into_iter().chain(words).collect();
+
+                let base = define(&mut statements, Expression::Immutable(HEAP_START.to_string()));
+                for (i, word) in words.iter().enumerate() {
+                    let address = match i {
+                        0 => base.clone(),
+                        _ => {
+                            let offset = define(&mut statements, Expression::Const(U256::from(32 * i)));
+                            define(&mut statements, op("add", vec![base.clone(), offset]))
+                        }
+                    };
+                    let value = define(&mut statements, Expression::Const(*word));
+                    statements.push(Statement(vec![], op("mstore", vec![address, value])));
+                }
+                let offset = define(&mut statements, Expression::Const(U256::from(28)));
+                let start = define(&mut statements, op("add", vec![base, offset]));
+                let size = define(&mut statements, Expression::Const(U256::from(32 * words.len() - 28)));
+                statements.push(Statement(vec![], op("revert", vec![start, size])));
+
+                block.1.extend(std::iter::repeat_n(span, statements.len()));
+                block.0.extend(statements);
+            }
+            Parsed::Log { topics, offset, size } => {
+                let args = [offset, size].into_iter().chain(topics.iter().copied()).map(&mut name).collect();
+                block.0.push(Statement(vec![], Expression::Op(format!("log{}", topics.len()), args)));
+                block.1.push(span);
+            }
+            Parsed::Select { var, cond, then, otherwise } => {
+                let t: Vec<_> = (0..4).map(|i| name(&format!("{var}%{i}"))).collect();
+                let (cond, then, otherwise) = (name(cond), name(then), name(otherwise));
+                let op = |op: &str, args: Vec<V>| Expression::Op(op.to_string(), args);
+                let statements = [
+                    Statement(vec![t[0].clone()], op("iszero", vec![cond])),
+                    Statement(vec![t[1].clone()], op("iszero", vec![t[0].clone()])),
+                    Statement(vec![t[2].clone()], op("xor", vec![then, otherwise.clone()])),
+                    Statement(vec![t[3].clone()], op("mul", vec![t[2].clone(), t[1].clone()])),
+                    Statement(vec![name(var)], op("xor", vec![otherwise, t[3].clone()])),
+                ];
+                for s in statements {
+                    block.0.push(s);
+                    block.1.push(span.clone());
+                }
+            }
+        }
+    }
+    block
+}
+
+pub fn parse(input: &str) -> Result<Block<String>, ParseError> {
+    Ok(lower(parse_borrowed(input)?, |name: &str| name.to_string()))
+}
+
+/// Whether `input` is a contract, made of functions rather than statements.
+pub fn is_contract(input: &str) -> bool {
+    (keyword("function"), token!(String(_))).parse_next(&mut &*input).is_ok()
+}
+
+/// Parses the functions of a contract, each with its signature and its body. Spans are offsets
+/// in the whole of `input`, and extern ops are declared in the function that uses them. Functions
+/// read their arguments from the calldata, and can't use `takes` or `returns`.
+pub fn parse_contract(input: &str) -> Result<Vec<(String, Block<String>)>, ParseError> {
+    let functions: Vec<_> = terminated(repeat(1.., function), ws).parse(input)
+        .map_err(|e| ParseError::Syntax { offset: e.offset(), message: e.to_string() })?;
+    functions.into_iter()
+        .map(|(signature, statements)| {
+            if let Some((_, _, text)) = statements.iter().find(|(_, s, _)| matches!(s, Parsed::Takes(_) | Parsed::Returns(_))) {
+                let offset = text.as_ptr() as usize - input.as_ptr() as usize;
+                let message = "functions read their arguments from the calldata, so they can't use takes or returns".to_string();
+                return Err(ParseError::Syntax { offset, message });
+            }
+            Ok((signature.to_string(), lower(check(input, statements)?, |name: &str| name.to_string())))
+        })
+        .collect()
 }
 
-pub fn parse(ref mut input: &str) -> eyre::Result<Block<String>> {
-    file.parse(input).map_err(|e| eyre!("parser error: {e}"))
+/// Parses `input` with its variable names interned in `interner`, allocating once per distinct
+/// name rather than once per occurrence.
+pub fn parse_interned(input: &str, interner: &mut Interner) -> Result<Block<Symbol>, ParseError> {
+    Ok(lower(parse_borrowed(input)?, |name| interner.intern(name)))
 }
@@ -0,0 +1,126 @@
+//! Local rewrites of instruction sequences. [`minimize_swaps`] runs on the code the scheduler
+//! generates, and [`optimize`] applies every rule to any sequence, such as disassembled bytecode
+//! or assembly parsed with [`InstructionSeq`]'s `FromStr`.
+
+use crate::codegen::Fuel;
+use crate::evm::{DataInstruction, Instruction, InstructionSeq, StackInstruction};
+
+fn swap_depth(instr: &Instruction) -> Option<usize> {
+    match *instr {
+        Instruction::Stack(StackInstruction::Swap(depth)) => Some(depth),
+        _ => None,
+    }
+}
+
+/// Finds a shortest sequence of swaps with the same effect as `chain`.
+fn minimal_swaps(chain: &[usize]) -> Vec<usize> {
+    let size = chain.iter().max().map_or(0, |d| d + 1);
+
+    let mut target: Vec<usize> = (0..size).collect();
+    for &depth in chain {
+        target.swap(0, depth);
+    }
+
+    let mut state: Vec<usize> = (0..size).collect();
+    let mut swaps = Vec::new();
+
+    loop {
+        // Send the top item straight to where it belongs, or if it is already in place, bring up
+        // any item that is not.
+        let dest = target.iter().position(|&x| x == state[0]).unwrap();
+        let depth =
+            if dest != 0 {
+                dest
+            } else if let Some(depth) = (1..size).find(|&d| state[d] != target[d]) {
+                depth
+            } else {
+                break;
+            };
+        state.swap(0, depth);
+        swaps.push(depth);
+    }
+
+    swaps
+}
+
+/// Replaces every run of consecutive swaps with a minimal one implementing the same permutation.
+/// `origins` is kept parallel to `code`; a rewritten run takes the origin of its first swap. Each
+/// run that changes uses up a unit of `fuel`, and is kept as it is without any.
+pub(crate) fn minimize_swaps(code: &mut Vec<Instruction>, origins: &mut Vec<usize>, fuel: &mut Fuel) {
+    let old_code = std::mem::take(code);
+    let old_origins = std::mem::take(origins);
+
+    let mut i = 0;
+    while i < old_code.len() {
+        let chain: Vec<usize> = old_code[i..].iter().map_while(swap_depth).collect();
+
+        if chain.is_empty() {
+            code.push(old_code[i].clone());
+            origins.push(old_origins[i]);
+            i += 1;
+        } else {
+            let minimal = minimal_swaps(&chain);
+            let swaps = if minimal != chain && fuel.consume("minimize swaps") { minimal } else { chain.clone() };
+            for depth in swaps {
+                code.push(Instruction::Stack(StackInstruction::Swap(depth)));
+                origins.push(old_origins[i]);
+            }
+            i += chain.len();
+        }
+    }
+}
+
+/// Whether `instr` pushes one value and has no other effect.
+fn pushes_value(instr: &Instruction) -> bool {
+    matches!(instr, Instruction::Stack(StackInstruction::Push(_) | StackInstruction::Dup(_)) | Instruction::Immutable(_))
+}
+
+/// Whether removing instructions around `instr` could change what it does, because it depends on
+/// code offsets or its effect on the stack is unknown.
+fn depends_on_layout(instr: &Instruction) -> bool {
+    matches!(
+        instr,
+        Instruction::Data(DataInstruction::Codesize | DataInstruction::Codecopy)
+            | Instruction::Unknown(_) | Instruction::Truncated(_) | Instruction::Verbatim(..)
+    )
+}
+
+/// Removes the first pair of adjacent instructions that has no effect on the stack or memory: a
+/// push or dup followed by a pop, `dup1 swap1`, or `swap1` before a commutative operation.
+fn remove_pair(code: &mut Vec<Instruction>) -> bool {
+    use DataInstruction::*;
+    use StackInstruction::*;
+
+    for i in 0..code.len().saturating_sub(1) {
+        let removed = match (&code[i], &code[i + 1]) {
+            (a, Instruction::Data(Pop)) if pushes_value(a) => i..i + 2,
+            (Instruction::Stack(Dup(0)), Instruction::Stack(Swap(1))) => i + 1..i + 2,
+            (Instruction::Stack(Swap(1)), Instruction::Data(Add | Mul | Eq | Xor)) => i..i + 1,
+            _ => continue,
+        };
+        code.drain(removed);
+        return true;
+    }
+    false
+}
+
+/// Applies the peephole rules to `seq` until none applies: pairs with no effect are removed, and
+/// runs of swaps are minimized. Sequences whose behavior depends on where their instructions end
+/// up, because they read the code or contain bytes not decoded as instructions such as dynamic
+/// jumps, are left as they are. Returns the number of instructions removed.
+pub fn optimize(seq: &mut InstructionSeq) -> usize {
+    if seq.iter().any(depends_on_layout) {
+        return 0;
+    }
+    let len = seq.len();
+    let mut code = std::mem::take(seq).into_inner();
+    loop {
+        let mut origins = vec![0; code.len()];
+        minimize_swaps(&mut code, &mut origins, &mut Fuel::new(None));
+        if !remove_pair(&mut code) {
+            break;
+        }
+    }
+    *seq = code.into();
+    len - seq.len()
+}
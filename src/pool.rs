@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+
+use alloy_primitives::U256;
+
+use crate::codegen::Fuel;
+use crate::evm::{DataInstruction, EvmVersion, Instruction, StackInstruction};
+
+fn push(c: U256) -> Instruction {
+    Instruction::Stack(StackInstruction::Push(c.into()))
+}
+
+fn size(code: &[Instruction], version: EvmVersion) -> usize {
+    code.iter().map(|i| i.size(&HashMap::new(), version)).sum()
+}
+
+/// The constants pushed in `code` with the number of pushes of each, in order of first push.
+fn pushes(code: &[Instruction]) -> Vec<(U256, usize)> {
+    let mut uses: Vec<(U256, usize)> = Vec::new();
+    for instr in code {
+        if let Instruction::Stack(StackInstruction::Push(c)) = instr {
+            match uses.iter_mut().find(|(d, _)| *d == c.value()) {
+                Some((_, n)) => *n += 1,
+                None => uses.push((c.value(), 1)),
+            }
+        }
+    }
+    uses
+}
+
+/// Loads word `offset` of the code onto the stack, going through memory at `scratch`.
+fn load(offset: usize, scratch: U256) -> [Instruction; 6] {
+    [
+        push(U256::from(32)),
+        push(U256::from(offset)),
+        push(scratch),
+        Instruction::Data(DataInstruction::Codecopy),
+        push(scratch),
+        Instruction::Data(DataInstruction::Mload),
+    ]
+}
+
+/// Moves constants pushed repeatedly into a pool of words appended to the code after a `stop`, and
+/// replaces their pushes with loads through the memory word at `scratch`. A constant is pooled when
+/// that saves at least `threshold` bytes, and uses up a unit of `fuel`. Returns whether any constant
+/// was pooled.
+pub fn pool_constants(code: &mut Vec<Instruction>, origins: &mut Vec<usize>, scratch: U256, threshold: usize, version: EvmVersion, fuel: &mut Fuel) -> bool {
+    let uses = pushes(code);
+
+    // Loads are sized assuming code shorter than 64 KiB.
+    let load_size = size(&load(0xffff, scratch), version);
+    let pooled: Vec<U256> = uses.into_iter()
+        .filter(|&(c, n)| {
+            let push_size = 1 + version.push_width(&c);
+            (n * push_size).saturating_sub(n * load_size + 32) >= threshold.max(1) && fuel.consume("pool constant")
+        })
+        .map(|(c, _)| c)
+        .collect();
+    if pooled.is_empty() {
+        return false;
+    }
+
+    let data: Vec<u8> = pooled.iter().flat_map(|c| c.to_be_bytes::<32>()).collect();
+    let last_origin = origins.last().copied().unwrap_or(0);
+
+    // The pool starts where the code ends, which depends on the widths of the offsets pushed.
+    let mut start = 0;
+    loop {
+        let mut new_code = Vec::new();
+        let mut new_origins = Vec::new();
+        for (instr, &origin) in code.iter().zip(origins.iter()) {
+            match instr {
+                Instruction::Stack(StackInstruction::Push(c)) if pooled.contains(&c.value()) => {
+                    let index = pooled.iter().position(|d| *d == c.value()).unwrap();
+                    new_code.extend(load(start + 32 * index, scratch));
+                    new_origins.extend([origin; 6]);
+                }
+                _ => {
+                    new_code.push(instr.clone());
+                    new_origins.push(origin);
+                }
+            }
+        }
+        new_code.push(Instruction::Data(DataInstruction::Stop));
+        new_origins.push(last_origin);
+
+        let size = size(&new_code, version);
+        if size == start {
+            new_code.push(Instruction::Verbatim(data.into(), 0, 0));
+            new_origins.push(last_origin);
+            *code = new_code;
+            *origins = new_origins;
+            return true;
+        }
+        start = size;
+    }
+}
+
+/// Stores constants pushed repeatedly into the memory registers from `first` on at the start of the
+/// code, and replaces their pushes with loads from them. A constant gets a register when that saves
+/// at least `threshold` bytes, and uses up a unit of `fuel`. Returns the number of registers used.
+pub fn register_constants(code: &mut Vec<Instruction>, origins: &mut Vec<usize>, first: U256, threshold: usize, version: EvmVersion, fuel: &mut Fuel) -> usize {
+    let mut registers: Vec<(U256, U256)> = Vec::new();
+    for (c, n) in pushes(code) {
+        let address = first + U256::from(32 * registers.len());
+        let load_size = size(&[push(address), Instruction::Data(DataInstruction::Mload)], version);
+        let store_size = size(&[push(c), push(address), Instruction::Data(DataInstruction::Mstore)], version);
+        let push_size = 1 + version.push_width(&c);
+        if (n * push_size).saturating_sub(n * load_size + store_size) >= threshold.max(1) && fuel.consume("keep constant in register") {
+            registers.push((c, address));
+        }
+    }
+    if registers.is_empty() {
+        return 0;
+    }
+
+    let first_origin = origins.first().copied().unwrap_or(0);
+    let mut new_code = Vec::new();
+    let mut new_origins = Vec::new();
+    for &(c, address) in &registers {
+        new_code.extend([push(c), push(address), Instruction::Data(DataInstruction::Mstore)]);
+        new_origins.extend([first_origin; 3]);
+    }
+    for (instr, &origin) in code.iter().zip(origins.iter()) {
+        match instr {
+            Instruction::Stack(StackInstruction::Push(c)) if registers.iter().any(|&(d, _)| d == c.value()) => {
+                let &(_, address) = registers.iter().find(|&&(d, _)| d == c.value()).unwrap();
+                new_code.extend([push(address), Instruction::Data(DataInstruction::Mload)]);
+                new_origins.extend([origin; 2]);
+            }
+            _ => {
+                new_code.push(instr.clone());
+                new_origins.push(origin);
+            }
+        }
+    }
+    *code = new_code;
+    *origins = new_origins;
+    registers.len()
+}
@@ -1,13 +1,146 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::ops::Range;
+
 use alloy_primitives::U256;
 
 #[derive(Debug, Clone)]
 pub enum Expression<V> {
     Const(U256),
     Op(String, Vec<V>),
+    /// A value filled in after compilation, such as an address known at deployment.
+    Immutable(String),
+    /// An operation declared `extern`, with its name and the bytes it assembles to. It takes the
+    /// arguments and returns as many values as the statement binds.
+    Verbatim(String, Vec<u8>, Vec<V>),
+    /// The values on the stack when the program starts, bound by a first statement `takes (a, b);`
+    /// with the first on top.
+    Input,
+    /// The values to leave on the stack when the program ends and nothing else, returned by a last
+    /// statement `returns (x, y);` with the first on top.
+    Output(Vec<V>),
 }
 
 #[derive(Debug)]
 pub struct Statement<V>(pub Vec<V>, pub Expression<V>);
 
+/// Byte range of a statement in the source text.
+pub type Span = Range<usize>;
+
+/// Statements along with their spans, which are either one per statement or absent, and the
+/// source comments attached to the statement after them, by index of that statement.
 #[derive(Debug)]
-pub struct Block<V>(pub Vec<Statement<V>>);
+pub struct Block<V>(pub Vec<Statement<V>>, pub Vec<Span>, pub Vec<(usize, String)>);
+
+impl<V> Expression<V> {
+    pub fn map<W>(self, f: impl FnMut(V) -> W) -> Expression<W> {
+        match self {
+            Expression::Const(c) => Expression::Const(c),
+            Expression::Op(op, args) => Expression::Op(op, args.into_iter().map(f).collect()),
+            Expression::Immutable(name) => Expression::Immutable(name),
+            Expression::Verbatim(name, bytes, args) => Expression::Verbatim(name, bytes, args.into_iter().map(f).collect()),
+            Expression::Input => Expression::Input,
+            Expression::Output(args) => Expression::Output(args.into_iter().map(f).collect()),
+        }
+    }
+}
+
+impl<V> Statement<V> {
+    pub fn map<W>(self, mut f: impl FnMut(V) -> W) -> Statement<W> {
+        let Statement(vs, e) = self;
+        let e = e.map(&mut f);
+        Statement(vs.into_iter().map(f).collect(), e)
+    }
+}
+
+impl<V> Block<V> {
+    /// The same block with each variable replaced by `f` of it.
+    pub fn map<W>(self, mut f: impl FnMut(V) -> W) -> Block<W> {
+        let Block(ss, spans, comments) = self;
+        Block(ss.into_iter().map(|s| s.map(&mut f)).collect(), spans, comments)
+    }
+}
+
+/// Identifier interned in an [`Interner`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Symbol(u32);
+
+impl Symbol {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// Table of the distinct identifiers of a program, each stored once.
+#[derive(Debug, Default)]
+pub struct Interner {
+    names: Vec<String>,
+    symbols: HashMap<String, Symbol>,
+}
+
+impl Interner {
+    pub fn intern(&mut self, name: &str) -> Symbol {
+        if let Some(&symbol) = self.symbols.get(name) {
+            return symbol;
+        }
+        let symbol = Symbol(self.names.len().try_into().unwrap());
+        self.names.push(name.to_string());
+        self.symbols.insert(name.to_string(), symbol);
+        symbol
+    }
+
+    pub fn name(&self, symbol: Symbol) -> &str {
+        &self.names[symbol.index()]
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+}
+
+impl<V: Display> Display for Expression<V> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Expression::Const(c) => write!(f, "const {c}"),
+            Expression::Immutable(name) => write!(f, "immutable {name}"),
+            Expression::Input => write!(f, "input"),
+            Expression::Output(args) => {
+                let args: Vec<_> = args.iter().map(|a| a.to_string()).collect();
+                write!(f, "returns ({})", args.join(", "))
+            }
+            Expression::Op(op, args) | Expression::Verbatim(op, _, args) => {
+                write!(f, "{op}")?;
+                for a in args {
+                    write!(f, " {a}")?;
+                }
+                std::fmt::Result::Ok(())
+            }
+        }
+    }
+}
+
+impl<V: Display> Display for Statement<V> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Statement(vs, e) = self;
+        match e {
+            Expression::Input => {
+                let vs: Vec<_> = vs.iter().map(|v| v.to_string()).collect();
+                return write!(f, "takes ({});", vs.join(", "));
+            }
+            Expression::Output(_) => return write!(f, "{e};"),
+            _ => (),
+        }
+        if let Some((first, rest)) = vs.split_first() {
+            write!(f, "let {first}")?;
+            for v in rest {
+                write!(f, ", {v}")?;
+            }
+            write!(f, " = ")?;
+        }
+        write!(f, "{e};")
+    }
+}
@@ -1,70 +1,188 @@
-use eyre::{eyre, Result, Ok};
-use std::{borrow::Borrow, collections::HashMap, fmt::Display, hash::Hash};
+use std::fmt::Display;
 
 use crate::program::*;
 
-#[derive(PartialEq, Eq, Hash, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
 pub struct Var {
     index: u32,
 }
 
 impl Var {
+    /// An index no resolved program uses, for values introduced by the compiler.
+    pub const RESERVED: Var = Var { index: u32::MAX };
+
+    pub fn new(index: usize) -> Var {
+        Var { index: index.try_into().unwrap() }
+    }
+
     pub fn index(&self) -> usize {
         self.index.try_into().unwrap()
     }
 }
 
-struct Env(HashMap<String, Var>);
+impl Display for Var {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "v{}", self.index)
+    }
+}
+
+/// The variable each symbol currently refers to.
+struct Env(Vec<Option<Var>>);
 
 impl Env {
-    fn new() -> Self {
-        Env(HashMap::new())
+    fn new(symbols: usize) -> Self {
+        Env(vec![None; symbols])
     }
 
-    fn get(&self, name: impl Borrow<String>) -> Result<Var> {
-        let key = name.borrow();
-        if let Some(&vi) = self.0.get(key) {
-            Ok(vi)
-        } else {
-            Err(eyre!("Unknown variable: {key}"))
-        }
+    fn get(&self, name: Symbol) -> Option<Var> {
+        self.0[name.index()]
     }
 
-    fn insert(&mut self, name: String, value: Var) {
-        self.0.insert(name, value);
+    fn insert(&mut self, name: Symbol, value: Var) {
+        self.0[name.index()] = Some(value);
     }
 }
 
+#[derive(Debug, thiserror::Error)]
+pub enum ResolveError {
+    #[error("Unknown variable: {name}")]
+    UnknownVariable { name: String, statement: usize, span: Option<Span> },
+}
+
 pub struct ResolvedBlock {
     pub block: Block<Var>,
     pub var_count: usize,
+    pub names: Vec<String>,
+    pub flat: FlatBlock,
+}
+
+/// The variables each statement defines and uses, in one vector indexed by statement, for passes
+/// that only follow variables and would otherwise chase a few allocations per statement.
+#[derive(Debug, Default)]
+pub struct FlatBlock {
+    /// The results and then the arguments of each statement, back to back.
+    vars: Vec<Var>,
+    /// Where the results and the arguments of each statement start in `vars`.
+    starts: Vec<(u32, u32)>,
+}
+
+impl FlatBlock {
+    fn push(&mut self, results: &[Var], args: &[Var]) {
+        let start: u32 = self.vars.len().try_into().unwrap();
+        self.starts.push((start, start + results.len() as u32));
+        self.vars.extend_from_slice(results);
+        self.vars.extend_from_slice(args);
+    }
+
+    fn end(&self, statement: usize) -> usize {
+        self.starts.get(statement + 1).map_or(self.vars.len(), |&(start, _)| start as usize)
+    }
+
+    pub fn len(&self) -> usize {
+        self.starts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.starts.is_empty()
+    }
+
+    pub fn results(&self, statement: usize) -> &[Var] {
+        let (start, args) = self.starts[statement];
+        &self.vars[start as usize..args as usize]
+    }
+
+    pub fn args(&self, statement: usize) -> &[Var] {
+        &self.vars[self.starts[statement].1 as usize..self.end(statement)]
+    }
+
+    /// The results and arguments of each statement.
+    pub fn iter(&self) -> impl Iterator<Item = (&[Var], &[Var])> {
+        (0..self.len()).map(|i| (self.results(i), self.args(i)))
+    }
 }
 
-pub fn resolve(Block(ss): Block<String>) -> Result<ResolvedBlock> {
-    let mut env = Env::new();
+impl ResolvedBlock {
+    pub fn name(&self, v: Var) -> &str {
+        &self.names[v.index()]
+    }
+
+    pub fn named_statement(&self, index: usize) -> Statement<&str> {
+        let Statement(vs, e) = &self.block.0[index];
+        let e = match e {
+            Expression::Const(c) => Expression::Const(*c),
+            Expression::Immutable(name) => Expression::Immutable(name.clone()),
+            Expression::Input => Expression::Input,
+            Expression::Output(args) => Expression::Output(args.iter().map(|&x| self.name(x)).collect()),
+            Expression::Op(op, args) => Expression::Op(op.clone(), args.iter().map(|&x| self.name(x)).collect()),
+            Expression::Verbatim(name, bytes, args) => {
+                Expression::Verbatim(name.clone(), bytes.clone(), args.iter().map(|&x| self.name(x)).collect())
+            }
+        };
+        Statement(vs.iter().map(|&v| self.name(v)).collect(), e)
+    }
+
+    /// The variables left on the stack when the program ends, the first on top.
+    pub fn outputs(&self) -> &[Var] {
+        match self.block.0.last() {
+            Some(Statement(_, Expression::Output(args))) => args,
+            _ => &[],
+        }
+    }
+
+    /// The variables on the stack when the program starts, the first on top.
+    pub fn inputs(&self) -> &[Var] {
+        match self.block.0.first() {
+            Some(Statement(vs, Expression::Input)) => vs,
+            _ => &[],
+        }
+    }
+}
+
+pub fn resolve(block: Block<String>) -> Result<ResolvedBlock, ResolveError> {
+    let mut interner = Interner::default();
+    let block = block.map(|name| interner.intern(&name));
+    resolve_interned(block, &interner)
+}
+
+pub fn resolve_interned(Block(ss, spans, comments): Block<Symbol>, interner: &Interner) -> Result<ResolvedBlock, ResolveError> {
+    let mut env = Env::new(interner.len());
     let mut i: u32 = 0;
+    let mut names = Vec::new();
+    let mut flat = FlatBlock::default();
 
-    let ss = ss.into_iter().map(|Statement(vs, e)| {
+    let ss = ss.into_iter().enumerate().map(|(statement, Statement(vs, e))| {
+        let resolve_args = |args: Vec<Symbol>| {
+            args.into_iter()
+                .map(|name| env.get(name).ok_or_else(|| {
+                    let name = interner.name(name).to_string();
+                    ResolveError::UnknownVariable { span: spans.get(statement).cloned(), name, statement }
+                }))
+                .collect::<Result<_, _>>()
+        };
         let e = match e {
             Expression::Const(c) => Expression::Const(c),
-
-            Expression::Op(op, args) => {
-                Expression::Op(
-                    op,
-                    args.into_iter().map(|x| env.get(x)).collect::<Result<_>>()?,
-                )
-            }
+            Expression::Immutable(name) => Expression::Immutable(name),
+            Expression::Input => Expression::Input,
+            Expression::Output(args) => Expression::Output(resolve_args(args)?),
+            Expression::Op(op, args) => Expression::Op(op, resolve_args(args)?),
+            Expression::Verbatim(name, bytes, args) => Expression::Verbatim(name, bytes, resolve_args(args)?),
         };
 
-        let vs = vs.into_iter().map(|v| {
+        let vs: Vec<_> = vs.into_iter().map(|v| {
             let vi = Var { index: i };
             i += 1;
+            names.push(interner.name(v).to_string());
             env.insert(v, vi);
             vi
         }).collect();
 
+        let args = match &e {
+            Expression::Op(_, args) | Expression::Verbatim(_, _, args) | Expression::Output(args) => &args[..],
+            _ => &[],
+        };
+        flat.push(&vs, args);
         Ok(Statement(vs, e))
-    }).collect::<Result<_>>()?;
+    }).collect::<Result<_, _>>()?;
 
-    Ok(ResolvedBlock { block: Block(ss), var_count: i.try_into().unwrap() })
+    Ok(ResolvedBlock { block: Block(ss, spans, comments), var_count: i.try_into().unwrap(), names, flat })
 }
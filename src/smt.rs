@@ -0,0 +1,350 @@
+//! Proves generated code equivalent to its program. Both are encoded as SMT-LIB formulas over
+//! 256-bit bitvectors, with memory as an array of bytes, and an external solver such as `z3 -in`
+//! is asked for an input on which they differ.
+
+use std::collections::BTreeMap;
+use std::fmt::{Display, Write as _};
+use std::io::Write as _;
+use std::process::{Command, Stdio};
+
+use alloy_primitives::U256;
+use eyre::{bail, ensure, eyre, Result, WrapErr};
+
+use crate::codegen::HEAP_START;
+use crate::evm::{ControlInstruction, DataInstruction, Instruction, StackInstruction};
+use crate::program::{Expression, Statement};
+use crate::scope::Var;
+use crate::{analysis, Output};
+
+const WORD: &str = "(_ BitVec 256)";
+const MEMORY: &str = "(Array (_ BitVec 256) (_ BitVec 8))";
+const STORAGE: &str = "(Array (_ BitVec 256) (_ BitVec 256))";
+
+#[derive(Clone)]
+enum Term {
+    Const(U256),
+    Name(String),
+}
+
+impl Display for Term {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Term::Const(c) => write!(f, "(_ bv{c} 256)"),
+            Term::Name(name) => write!(f, "{name}"),
+        }
+    }
+}
+
+fn word(n: usize) -> Term {
+    Term::Const(U256::from(n))
+}
+
+fn constant(term: &Term, what: &str) -> Result<usize> {
+    match term {
+        Term::Const(c) => (*c).try_into().map_err(|_| eyre!("{what} {c} out of range")),
+        Term::Name(_) => bail!("cannot encode a {what} that isn't constant"),
+    }
+}
+
+/// The script being built, shared by both executions.
+struct Script {
+    text: String,
+    next: usize,
+    immutables: BTreeMap<String, String>,
+}
+
+impl Script {
+    fn define(&mut self, sort: &str, body: String) -> String {
+        let name = format!("t{}", self.next);
+        self.next += 1;
+        writeln!(self.text, "(define-fun {name} () {sort} {body})").unwrap();
+        name
+    }
+
+    fn immutable(&mut self, name: &str) -> Term {
+        let count = self.immutables.len();
+        let symbol = self.immutables.entry(name.to_string()).or_insert_with(|| format!("immutable{count}")).clone();
+        Term::Name(symbol)
+    }
+}
+
+/// Symbolic state of one execution.
+struct State {
+    memory: String,
+    transient: String,
+    /// Offset and size of the returned data, or `None` when halted by `stop`.
+    halted: Option<Option<(Term, Term)>>,
+    /// Memory ranges accessed, as offset and size.
+    accesses: Vec<(Term, Term)>,
+    code: Vec<u8>,
+}
+
+impl State {
+    fn new(code: Vec<u8>) -> State {
+        State { memory: "m0".to_string(), transient: "s0".to_string(), halted: None, accesses: Vec::new(), code }
+    }
+
+    fn store(&mut self, script: &mut Script, writes: impl IntoIterator<Item = (Term, String)>) {
+        let mut body = self.memory.clone();
+        for (offset, byte) in writes {
+            body = format!("(store {body} {offset} {byte})");
+        }
+        self.memory = script.define(MEMORY, body);
+    }
+
+    fn apply(&mut self, script: &mut Script, op: DataInstruction, args: &[Term]) -> Result<Vec<Term>> {
+        use DataInstruction::*;
+
+        let (inputs, _) = op.arity();
+        ensure!(args.len() == inputs, "wrong number of arguments to {}", op.name());
+        let at = |base: &Term, i: usize| match base {
+            Term::Const(c) => Term::Const(c.wrapping_add(U256::from(i))),
+            _ => Term::Name(format!("(bvadd {base} {})", word(i))),
+        };
+
+        let results = match op {
+            Stop => {
+                self.halted = Some(None);
+                vec![]
+            }
+            Add => match (&args[0], &args[1]) {
+                (Term::Const(a), Term::Const(b)) => vec![Term::Const(a.wrapping_add(*b))],
+                (a, b) => vec![Term::Name(script.define(WORD, format!("(bvadd {a} {b})")))],
+            },
+            Mul => match (&args[0], &args[1]) {
+                (Term::Const(a), Term::Const(b)) => vec![Term::Const(a.wrapping_mul(*b))],
+                (a, b) => vec![Term::Name(script.define(WORD, format!("(bvmul {a} {b})")))],
+            },
+            Eq => match (&args[0], &args[1]) {
+                (Term::Const(a), Term::Const(b)) => vec![Term::Const(U256::from(a == b))],
+                (a, b) => vec![Term::Name(script.define(WORD, format!("(ite (= {a} {b}) {} {})", word(1), word(0))))],
+            },
+            Iszero => match &args[0] {
+                Term::Const(a) => vec![Term::Const(U256::from(a.is_zero()))],
+                a => vec![Term::Name(script.define(WORD, format!("(ite (= {a} {}) {} {})", word(0), word(1), word(0))))],
+            },
+            Xor => match (&args[0], &args[1]) {
+                (Term::Const(a), Term::Const(b)) => vec![Term::Const(a ^ b)],
+                (a, b) => vec![Term::Name(script.define(WORD, format!("(bvxor {a} {b})")))],
+            },
+            Shr => match (&args[0], &args[1]) {
+                (Term::Const(shift), Term::Const(value)) => {
+                    vec![Term::Const(value.checked_shr(shift.try_into().unwrap_or(usize::MAX)).unwrap_or_default())]
+                }
+                (shift, value) => vec![Term::Name(script.define(WORD, format!("(bvlshr {value} {shift})")))],
+            },
+            // The same calldata in both executions, as a symbol for each constant offset read.
+            Calldataload => match &args[0] {
+                Term::Const(offset) => vec![script.immutable(&format!("%calldata{offset}"))],
+                _ => bail!("calldataload at an offset that isn't constant"),
+            },
+            // Read the same environment in both executions, as symbols apart from the immutables.
+            Address | Origin | Caller | Callvalue | Calldatasize | Codesize | Gasprice | Returndatasize | Coinbase | Timestamp
+            | Number | Prevrandao | Gaslimit | Chainid | Selfbalance | Basefee | Blobbasefee | Gas => {
+                vec![script.immutable(&format!("%{}", op.name()))]
+            }
+            Msize => bail!("msize depends on the spill registers"),
+            Log0 | Log1 | Log2 | Log3 | Log4 | Revert => bail!("{} has no model", op.name()),
+            Pop => vec![],
+            Mload => {
+                self.accesses.push((args[0].clone(), word(32)));
+                let bytes = (0..32).rev().fold(String::new(), |rest, i| {
+                    let byte = format!("(select {} {})", self.memory, at(&args[0], i));
+                    if rest.is_empty() { byte } else { format!("(concat {byte} {rest})") }
+                });
+                vec![Term::Name(script.define(WORD, bytes))]
+            }
+            Mstore => {
+                self.accesses.push((args[0].clone(), word(32)));
+                let value = &args[1];
+                let writes = (0..32).map(|i| (at(&args[0], i), format!("((_ extract {} {}) {value})", 255 - 8 * i, 248 - 8 * i)));
+                self.store(script, writes.collect::<Vec<_>>());
+                vec![]
+            }
+            Tload => vec![Term::Name(script.define(WORD, format!("(select {} {})", self.transient, args[0])))],
+            Tstore => {
+                self.transient = script.define(STORAGE, format!("(store {} {} {})", self.transient, args[0], args[1]));
+                vec![]
+            }
+            Codecopy => {
+                let (offset, size) = (constant(&args[1], "code offset")?, constant(&args[2], "size")?);
+                self.accesses.push((args[0].clone(), args[2].clone()));
+                let bytes: Vec<_> = (0..size)
+                    .map(|i| (at(&args[0], i), format!("(_ bv{} 8)", self.code.get(offset + i).copied().unwrap_or(0))))
+                    .collect();
+                self.store(script, bytes);
+                vec![]
+            }
+            Mcopy => {
+                let size = constant(&args[2], "size")?;
+                self.accesses.push((args[0].clone(), args[2].clone()));
+                self.accesses.push((args[1].clone(), args[2].clone()));
+                let source = self.memory.clone();
+                let bytes: Vec<_> = (0..size).map(|i| (at(&args[0], i), format!("(select {source} {})", at(&args[1], i)))).collect();
+                self.store(script, bytes);
+                vec![]
+            }
+            Return => {
+                self.accesses.push((args[0].clone(), args[1].clone()));
+                self.halted = Some(Some((args[0].clone(), args[1].clone())));
+                vec![]
+            }
+        };
+        Ok(results)
+    }
+}
+
+/// The effects of the program, with the values it returns on the stack, the first on top.
+fn run_program(output: &Output, script: &mut Script) -> Result<(State, Vec<Term>)> {
+    let program = &output.program;
+    let mut state = State::new(output.bytecode.clone());
+    let mut values: Vec<Option<Term>> = vec![None; program.var_count];
+    let mut returned = Vec::new();
+    let offsets = analysis::constant_offsets(program);
+
+    for Statement(ress, e) in &program.block.0 {
+        if state.halted.is_some() {
+            break;
+        }
+        let value = |a: &Var| values[a.index()].clone().ok_or_else(|| eyre!("{} used before its definition", program.name(*a)));
+        let results = match e {
+            Expression::Const(c) => match output.code.memory_offset {
+                Some(offset) if offsets[ress[0].index()] => vec![Term::Const(offset.wrapping_add(*c))],
+                _ => vec![Term::Const(*c)],
+            },
+            Expression::Immutable(name) if name == HEAP_START => vec![Term::Const(output.code.heap_start)],
+            Expression::Immutable(name) => vec![script.immutable(name)],
+            Expression::Input => (0..ress.len()).map(|i| script.immutable(&format!("%input{i}"))).collect(),
+            Expression::Op(op, args) => {
+                let args = args.iter().map(value).collect::<Result<Vec<_>>>()?;
+                state.apply(script, op.parse()?, &args)?
+            }
+            Expression::Output(args) => {
+                returned = args.iter().map(value).collect::<Result<Vec<_>>>()?;
+                vec![]
+            }
+            Expression::Verbatim(name, ..) => bail!("extern op {name} has no model"),
+        };
+        ensure!(state.halted.is_some() || results.len() == ress.len(), "wrong number of results from {e}");
+        for (r, value) in ress.iter().zip(results) {
+            values[r.index()] = Some(value);
+        }
+    }
+    Ok((state, returned))
+}
+
+/// The effects of the generated code, with the stack it leaves, the top first.
+fn run_code(output: &Output, script: &mut Script) -> Result<(State, Vec<Term>)> {
+    let mut state = State::new(output.bytecode.clone());
+    // The inputs are named as in `run_program`, the first on top.
+    let mut stack: Vec<Term> = (0..output.program.inputs().len()).rev().map(|i| script.immutable(&format!("%input{i}"))).collect();
+
+    for instr in &output.code.instructions {
+        if state.halted.is_some() {
+            break;
+        }
+        let len = stack.len();
+        match instr {
+            Instruction::Stack(StackInstruction::Push(c)) => stack.push(Term::Const(c.value())),
+            Instruction::Stack(StackInstruction::Dup(depth)) => {
+                ensure!(*depth < len, "stack underflow at {instr}");
+                stack.push(stack[len - 1 - depth].clone());
+            }
+            Instruction::Stack(StackInstruction::Swap(depth)) => {
+                ensure!(*depth < len, "stack underflow at {instr}");
+                stack.swap(len - 1, len - 1 - depth);
+            }
+            Instruction::Data(op) => {
+                let (inputs, _) = op.arity();
+                ensure!(inputs <= len, "stack underflow at {instr}");
+                let args: Vec<_> = stack.drain(len - inputs..).rev().collect();
+                let results = state.apply(script, *op, &args)?;
+                stack.extend(results);
+            }
+            Instruction::Immutable(name) => stack.push(script.immutable(name)),
+            Instruction::Control(ControlInstruction::Jumpdest(_)) => (),
+            _ => bail!("cannot encode {instr}"),
+        }
+    }
+    stack.reverse();
+    Ok((state, stack))
+}
+
+/// Builds an SMT-LIB script that is unsatisfiable exactly when the generated code has the same
+/// effects as the program for every initial memory, transient storage and immutable values:
+/// the same memory outside the spill registers, transient storage and returned data, and the same
+/// values on the stack if the program returns any there.
+///
+/// The program is assumed not to access the spill registers itself.
+pub fn encode(output: &Output) -> Result<String> {
+    let mut script = Script { text: String::new(), next: 0, immutables: BTreeMap::new() };
+    let (expected, outputs) = run_program(output, &mut script)?;
+    let (actual, stack) = run_code(output, &mut script)?;
+
+    let mut header = format!("(set-logic QF_ABV)\n(declare-const m0 {MEMORY})\n(declare-const s0 {STORAGE})\n");
+    for symbol in script.immutables.values() {
+        writeln!(header, "(declare-const {symbol} {WORD})").unwrap();
+    }
+    let mut text = header + &script.text;
+
+    let code = &output.code;
+    let region = code.spill_region();
+    let (start, end) = (Term::Const(region.start), Term::Const(region.end));
+    let limit = word(1 << 32);
+    let outside = |offset: &Term, size: &Term| {
+        if code.register_count == 0 {
+            return "true".to_string();
+        }
+        format!("(or (bvule (bvadd {offset} {size}) {start}) (bvuge {offset} {end}))")
+    };
+    for (offset, size) in &expected.accesses {
+        writeln!(text, "(assert (and (bvult {offset} {limit}) (bvult {size} {limit}) {}))", outside(offset, size)).unwrap();
+    }
+
+    let returned = match (&expected.halted, &actual.halted) {
+        (Some(Some((o1, s1))), Some(Some((o2, s2)))) => format!("(not (and (= {o1} {o2}) (= {s1} {s2})))"),
+        (Some(None), Some(None)) => "false".to_string(),
+        (None, None) if outputs.is_empty() => "false".to_string(),
+        (None, None) => {
+            ensure!(stack.len() == outputs.len(), "the code leaves {} values on the stack instead of {}", stack.len(), outputs.len());
+            let equal: Vec<_> = outputs.iter().zip(&stack).map(|(a, b)| format!("(= {a} {b})")).collect();
+            format!("(not (and {}))", equal.join(" "))
+        }
+        _ => bail!("only one of the executions halts, or they halt differently"),
+    };
+    writeln!(text, "(declare-const address {WORD})\n(declare-const key {WORD})").unwrap();
+    writeln!(
+        text,
+        "(assert (or (and {} (not (= (select {} address) (select {} address)))) (not (= (select {} key) (select {} key))) {returned}))",
+        outside(&Term::Name("address".to_string()), &word(1)),
+        expected.memory,
+        actual.memory,
+        expected.transient,
+        actual.transient,
+    )
+    .unwrap();
+    text += "(check-sat)\n(get-model)\n";
+    Ok(text)
+}
+
+/// Proves the generated code equivalent to the program with `solver`, a command that reads an
+/// SMT-LIB script on stdin, such as `z3 -in` or `bitwuzla`.
+pub fn verify(output: &Output, solver: &str) -> Result<()> {
+    let script = encode(output)?;
+    let mut words = solver.split_whitespace();
+    let program = words.next().ok_or_else(|| eyre!("no solver given"))?;
+    let mut child = Command::new(program)
+        .args(words)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .wrap_err_with(|| format!("running {program}"))?;
+    child.stdin.take().unwrap().write_all(script.as_bytes())?;
+    let result = String::from_utf8_lossy(&child.wait_with_output()?.stdout).into_owned();
+
+    match result.lines().next().map(str::trim) {
+        Some("unsat") => Ok(()),
+        Some("sat") => bail!("generated code differs from the program, for example with:\n{}", result.split_once('\n').unwrap().1.trim()),
+        _ => bail!("solver gave no answer: {}", result.trim()),
+    }
+}
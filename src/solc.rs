@@ -0,0 +1,96 @@
+//! Comparison against solc's Yul stack allocator, which moves variables to memory when a block is
+//! too deep for the stack.
+
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use alloy_primitives::hex;
+use eyre::{bail, eyre, Result, WrapErr};
+
+use evm_spilling::evm::{self, DataInstruction, EvmVersion, Instruction};
+use evm_spilling::program::{Block, Expression, Statement};
+use evm_spilling::{compile, parser, Options};
+
+/// Translates a program into a Yul block. The block starts by setting the free memory pointer to a
+/// `memoryguard`, which allows solc to move variables to memory.
+pub fn to_yul(block: &Block<String>) -> Result<String> {
+    let mut out = "{\n    mstore(64, memoryguard(128))\n".to_string();
+    for Statement(vars, e) in &block.0 {
+        let vars: Vec<_> = vars.iter().map(|v| format!("v_{v}")).collect();
+        let value = match e {
+            Expression::Const(c) => c.to_string(),
+            Expression::Op(op, args) => {
+                let args: Vec<_> = args.iter().map(|a| format!("v_{a}")).collect();
+                format!("{op}({})", args.join(", "))
+            }
+            Expression::Verbatim(_, bytes, args) => {
+                let args: Vec<_> = args.iter().map(|a| format!(", v_{a}")).collect();
+                format!("verbatim_{}i_{}o(hex\"{}\"{})", args.len(), vars.len(), hex::encode(bytes), args.concat())
+            }
+            Expression::Immutable(name) => bail!("immutable {name} has no equivalent in a Yul block"),
+            Expression::Input | Expression::Output(_) => bail!("stack inputs and outputs have no equivalent in a Yul block"),
+        };
+        out += &match vars.is_empty() {
+            true if matches!(e, Expression::Const(_)) => format!("    pop({value})\n"),
+            true => format!("    {value}\n"),
+            false => format!("    let {} := {value}\n", vars.join(", ")),
+        };
+    }
+    out += "}\n";
+    Ok(out)
+}
+
+/// Runs `solc --strict-assembly` with the optimizer on `yul`, returning the bytecode.
+fn run_solc(solc: &Path, yul: &str, version: EvmVersion) -> Result<Vec<u8>> {
+    let mut child = Command::new(solc)
+        .args(["--strict-assembly", "--optimize", "--bin", "--evm-version", &version.to_string(), "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .wrap_err_with(|| format!("running {}", solc.display()))?;
+    child.stdin.take().unwrap().write_all(yul.as_bytes())?;
+    let output = child.wait_with_output()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    if !output.status.success() {
+        bail!("solc failed: {}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+
+    let mut lines = stdout.lines().skip_while(|line| !line.starts_with("Binary representation"));
+    let hex = lines.nth(1).ok_or_else(|| eyre!("no binary in solc output"))?;
+    hex::decode(hex.trim()).map_err(|e| eyre!("bad solc output: {e}"))
+}
+
+/// Gas for executing every instruction once, size in bytes, and memory accesses.
+fn measure(instructions: &[Instruction], size: usize, version: EvmVersion) -> (u64, usize, usize) {
+    let gas = instructions.iter().map(|i| i.gas_cost(version)).sum();
+    let memory = instructions.iter()
+        .filter(|i| matches!(i, Instruction::Data(DataInstruction::Mload | DataInstruction::Mstore)))
+        .count();
+    (gas, size, memory)
+}
+
+/// Compiles each program both ways and tabulates gas, size and memory accesses, where the extra
+/// accesses are spills and restores. Programs solc rejects are reported under the table row.
+pub fn compare(programs: &[(String, String)], solc: &Path, options: &Options) -> Result<String> {
+    let version = options.codegen.evm_version;
+    let mut out = format!("{:<24} {:>8} {:>8} {:>8} {:>8} {:>8} {:>8}\n", "program", "gas", "solc", "bytes", "solc", "memory", "solc");
+    for (name, source) in programs {
+        let output = compile(source, options)?;
+        let ours = measure(&output.code.instructions, output.bytecode.len(), version);
+        let theirs = to_yul(&parser::parse(source)?)
+            .and_then(|yul| run_solc(solc, &yul, version))
+            .map(|bytecode| measure(&evm::disassemble(&bytecode)[..], bytecode.len(), version));
+
+        let column = |f: fn((u64, usize, usize)) -> String| theirs.as_ref().map_or("-".to_string(), |t| f(*t));
+        out += &format!(
+            "{name:<24} {:>8} {:>8} {:>8} {:>8} {:>8} {:>8}\n",
+            ours.0, column(|t| t.0.to_string()), ours.1, column(|t| t.1.to_string()), ours.2, column(|t| t.2.to_string())
+        );
+        if let Err(e) = theirs {
+            out += &format!("  solc: {e:#}\n");
+        }
+    }
+    Ok(out)
+}
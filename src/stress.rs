@@ -0,0 +1,133 @@
+//! Programs built to stress the spiller: more than 16 values live at once, interleaved lifetimes,
+//! and values used exactly at the edge of what `dup` and `swap` can reach.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use eyre::{Result, WrapErr};
+
+use crate::{compile_all, equivalence, Options};
+
+/// Memory the programs store their results to, far above the spill registers.
+const MEMORY_BASE: u64 = 0x10000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pattern {
+    /// Values used when they are at depth 15, 16 and 17.
+    Boundary,
+    /// Values used in a shuffled order, interleaved with new definitions.
+    Interleaved,
+    /// Many values summed up starting from the deepest.
+    Wide,
+}
+
+impl Pattern {
+    pub const ALL: [Pattern; 3] = [Pattern::Boundary, Pattern::Interleaved, Pattern::Wide];
+}
+
+/// Xorshift generator, enough to vary the programs from one seed to the next.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+#[derive(Default)]
+struct Writer {
+    source: String,
+    count: usize,
+    stores: usize,
+}
+
+impl Writer {
+    fn define(&mut self, expression: String) -> String {
+        let name = format!("v{}", self.count);
+        self.count += 1;
+        writeln!(self.source, "let {name} = {expression};").unwrap();
+        name
+    }
+
+    /// Stores `value` to the next free word, so that it's part of the observable result.
+    fn store(&mut self, value: &str) {
+        let address = self.define(format!("const {}", MEMORY_BASE + 32 * self.stores as u64));
+        self.stores += 1;
+        writeln!(self.source, "mstore {address} {value};").unwrap();
+    }
+
+    fn pop(&mut self, value: &str) {
+        writeln!(self.source, "pop {value};").unwrap();
+    }
+}
+
+/// Generates the program for `pattern` varied by `seed`.
+pub fn generate(pattern: Pattern, seed: u64) -> String {
+    let mut rng = Rng(seed.wrapping_mul(0x9e3779b97f4a7c15) | 1);
+    let mut w = Writer::default();
+
+    match pattern {
+        Pattern::Boundary => {
+            let live = 17 + rng.below(4);
+            let values: Vec<_> = (0..live).map(|i| w.define(format!("const {}", 1000 + i))).collect();
+            // Each sum is stored right away, so the values stay at the same depths.
+            for depth in [15, 16, 17].into_iter().filter(|&depth| depth < live) {
+                let v = &values[live - 1 - depth];
+                let sum = w.define(format!("add {v} {v}"));
+                w.store(&sum);
+            }
+            for v in values.iter().rev() {
+                w.pop(v);
+            }
+        }
+
+        Pattern::Interleaved => {
+            let mut live: Vec<String> = Vec::new();
+            for i in 0..40 + rng.below(40) {
+                if live.len() < 17 || rng.below(3) > 0 {
+                    live.push(w.define(format!("const {}", rng.next() % 1_000_000 + i as u64)));
+                } else {
+                    let a = live.swap_remove(rng.below(live.len()));
+                    let b = live[rng.below(live.len())].clone();
+                    let sum = w.define(format!("add {a} {b}"));
+                    w.store(&sum);
+                }
+            }
+            while let Some(v) = live.pop() {
+                w.store(&v);
+            }
+        }
+
+        Pattern::Wide => {
+            let values: Vec<_> = (0..17 + rng.below(32)).map(|_| w.define(format!("const {}", rng.next() % 1_000_000))).collect();
+            let mut sum = values[0].clone();
+            for v in &values[1..] {
+                sum = w.define(format!("add {sum} {v}"));
+            }
+            w.store(&sum);
+        }
+    }
+    w.source
+}
+
+/// Compiles the program for every pattern and seed in `seeds` with verification on, and checks the
+/// result against the program with the interpreter. Returns how many programs were checked.
+pub fn run(seeds: std::ops::Range<u64>, mut options: Options) -> Result<usize> {
+    options.codegen.verify = true;
+    let programs: Vec<_> = seeds.flat_map(|seed| Pattern::ALL.map(|pattern| (pattern, seed))).collect();
+    let sources: Vec<_> = programs.iter().map(|&(pattern, seed)| generate(pattern, seed)).collect();
+    let outputs = compile_all(&sources, &options);
+    for (((pattern, seed), source), output) in programs.iter().zip(&sources).zip(outputs) {
+        let context = || format!("{pattern:?} program with seed {seed}:\n{source}");
+        let output = output.wrap_err_with(context)?;
+        equivalence::check(&output, &HashMap::new()).wrap_err_with(context)?;
+    }
+    Ok(programs.len())
+}
@@ -0,0 +1,77 @@
+use std::fmt::Write;
+
+use crate::codegen::Code;
+use crate::scope::ResolvedBlock;
+
+const STEP: usize = 8;
+const ROW: usize = 10;
+const MARGIN: usize = 30;
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+/// Stack depth after each instruction.
+fn depths(code: &Code) -> Vec<usize> {
+    let mut depth = 0;
+    code.instructions.iter()
+        .map(|instr| {
+            if let Some(m) = instr.metadata(code.evm_version) {
+                depth = (depth + m.outputs).saturating_sub(m.inputs);
+            }
+            depth
+        })
+        .collect()
+}
+
+/// Renders an HTML page with an SVG plot of the stack depth over the instructions, marking the
+/// spills and restores. Hovering an instruction shows it along with the statement it came from.
+pub fn render(code: &Code, rblock: &ResolvedBlock) -> String {
+    let depths = depths(code);
+    let max_depth = depths.iter().copied().max().unwrap_or(0).max(16);
+    let width = depths.len() * STEP + 2 * MARGIN;
+    let height = (max_depth + 1) * ROW + 2 * MARGIN;
+    let y = |depth: usize| MARGIN + (max_depth - depth) * ROW;
+    let x = |index: usize| MARGIN + index * STEP;
+
+    let mut svg = String::new();
+    let _ = writeln!(svg, r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" font-family="monospace" font-size="10">"#);
+
+    // Alternate shading for the instructions of each statement, with hover info per instruction.
+    for (index, (instr, &origin)) in code.instructions.iter().zip(&code.origins).enumerate() {
+        let fill = if origin % 2 == 0 { "#f4f4f4" } else { "#e8e8f4" };
+        let statement = escape(&rblock.named_statement(origin).to_string());
+        let instr = escape(&instr.display(code.evm_version).to_string());
+        let _ = writeln!(svg,
+            r#"<rect x="{}" y="{MARGIN}" width="{STEP}" height="{}" fill="{fill}"><title>{index}: {instr} (depth {})&#10;statement {origin}: {statement}</title></rect>"#,
+            x(index), (max_depth + 1) * ROW, depths[index],
+        );
+    }
+
+    let _ = writeln!(svg, r##"<line x1="{MARGIN}" y1="{0}" x2="{1}" y2="{0}" stroke="#c33" stroke-dasharray="4 2"/>"##, y(16), width - MARGIN);
+    let _ = writeln!(svg, r##"<text x="2" y="{}" fill="#c33">16</text>"##, y(16) + 3);
+
+    let points: Vec<_> = depths.iter().enumerate()
+        .flat_map(|(index, &depth)| [format!("{},{}", x(index), y(depth)), format!("{},{}", x(index + 1), y(depth))])
+        .collect();
+    let _ = writeln!(svg, r##"<polyline points="{}" fill="none" stroke="#236" stroke-width="1.5" pointer-events="none"/>"##, points.join(" "));
+
+    // Spill code is emitted at the end of a statement's instructions.
+    for spill in &code.spills {
+        let Some(last) = code.origins.iter().rposition(|&o| o == spill.statement) else { continue };
+        let (color, action) = if spill.restore { ("#2a2", "restore") } else { ("#d22", "spill") };
+        let name = escape(rblock.name(spill.var));
+        let _ = writeln!(svg,
+            r#"<circle cx="{}" cy="{}" r="3" fill="{color}"><title>{action} {name} to r{} in statement {}</title></circle>"#,
+            x(last + 1), MARGIN / 2, spill.register, spill.statement,
+        );
+    }
+
+    svg += "</svg>\n";
+
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Stack usage</title></head>\n<body>\n\
+         <p>{} instructions, peak stack depth {}, spill registers used: {}. Red: spill, green: restore.</p>\n{svg}</body>\n</html>\n",
+        depths.len(), depths.iter().max().unwrap_or(&0), code.register_count,
+    )
+}
@@ -0,0 +1,122 @@
+//! Follows the program's variables through the generated code without running it, for the
+//! debugger and for debug info.
+
+use std::collections::HashMap;
+
+use alloy_primitives::U256;
+
+use crate::evm::{DataInstruction, Instruction, StackInstruction};
+use crate::program::{Expression, Statement};
+use crate::Output;
+
+/// What is known about a stack item or register without running the code.
+#[derive(Clone, Default)]
+pub struct Slot {
+    pub name: Option<String>,
+    pub constant: Option<U256>,
+}
+
+impl Slot {
+    pub fn show(&self, value: Option<U256>) -> String {
+        match (&self.name, value.or(self.constant)) {
+            (Some(name), Some(value)) => format!("{name}={value}"),
+            (Some(name), None) => name.clone(),
+            (None, Some(value)) => value.to_string(),
+            (None, None) => "?".into(),
+        }
+    }
+}
+
+/// Follows variables through the instructions by their effects on the stack and on memory at
+/// known addresses.
+pub struct Tracker<'a> {
+    output: &'a Output,
+    /// From the bottom of the stack to the top.
+    pub stack: Vec<Slot>,
+    pub memory: HashMap<U256, Slot>,
+    defined: Vec<bool>,
+    steps: usize,
+}
+
+impl<'a> Tracker<'a> {
+    pub fn new(output: &'a Output) -> Tracker<'a> {
+        let program = &output.program;
+        let inputs = program.inputs().iter().rev().map(|&v| Slot { name: Some(program.name(v).to_string()), constant: None });
+        Tracker {
+            output,
+            stack: vec![Slot::default(); output.code.reserved_slots].into_iter().chain(inputs).collect(),
+            memory: HashMap::new(),
+            defined: vec![false; output.program.block.0.len()],
+            steps: 0,
+        }
+    }
+
+    /// The names of the variables `instr` defines, if it is the instruction that computes the
+    /// statement it was generated for.
+    fn definitions(&mut self, instr: &Instruction, statement: usize) -> Vec<String> {
+        let program = &self.output.program;
+        let Statement(ress, e) = &program.block.0[statement];
+        let defines = !self.defined[statement] && match (e, instr) {
+            (Expression::Const(c), Instruction::Stack(StackInstruction::Push(p))) => {
+                p.value() == *c || self.output.code.memory_offset.is_some_and(|offset| p.value() == offset.wrapping_add(*c))
+            }
+            (Expression::Immutable(name), Instruction::Immutable(n)) => **name == **n,
+            (Expression::Op(op, args), Instruction::Data(d)) => {
+                d.name() == op && args.iter().enumerate().all(|(depth, &a)| {
+                    let index = self.stack.len().checked_sub(depth + 1);
+                    index.and_then(|i| self.stack[i].name.as_deref()) == Some(program.name(a))
+                })
+            }
+            _ => false,
+        };
+        if !defines {
+            return Vec::new();
+        }
+        self.defined[statement] = true;
+        ress.iter().map(|&r| program.name(r).to_string()).collect()
+    }
+
+    /// Applies the effects of `instr`, generated for `statement`.
+    pub fn step(&mut self, instr: &Instruction, statement: usize) {
+        self.steps += 1;
+        let code = &self.output.code;
+        if code.base_on_stack && self.steps == 1 {
+            self.stack.push(Slot { name: Some("spill_base".into()), constant: Some(code.spill_base) });
+            return;
+        }
+
+        let names = self.definitions(instr, statement);
+        let named = |i: usize, slot: Slot| Slot { name: names.get(i).cloned().or(slot.name), ..slot };
+
+        let len = self.stack.len();
+        match instr {
+            Instruction::Stack(StackInstruction::Push(c)) => {
+                self.stack.push(named(0, Slot { name: None, constant: Some(c.value()) }));
+            }
+            Instruction::Stack(StackInstruction::Dup(depth)) => self.stack.push(self.stack[len - 1 - depth].clone()),
+            Instruction::Stack(StackInstruction::Swap(depth)) => self.stack.swap(len - 1, len - 1 - depth),
+            Instruction::Control(_) => (),
+            _ => {
+                let Some(metadata) = instr.metadata(code.evm_version) else { return };
+                let args = self.stack.split_off(len.saturating_sub(metadata.inputs));
+                let constant = |i: usize| args.get(args.len().wrapping_sub(i + 1)).and_then(|s| s.constant);
+                let results = match instr {
+                    Instruction::Data(DataInstruction::Add) => {
+                        vec![Slot { name: None, constant: constant(0).zip(constant(1)).map(|(a, b)| a.wrapping_add(b)) }]
+                    }
+                    Instruction::Data(DataInstruction::Mload) => {
+                        vec![constant(0).and_then(|a| self.memory.get(&a).cloned()).unwrap_or_default()]
+                    }
+                    Instruction::Data(DataInstruction::Mstore) => {
+                        if let Some(address) = constant(0) {
+                            self.memory.insert(address, args[args.len() - 2].clone());
+                        }
+                        vec![]
+                    }
+                    _ => vec![Slot::default(); metadata.outputs],
+                };
+                self.stack.extend(results.into_iter().enumerate().map(|(i, s)| named(i, s)));
+            }
+        }
+    }
+}
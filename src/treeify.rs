@@ -0,0 +1,213 @@
+//! Code generation without swaps, in the manner of solc's old treeify. Each statement leaves the
+//! stack as it found it: constants and pure operations whose result is used once are computed
+//! where they are used, and every other value is stored in a memory register as soon as it is
+//! defined and loaded from there at each use. The code is larger than the scheduler's, but makes a
+//! baseline for comparison.
+
+use alloy_primitives::U256;
+
+use crate::analysis::constant_offsets;
+use crate::codegen::{layout, register_address, Code, CodegenError, Fuel, Options, SpillEvent, RELOCATED};
+use crate::evm::{DataInstruction, Instruction, StackInstruction};
+use crate::program::{Expression, Statement};
+use crate::scope::{ResolvedBlock, Var};
+
+/// Where a value comes from at each of its uses.
+#[derive(Clone)]
+enum Home {
+    Push(U256),
+    Immutable(String),
+    /// The result of a pure operation used once, computed at its use from the statement.
+    Inline(usize),
+    Register(usize),
+}
+
+struct Treeify<'a> {
+    rblock: &'a ResolvedBlock,
+    options: &'a Options,
+    homes: Vec<Option<Home>>,
+    /// Uses left of each variable, to free its register after the last one.
+    uses: Vec<usize>,
+    code: Vec<Instruction>,
+    origins: Vec<usize>,
+    statement: usize,
+    register_count: usize,
+    free_registers: Vec<usize>,
+    fuel: Fuel,
+    spills: Vec<SpillEvent>,
+}
+
+/// Whether the operation of `e` can be computed at any point after its arguments with the same
+/// result. Unlike [`DataInstruction::is_pure`], this leaves out the readings of gas and memory size,
+/// and of transient storage, which the block can write in between.
+fn recomputable(e: &Expression<Var>) -> bool {
+    match e {
+        Expression::Const(_) | Expression::Immutable(_) => true,
+        Expression::Op(op, _) => op.parse::<DataInstruction>()
+            .is_ok_and(|op| op.is_pure() && !matches!(op, DataInstruction::Gas | DataInstruction::Msize | DataInstruction::Tload)),
+        Expression::Verbatim(..) | Expression::Input | Expression::Output(_) => false,
+    }
+}
+
+impl Treeify<'_> {
+    fn emit(&mut self, instr: Instruction) {
+        self.code.push(instr);
+        self.origins.push(self.statement);
+    }
+
+    fn allocate_register(&mut self) -> usize {
+        if !self.options.stable_registers && !self.free_registers.is_empty() && self.fuel.consume("reuse register") {
+            return self.free_registers.pop().unwrap();
+        }
+        self.register_count += 1;
+        self.register_count - 1
+    }
+
+    /// Moves the value on top of the stack to memory if `var` is used, or drops it otherwise.
+    fn define(&mut self, var: Var) {
+        if self.uses[var.index()] == 0 {
+            self.emit(Instruction::Data(DataInstruction::Pop));
+            return;
+        }
+        let register = self.allocate_register();
+        self.homes[var.index()] = Some(Home::Register(register));
+        self.move_register(var, register, false);
+    }
+
+    fn move_register(&mut self, var: Var, register: usize, restore: bool) {
+        let start = self.code.len();
+        self.emit(Instruction::Stack(StackInstruction::Push(register_address(self.options.spill_base, register).into())));
+        self.emit(Instruction::Data(if restore { DataInstruction::Mload } else { DataInstruction::Mstore }));
+        let gas = self.code[start..].iter().map(|i| i.gas_cost(self.options.evm_version)).sum();
+        self.spills.push(SpillEvent { statement: self.statement, var, register, restore, gas });
+    }
+
+    /// Pushes the value of `var`.
+    fn value(&mut self, var: Var) -> Result<(), CodegenError> {
+        let home = self.homes[var.index()].clone().ok_or(CodegenError::Internal("variable used before its definition"))?;
+        self.uses[var.index()] -= 1;
+        match home {
+            Home::Push(c) => self.emit(Instruction::Stack(StackInstruction::Push(c.into()))),
+            Home::Immutable(name) => self.emit(Instruction::Immutable(name.into())),
+            Home::Inline(statement) => self.operation(statement)?,
+            Home::Register(register) => {
+                self.move_register(var, register, true);
+                if self.uses[var.index()] == 0 && !self.options.stable_registers {
+                    self.free_registers.push(register);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Pushes the arguments of the operation of `statement`, the first on top, and applies it.
+    fn operation(&mut self, statement: usize) -> Result<(), CodegenError> {
+        let Statement(ress, e) = &self.rblock.block.0[statement];
+        let (Expression::Op(name, args) | Expression::Verbatim(name, _, args)) = e else {
+            return Err(CodegenError::Internal("not an operation"));
+        };
+        let instr = match e {
+            Expression::Verbatim(_, bytes, _) => {
+                let takes = args.len().try_into().map_err(|_| CodegenError::Internal("too many arguments"))?;
+                let returns = ress.len().try_into().map_err(|_| CodegenError::Internal("too many results"))?;
+                Instruction::Verbatim(bytes.clone().into(), takes, returns)
+            }
+            _ => {
+                let op: DataInstruction = name.parse().map_err(|_| CodegenError::UnknownOperator(name.clone()))?;
+                let (nargs, nres) = op.arity();
+                if args.len() != nargs {
+                    return Err(CodegenError::WrongArgumentCount { op: name.clone(), expected: nargs, found: args.len() });
+                }
+                if ress.len() != nres {
+                    return Err(CodegenError::WrongResultCount { expected: nres, found: ress.len() });
+                }
+                if !self.options.evm_version.supports(op) {
+                    return Err(CodegenError::Unsupported { op: name.clone(), evm_version: self.options.evm_version });
+                }
+                Instruction::Data(op)
+            }
+        };
+        for &arg in args.iter().rev() {
+            self.value(arg)?;
+        }
+        self.emit(instr);
+        Ok(())
+    }
+}
+
+pub fn generate(rblock: &ResolvedBlock, options: &Options) -> Result<Code, CodegenError> {
+    if options.base_on_stack {
+        return Err(CodegenError::RelocatableTreeify);
+    }
+    let statements = &rblock.block.0;
+
+    // Uses by the statements that are computed, from the last one back: a recomputable statement
+    // whose results are never used is left out.
+    let mut uses = vec![0; rblock.var_count];
+    let mut computed = vec![false; statements.len()];
+    for (i, Statement(ress, e)) in statements.iter().enumerate().rev() {
+        computed[i] = !recomputable(e) || ress.iter().any(|r| uses[r.index()] > 0);
+        if computed[i] {
+            for &arg in rblock.flat.args(i) {
+                uses[arg.index()] += 1;
+            }
+        }
+    }
+
+    let offsets = constant_offsets(rblock);
+    let relocate = options.relocate_memory;
+    let mut treeify = Treeify {
+        rblock,
+        options,
+        homes: vec![None; rblock.var_count],
+        uses,
+        code: Vec::new(),
+        origins: Vec::new(),
+        statement: 0,
+        register_count: 0,
+        free_registers: Vec::new(),
+        fuel: Fuel::new(options.fuel),
+        spills: Vec::new(),
+    };
+
+    for (statement, Statement(ress, e)) in statements.iter().enumerate() {
+        treeify.statement = statement;
+        if !computed[statement] {
+            continue;
+        }
+        if matches!(e, Expression::Const(_) | Expression::Immutable(_)) && ress.len() != 1 {
+            return Err(CodegenError::WrongResultCount { expected: 1, found: ress.len() });
+        }
+        let single = ress.len() == 1 && treeify.uses[ress[0].index()] == 1;
+        match e {
+            Expression::Const(c) if relocate && offsets[ress[0].index()] => {
+                treeify.homes[ress[0].index()] = Some(Home::Immutable(format!("{RELOCATED}{c}")));
+            }
+            Expression::Const(c) => treeify.homes[ress[0].index()] = Some(Home::Push(*c)),
+            Expression::Immutable(name) => treeify.homes[ress[0].index()] = Some(Home::Immutable(name.clone())),
+            Expression::Op(..) if recomputable(e) && single => treeify.homes[ress[0].index()] = Some(Home::Inline(statement)),
+            Expression::Input if statement == 0 => {
+                for &r in ress {
+                    treeify.define(r);
+                }
+            }
+            Expression::Input => return Err(CodegenError::Internal("inputs taken after the first statement")),
+            Expression::Output(args) if statement + 1 == statements.len() => {
+                for &arg in args.iter().rev() {
+                    treeify.value(arg)?;
+                }
+            }
+            Expression::Output(_) => return Err(CodegenError::Internal("outputs returned before the last statement")),
+            Expression::Op(..) | Expression::Verbatim(..) => {
+                treeify.operation(statement)?;
+                for &r in ress.iter().rev() {
+                    treeify.define(r);
+                }
+            }
+        }
+    }
+
+    let Treeify { code, origins, register_count, mut fuel, spills, .. } = treeify;
+    let code = layout(code, origins, register_count, options, &mut fuel)?;
+    Ok(Code { spills, ..code })
+}
@@ -0,0 +1,77 @@
+//! JavaScript bindings, built with `--features wasm` for wasm32-unknown-unknown.
+
+use alloy_primitives::U256;
+use eyre::{eyre, Result};
+use serde_json::{json, Value};
+use wasm_bindgen::prelude::wasm_bindgen;
+
+use crate::{codegen, compile_json, Limits, Options, Syntax};
+
+/// Reads options from a JSON object with the optional fields `stableRegisters`, `minimizeSwaps`,
+/// `spillBase` (a number or a numeric string), `baseOnStack`, `evmVersion`, `constantPool`,
+/// `constantRegisters`, `reservedSlots`, `spillDepth`, `treeify`, `relocateMemory`, `retries`,
+/// `fuel`, `seed`, `verify` and `metadata`, and the limits `maxStatements`, `maxLiveVariables`, `maxRegisters` and
+/// `maxBytecodeSize`.
+fn options(json: &str) -> Result<Options> {
+    let value: Value = if json.trim().is_empty() { json!({}) } else { serde_json::from_str(json)? };
+    let flag = |key: &str, default: bool| value.get(key).and_then(Value::as_bool).unwrap_or(default);
+    let number = |key: &str| value.get(key).and_then(Value::as_u64).map(|n| n as usize);
+
+    let defaults = codegen::Options::default();
+    let spill_base = match value.get("spillBase") {
+        None => defaults.spill_base,
+        Some(Value::Number(n)) => U256::from(n.as_u64().ok_or_else(|| eyre!("bad spillBase: {n}"))?),
+        Some(Value::String(s)) => s.parse()?,
+        Some(v) => return Err(eyre!("bad spillBase: {v}")),
+    };
+    let evm_version = match value.get("evmVersion").and_then(Value::as_str) {
+        Some(name) => name.parse()?,
+        None => defaults.evm_version,
+    };
+
+    let syntax = match value.get("syntax").and_then(Value::as_str) {
+        None | Some("dsl") => Syntax::Dsl,
+        Some("yul") => Syntax::Yul,
+        Some(other) => return Err(eyre!("bad syntax: {other}")),
+    };
+
+    Ok(Options {
+        syntax,
+        codegen: codegen::Options {
+            verify: flag("verify", defaults.verify),
+            stable_registers: flag("stableRegisters", defaults.stable_registers),
+            minimize_swaps: flag("minimizeSwaps", defaults.minimize_swaps),
+            spill_base,
+            base_on_stack: flag("baseOnStack", defaults.base_on_stack),
+            evm_version,
+            constant_pool: number("constantPool"),
+            constant_registers: number("constantRegisters"),
+            reserved_slots: number("reservedSlots").unwrap_or(defaults.reserved_slots),
+            spill_depth: number("spillDepth").unwrap_or(defaults.spill_depth),
+            treeify: flag("treeify", defaults.treeify),
+            relocate_memory: flag("relocateMemory", defaults.relocate_memory),
+            retries: number("retries").unwrap_or(defaults.retries),
+            fuel: number("fuel"),
+            seed: number("seed").map_or(defaults.seed, |n| n as u64),
+            ..defaults
+        },
+        metadata: flag("metadata", false),
+        limits: Limits {
+            statements: number("maxStatements"),
+            live_variables: number("maxLiveVariables"),
+            registers: number("maxRegisters"),
+            bytecode_size: number("maxBytecodeSize"),
+        },
+    })
+}
+
+/// Compiles `source` with the options in `options_json`, returning the same JSON document as
+/// `--format json`.
+#[wasm_bindgen]
+pub fn compile(source: &str, options_json: &str) -> String {
+    let document = match options(options_json) {
+        Ok(options) => compile_json(source, &options),
+        Err(e) => json!({ "diagnostics": [{ "severity": "error", "message": format!("invalid options: {e:#}") }] }),
+    };
+    document.to_string()
+}
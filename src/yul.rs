@@ -0,0 +1,232 @@
+//! Frontend for a straight-line subset of Yul: `let` declarations, assignments and builtin calls,
+//! without functions, control flow or nested blocks. Nested calls are flattened into temporaries,
+//! evaluating arguments right to left as Yul does.
+
+use std::collections::HashMap;
+
+use alloy_primitives::U256;
+use winnow::{
+    ascii::{digit1, hex_digit1, multispace1, till_line_ending},
+    combinator::{alt, cut_err, delimited, fail, opt, peek, preceded, repeat, separated, terminated},
+    error::{ContextError, StrContext, StrContextValue},
+    prelude::*,
+    token::{take_until, take_while},
+};
+
+use crate::parser::ParseError;
+use crate::program::{Block, Expression, Span, Statement};
+
+enum YulExpression {
+    Literal(U256),
+    Identifier(String),
+    Call(String, Vec<YulExpression>),
+}
+
+enum YulStatement {
+    Let(Vec<String>, Option<YulExpression>),
+    Assign(Vec<String>, YulExpression),
+    Expression(YulExpression),
+}
+
+/// Keywords of the constructs outside the subset.
+const UNSUPPORTED: [&str; 9] = ["function", "if", "for", "switch", "case", "default", "break", "continue", "leave"];
+
+fn ws(input: &mut &str) -> PResult<()> {
+    repeat(0.., alt((
+        multispace1.void(),
+        ("//", till_line_ending).void(),
+        ("/*", take_until(0.., "*/"), "*/").void(),
+    )))
+    .parse_next(input)
+}
+
+fn lexeme<'a, O>(parser: impl Parser<&'a str, O, ContextError>) -> impl Parser<&'a str, O, ContextError> {
+    preceded(ws, parser)
+}
+
+fn name<'a>(input: &mut &'a str) -> PResult<&'a str> {
+    let start = |c: char| c.is_ascii_alphabetic() || c == '_' || c == '$';
+    let rest = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '$' || c == '.';
+    lexeme((take_while(1, start), take_while(0.., rest)).recognize()).parse_next(input)
+}
+
+fn keyword<'a>(keyword: &'static str) -> impl Parser<&'a str, &'a str, ContextError> {
+    name.verify(move |id: &str| id == keyword)
+}
+
+fn identifier(input: &mut &str) -> PResult<String> {
+    name.verify(|id: &str| !["let", "true", "false"].contains(&id) && !UNSUPPORTED.contains(&id))
+        .map(String::from)
+        .parse_next(input)
+}
+
+fn literal(input: &mut &str) -> PResult<U256> {
+    alt((
+        lexeme(preceded("0x", hex_digit1)).try_map(|h| U256::from_str_radix(h, 16)),
+        lexeme(digit1).try_map(|d| U256::from_str_radix(d, 10)),
+        keyword("true").value(U256::from(1)),
+        keyword("false").value(U256::ZERO),
+    ))
+    .parse_next(input)
+}
+
+fn expression(input: &mut &str) -> PResult<YulExpression> {
+    let arguments = delimited(lexeme("("), separated(0.., expression, lexeme(",")), cut_err(lexeme(")")));
+    alt((
+        literal.map(YulExpression::Literal),
+        (identifier, opt(arguments)).map(|(id, args)| match args {
+            Some(args) => YulExpression::Call(id, args),
+            None => YulExpression::Identifier(id),
+        }),
+    ))
+    .parse_next(input)
+}
+
+fn variables(input: &mut &str) -> PResult<Vec<String>> {
+    separated(1.., identifier, lexeme(",")).parse_next(input)
+}
+
+fn statement(input: &mut &str) -> PResult<YulStatement> {
+    let unsupported = alt((name.verify(|id: &str| UNSUPPORTED.contains(&id)).void(), lexeme("{").void()));
+    let expected = StrContext::Expected(StrContextValue::Description("straight-line code without functions, control flow or blocks"));
+
+    alt((
+        preceded(keyword("let"), cut_err((variables, opt(preceded(lexeme(":="), expression)))))
+            .map(|(vars, value)| YulStatement::Let(vars, value)),
+        (terminated(variables, lexeme(":=")), cut_err(expression)).map(|(vars, e)| YulStatement::Assign(vars, e)),
+        expression.map(YulStatement::Expression),
+        preceded(peek(unsupported), cut_err(fail.context(expected))),
+    ))
+    .parse_next(input)
+}
+
+fn statements<'a>(input: &mut &'a str) -> PResult<Vec<(YulStatement, &'a str)>> {
+    repeat(0.., preceded(ws, statement.with_recognized())).parse_next(input)
+}
+
+/// The statements, optionally enclosed in braces as in `solc --strict-assembly` input.
+fn file<'a>(input: &mut &'a str) -> PResult<Vec<(YulStatement, &'a str)>> {
+    terminated(alt((delimited(lexeme("{"), statements, cut_err(lexeme("}"))), statements)), ws).parse_next(input)
+}
+
+/// Builds the block, naming each definition after its Yul variable, with `'1`, `'2`, ... added to
+/// later definitions of the same variable, and temporaries `%0`, `%1`, ...
+#[derive(Default)]
+struct Lowering {
+    statements: Vec<Statement<String>>,
+    spans: Vec<Span>,
+    /// Name currently holding the value of each Yul variable.
+    env: HashMap<String, String>,
+    definitions: HashMap<String, usize>,
+    temporaries: usize,
+}
+
+impl Lowering {
+    fn push(&mut self, vars: Vec<String>, e: Expression<String>, span: &Span) {
+        self.statements.push(Statement(vars, e));
+        self.spans.push(span.clone());
+    }
+
+    fn fresh(&mut self, var: &str) -> String {
+        let count = self.definitions.entry(var.to_string()).or_insert(0);
+        *count += 1;
+        if *count == 1 { var.to_string() } else { format!("{var}'{}", *count - 1) }
+    }
+
+    fn temporary(&mut self) -> String {
+        self.temporaries += 1;
+        format!("%{}", self.temporaries - 1)
+    }
+
+    /// Evaluates `e` to the name of a variable holding its value.
+    fn value(&mut self, e: YulExpression, span: &Span) -> String {
+        match e {
+            // Unknown variables are left for the resolver to report.
+            YulExpression::Identifier(id) => self.env.get(&id).cloned().unwrap_or(id),
+            YulExpression::Literal(c) => {
+                let t = self.temporary();
+                self.push(vec![t.clone()], Expression::Const(c), span);
+                t
+            }
+            YulExpression::Call(f, args) => {
+                let t = self.temporary();
+                self.call(vec![t.clone()], f, args, span);
+                t
+            }
+        }
+    }
+
+    fn call(&mut self, results: Vec<String>, f: String, args: Vec<YulExpression>, span: &Span) {
+        let mut values: Vec<_> = args.into_iter().rev().map(|a| self.value(a, span)).collect();
+        values.reverse();
+        self.push(results, Expression::Op(f, values), span);
+    }
+
+    /// Defines `vars` as the values of `e`.
+    fn define(&mut self, vars: Vec<String>, e: YulExpression, span: &Span) -> Result<(), ParseError> {
+        let error = |message: &str| ParseError::Syntax { offset: span.start, message: message.to_string() };
+        match e {
+            YulExpression::Call(f, args) => {
+                let mut results = Vec::new();
+                for var in &vars {
+                    results.push(self.fresh(var));
+                }
+                self.call(results.clone(), f, args, span);
+                self.env.extend(vars.into_iter().zip(results));
+            }
+            _ if vars.len() > 1 => return Err(error("only a call can assign several variables")),
+            YulExpression::Identifier(id) => {
+                let value = self.env.get(&id).cloned().unwrap_or(id);
+                self.env.insert(vars.into_iter().next().unwrap(), value);
+            }
+            YulExpression::Literal(c) => {
+                let var = vars.into_iter().next().unwrap();
+                let name = self.fresh(&var);
+                self.push(vec![name.clone()], Expression::Const(c), span);
+                self.env.insert(var, name);
+            }
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self, statement: YulStatement, span: &Span) -> Result<(), ParseError> {
+        let error = |message: String| ParseError::Syntax { offset: span.start, message };
+        match statement {
+            YulStatement::Let(vars, value) => {
+                let value = value.unwrap_or(YulExpression::Literal(U256::ZERO));
+                if let (YulExpression::Literal(_), true) = (&value, vars.len() > 1) {
+                    // `let a, b` declares several variables, all zero.
+                    for var in vars {
+                        self.define(vec![var], YulExpression::Literal(U256::ZERO), span)?;
+                    }
+                    return Ok(());
+                }
+                self.define(vars, value, span)
+            }
+            YulStatement::Assign(vars, e) => {
+                if let Some(var) = vars.iter().find(|v| !self.env.contains_key(*v)) {
+                    return Err(error(format!("assignment to undeclared variable {var}")));
+                }
+                self.define(vars, e, span)
+            }
+            YulStatement::Expression(YulExpression::Call(f, args)) => {
+                self.call(vec![], f, args, span);
+                Ok(())
+            }
+            YulStatement::Expression(_) => Err(error("expression statement must be a call".to_string())),
+        }
+    }
+}
+
+/// Parses Yul into a block, where each statement has the span of the Yul statement it came from.
+pub fn parse(input: &str) -> Result<Block<String>, ParseError> {
+    let statements = file.parse(input)
+        .map_err(|e| ParseError::Syntax { offset: e.offset(), message: e.to_string() })?;
+
+    let mut lowering = Lowering::default();
+    for (statement, text) in statements {
+        let start = text.as_ptr() as usize - input.as_ptr() as usize;
+        lowering.statement(statement, &(start..start + text.len()))?;
+    }
+    Ok(Block(lowering.statements, lowering.spans, Vec::new()))
+}
@@ -0,0 +1,166 @@
+//! Assembly of instruction sequences into bytecode: the constructor, jump labels, immutables, jump
+//! verification and the metadata trailer.
+
+use std::ops::Range;
+
+use alloy_primitives::{keccak256, U256};
+use evm_spilling::evm::{constructor, init_code, verify_jumps, DataInstruction, EvmVersion, Instruction, InstructionSeq};
+use evm_spilling::interpreter::Interpreter;
+use evm_spilling::metadata;
+
+/// Runtime code of `size` bytes.
+fn runtime(size: usize) -> InstructionSeq {
+    (0..size).fold(InstructionSeq::builder(), |builder, _| builder.op(DataInstruction::Pop)).build()
+}
+
+#[test]
+fn constructor_returns_runtime() {
+    // The runtime size and offset are pushed with one byte, then with two.
+    for size in [10, 300] {
+        for version in [EvmVersion::London, EvmVersion::Cancun] {
+            let code = runtime(size).to_bytes(version).unwrap();
+            let init = init_code(&runtime(size), version).unwrap();
+            let constructor = constructor(size, version);
+            assert_eq!(init.len(), constructor.size(version).unwrap() + size);
+            assert_eq!(init[init.len() - size..], code);
+
+            let mut interpreter = Interpreter::new(init);
+            for instr in &constructor {
+                interpreter.step(instr).unwrap();
+            }
+            assert!(interpreter.halted && !interpreter.reverted);
+            assert_eq!(interpreter.output, code, "{size} bytes ({version:?})");
+        }
+    }
+}
+
+#[test]
+fn encoding() {
+    let seq = InstructionSeq::builder()
+        .push(U256::from(0x1234))
+        .dup(0)
+        .swap(2)
+        .op(DataInstruction::Add)
+        .build();
+    assert_eq!(seq.to_bytes(EvmVersion::Cancun).unwrap(), [0x61, 0x12, 0x34, 0x80, 0x91, 0x01]);
+    assert_eq!(seq.to_hex(EvmVersion::Cancun).unwrap(), "611234809101");
+}
+
+#[test]
+fn push_widths() {
+    let seq = InstructionSeq::builder()
+        .push(U256::ZERO)
+        .push(U256::from(0xff))
+        .push(U256::from(0x100))
+        .push(U256::MAX)
+        .op(DataInstruction::Stop)
+        .build();
+    // `push0` only exists from Shanghai on.
+    assert_eq!(seq.offsets(EvmVersion::London).unwrap(), [0, 2, 4, 7, 40]);
+    assert_eq!(seq.offsets(EvmVersion::Shanghai).unwrap(), [0, 1, 3, 6, 39]);
+    assert_eq!(seq.size(EvmVersion::Shanghai).unwrap(), 40);
+
+    let bytes = seq.to_bytes(EvmVersion::Shanghai).unwrap();
+    assert_eq!(bytes[..6], [0x5f, 0x60, 0xff, 0x61, 0x01, 0x00]);
+    assert_eq!(bytes[6], 0x7f);
+    assert!(bytes[7..39].iter().all(|&b| b == 0xff));
+    assert_eq!(seq.to_bytes(EvmVersion::London).unwrap()[..2], [0x60, 0x00]);
+}
+
+/// A jump over `filler` one-byte instructions to label 1.
+fn jump_over(filler: usize) -> InstructionSeq {
+    (0..filler)
+        .fold(InstructionSeq::builder().jump(1), |builder, _| builder.op(DataInstruction::Pop))
+        .jumpdest(1)
+        .jump(1)
+        .build()
+}
+
+#[test]
+fn labels() {
+    for version in [EvmVersion::London, EvmVersion::Cancun] {
+        // The target is 255, pushed with one byte.
+        let seq = jump_over(252);
+        assert_eq!(seq.labels(version).unwrap()[&1], 255);
+        let bytes = seq.to_bytes(version).unwrap();
+        assert_eq!(bytes[..3], [0x60, 0xff, 0x56]);
+        assert_eq!(bytes[255..], [0x5b, 0x60, 0xff, 0x56]);
+
+        // One more byte pushes the target past 255, which takes a wider push and shifts it again.
+        let seq = jump_over(253);
+        assert_eq!(seq.labels(version).unwrap()[&1], 257);
+        assert_eq!(seq.offsets(version).unwrap()[1..3], [4, 5]);
+        assert_eq!(seq.offsets(version).unwrap()[253..], [256, 257, 258]);
+        let bytes = seq.to_bytes(version).unwrap();
+        assert_eq!(bytes[..4], [0x61, 0x01, 0x01, 0x56]);
+        assert_eq!(bytes[257..], [0x5b, 0x61, 0x01, 0x01, 0x56]);
+    }
+}
+
+#[test]
+fn duplicate_label() {
+    let seq = InstructionSeq::builder().jumpdest(1).jumpdest(1).build();
+    assert!(seq.labels(EvmVersion::Cancun).unwrap_err().to_string().contains("Duplicate label"));
+}
+
+#[test]
+fn link_references() {
+    let immutable = |name: &str| Instruction::Immutable(name.into());
+    let seq = InstructionSeq::builder()
+        .push(U256::from(0x100))
+        .instr(immutable("b"))
+        .op(DataInstruction::Add)
+        .instr(immutable("a"))
+        .instr(immutable("b"))
+        .build();
+    let references = seq.link_references(EvmVersion::Cancun).unwrap();
+    assert_eq!(references.keys().collect::<Vec<_>>(), ["a", "b"]);
+    assert_eq!(references["a"], vec![Range { start: 38, end: 70 }]);
+    assert_eq!(references["b"], [4..36, 71..103]);
+
+    let bytes = seq.to_bytes(EvmVersion::Cancun).unwrap();
+    for range in references.values().flatten() {
+        assert_eq!(bytes[range.start - 1], 0x7f);
+        assert!(bytes[range.clone()].iter().all(|&b| b == 0));
+    }
+}
+
+#[test]
+fn jumps() {
+    verify_jumps(&jump_over(253).to_bytes(EvmVersion::Cancun).unwrap()).unwrap();
+    // A jump whose target isn't pushed right before it can't be checked, and passes.
+    verify_jumps(&[0x80, 0x56]).unwrap();
+
+    let error = |code: &[u8]| verify_jumps(code).unwrap_err().to_string();
+    // `push1 3; jump; stop`
+    assert_eq!(error(&[0x60, 0x03, 0x56, 0x00]), "Jump at 0x2 targets 0x3, which is not a jumpdest");
+    // `push1 4; jumpi; push1 0x5b`, where the 0x5b is push data.
+    assert_eq!(error(&[0x60, 0x04, 0x57, 0x60, 0x5b]), "Jump at 0x2 targets 0x4, which is not a jumpdest");
+    // Past the end of the code.
+    assert_eq!(error(&[0x60, 0x10, 0x56, 0x5b]), "Jump at 0x2 targets 0x10, which is not a jumpdest");
+}
+
+#[test]
+fn trailer() {
+    let source = "let x = const 1; return x x;";
+    let trailer = metadata::trailer(source);
+    let (map, len) = trailer.split_at(trailer.len() - 2);
+    assert_eq!(usize::from(u16::from_be_bytes([len[0], len[1]])), map.len());
+
+    let compiler = concat!("evm-spilling ", env!("CARGO_PKG_VERSION"));
+    let mut expected = vec![0xa2];
+    expected.push(0x68);
+    expected.extend(b"compiler");
+    expected.push(0x60 + compiler.len() as u8);
+    expected.extend(compiler.as_bytes());
+    expected.push(0x66);
+    expected.extend(b"source");
+    expected.extend([0x58, 0x20]);
+    expected.extend(keccak256(source));
+    assert_eq!(map, expected);
+
+    let mut code = vec![0x60, 0x01];
+    metadata::append(&mut code, source);
+    assert_eq!(code[..3], [0x60, 0x01, 0x00]);
+    assert_eq!(code[3..], trailer);
+}
@@ -0,0 +1,40 @@
+//! Programs that write memory at constant addresses among the spill registers are rejected.
+
+use alloy_primitives::U256;
+use evm_spilling::codegen::{self, CodegenError};
+use evm_spilling::{compile, Error, Options};
+
+/// Keeps `c` in register 0 at address 0 under treeify, then writes memory with `write`.
+fn program(write: &str) -> String {
+    format!("let p = const 64;\nlet c = mload p;\nlet z = const 0;\nlet n = const 32;\n{write}\nlet d = add c c;\nmstore p d;\n")
+}
+
+fn collision(write: &str) -> Option<U256> {
+    let options = Options { codegen: codegen::Options { treeify: true, ..codegen::Options::default() }, ..Options::default() };
+    match compile(&program(write), &options) {
+        Ok(_) => None,
+        Err(Error::Codegen(CodegenError::SpillCollision { address, .. })) => Some(address),
+        Err(e) => panic!("{e}"),
+    }
+}
+
+#[test]
+fn mstore() {
+    assert_eq!(collision("mstore z n;"), Some(U256::ZERO));
+}
+
+#[test]
+fn mcopy() {
+    assert_eq!(collision("mcopy z p n;"), Some(U256::ZERO));
+}
+
+#[test]
+fn codecopy() {
+    assert_eq!(collision("codecopy z p n;"), Some(U256::ZERO));
+}
+
+#[test]
+fn outside_registers() {
+    assert_eq!(collision("mcopy p z n;"), None);
+    assert_eq!(collision("let e = const 0;\ncodecopy z p e;"), None);
+}
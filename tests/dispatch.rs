@@ -0,0 +1,72 @@
+//! Contracts run through the interpreter, from the dispatcher into each function.
+
+use alloy_primitives::{keccak256, U256};
+use evm_spilling::dispatch::{self, Contract};
+use evm_spilling::interpreter::Interpreter;
+use evm_spilling::Options;
+
+fn contract() -> Contract {
+    let source = std::fs::read_to_string("tests/snapshots/contract.dsl").unwrap();
+    dispatch::compile(&source, &Options::default()).unwrap()
+}
+
+fn call(contract: &Contract, calldata: Vec<u8>) -> Interpreter {
+    let mut interpreter = Interpreter::new(contract.bytecode.clone());
+    // The interpreter reads the calldata size from the environment like any other.
+    interpreter.environment.insert("calldatasize".to_string(), U256::from(calldata.len()));
+    interpreter.calldata = calldata;
+    interpreter.run(&contract.instructions).unwrap();
+    assert!(interpreter.halted);
+    interpreter
+}
+
+fn selector(signature: &str) -> Vec<u8> {
+    keccak256(signature)[..4].to_vec()
+}
+
+fn word(value: u64) -> Vec<u8> {
+    U256::from(value).to_be_bytes::<32>().to_vec()
+}
+
+#[test]
+fn functions() {
+    let contract = contract();
+
+    let get = call(&contract, selector("get()"));
+    assert!(!get.reverted);
+    assert_eq!(get.output, word(7));
+
+    let double = call(&contract, [selector("double(uint256)"), word(21)].concat());
+    assert!(!double.reverted);
+    assert_eq!(double.output, word(42));
+
+    let ping = call(&contract, selector("ping()"));
+    assert!(!ping.reverted);
+    assert_eq!(ping.logs.len(), 1);
+
+    assert!(call(&contract, selector("missing()")).reverted);
+}
+
+#[test]
+fn short_calldata() {
+    // `f477()` has a selector ending in a zero byte, which the zeros past the end of the calldata
+    // would otherwise match.
+    let source = r#"function "f477()" { let x = const 1; let p = const 0; mstore p x; let n = const 32; return p n; }"#;
+    let contract = dispatch::compile(source, &Options::default()).unwrap();
+    let full = selector("f477()");
+    assert_eq!(full[3], 0);
+
+    assert!(!call(&contract, full.clone()).reverted);
+    for len in 0..4 {
+        assert!(call(&contract, full[..len].to_vec()).reverted, "{len} bytes");
+    }
+}
+
+#[test]
+fn takes_and_returns() {
+    for body in ["takes (a); mstore a a;", "let a = const 1; returns (a);"] {
+        let source = format!(r#"function "f()" {{ {body} }}"#);
+        let error = dispatch::compile(&source, &Options::default()).err().unwrap().to_string();
+        assert!(error.contains("can't use takes or returns"), "{error}");
+    }
+}
@@ -0,0 +1,51 @@
+//! EOF containers: the header, relative jumps and the code EOF doesn't allow.
+
+use alloy_primitives::U256;
+use evm_spilling::evm::{DataInstruction, InstructionSeq};
+
+#[test]
+fn container() {
+    let seq = InstructionSeq::builder()
+        .push(U256::from(2))
+        .push(U256::from(1))
+        .jumpi(2)
+        .jumpdest(1)
+        .push(U256::ZERO)
+        .jump(1)
+        .jumpdest(2)
+        .build();
+    let eof = seq.to_eof().unwrap();
+    let (header, body) = eof.split_at(15);
+
+    assert_eq!(header[..3], [0xef, 0x00, 0x01]);
+    // One type entry of 4 bytes, one code section and no data.
+    assert_eq!(header[3..6], [0x01, 0x00, 0x04]);
+    assert_eq!(header[6..9], [0x02, 0x00, 0x01]);
+    let code_size = u16::from_be_bytes([header[9], header[10]]) as usize;
+    assert_eq!(header[11..], [0xff, 0x00, 0x00, 0x00]);
+
+    let (types, code) = body.split_at(4);
+    assert_eq!(code.len(), code_size);
+    // No inputs, non-returning, and two values at most on the stack.
+    assert_eq!(types, [0x00, 0x80, 0x00, 0x02]);
+
+    // The `rjumpi` at 4 lands past the loop at 11, and the `rjump` at 8 goes back to 7, with
+    // offsets counted from the end of each jump.
+    assert_eq!(code, [0x60, 0x02, 0x60, 0x01, 0xe1, 0x00, 0x04, 0x5f, 0xe0, 0xff, 0xfc, 0x00]);
+}
+
+#[test]
+fn banned() {
+    for op in [DataInstruction::Codecopy, DataInstruction::Codesize, DataInstruction::Gas] {
+        let seq = InstructionSeq::builder().push(U256::ZERO).push(U256::ZERO).push(U256::ZERO).op(op).build();
+        let error = seq.to_eof().unwrap_err().to_string();
+        assert_eq!(error, format!("{} is not allowed in EOF code", op.name()));
+    }
+}
+
+#[test]
+fn inputs() {
+    let seq = InstructionSeq::builder().op(DataInstruction::Add).op(DataInstruction::Pop).build();
+    let error = seq.to_eof().unwrap_err().to_string();
+    assert_eq!(error, "EOF code can't take inputs from the stack, but this takes 2");
+}
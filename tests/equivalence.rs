@@ -0,0 +1,73 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use alloy_primitives::U256;
+use evm_spilling::codegen::CodegenError;
+use evm_spilling::{codegen, compile, equivalence, Error, Options, Output};
+
+fn configurations() -> Vec<(&'static str, codegen::Options)> {
+    let defaults = || codegen::Options { verify: true, ..codegen::Options::default() };
+    vec![
+        ("default", defaults()),
+        ("stable registers", codegen::Options { stable_registers: true, ..defaults() }),
+        ("no swap minimization", codegen::Options { minimize_swaps: false, ..defaults() }),
+        ("spill base", codegen::Options { spill_base: U256::from(0x4000), ..defaults() }),
+        ("base on stack", codegen::Options { spill_base: U256::from(0x4000), base_on_stack: true, ..defaults() }),
+        ("constant pool", codegen::Options { constant_pool: Some(2), ..defaults() }),
+        ("constant registers", codegen::Options { constant_registers: Some(1), ..defaults() }),
+        ("spill depth", codegen::Options { spill_depth: 12, ..defaults() }),
+        ("treeify", codegen::Options { treeify: true, ..defaults() }),
+        ("relocate memory", codegen::Options { relocate_memory: true, ..defaults() }),
+    ]
+}
+
+/// The examples, and the snapshot programs that depend on the memory layout or on the order of
+/// state accesses.
+fn programs() -> Vec<PathBuf> {
+    let mut paths: Vec<_> = std::fs::read_dir("examples").unwrap().map(|entry| entry.unwrap().path()).collect();
+    paths.sort();
+    paths.extend(["alloc", "heap", "revert", "message", "transient", "relocate"].map(|name| Path::new("tests/snapshots").join(name).with_extension("dsl")));
+    paths
+}
+
+/// Compiles `source`, or returns `None` if the configuration rejects it: a heap where base_on_stack
+/// doesn't allow one, or stores at constant addresses where its registers are.
+fn compile_program(path: &Path, source: &str, options: &Options) -> Option<Output> {
+    match compile(source, options) {
+        Ok(output) => Some(output),
+        Err(Error::Codegen(CodegenError::RelocatableHeap | CodegenError::SpillCollision { .. })) => None,
+        Err(e) => panic!("{}: {e}", path.display()),
+    }
+}
+
+#[test]
+fn examples() {
+    let immutables = HashMap::new();
+    for path in programs() {
+        let source = std::fs::read_to_string(&path).unwrap();
+        for (name, codegen) in configurations() {
+            let options = Options { codegen, ..Options::default() };
+            let Some(output) = compile_program(&path, &source, &options) else { continue };
+            if let Err(e) = equivalence::check(&output, &immutables) {
+                panic!("{} ({name}): {e:#}", path.display());
+            }
+        }
+    }
+}
+
+/// Compiling again gives the same code, whatever order hash maps iterate in, which differs from one
+/// map to the next.
+#[test]
+fn deterministic() {
+    for path in programs() {
+        let source = std::fs::read_to_string(&path).unwrap();
+        for (name, codegen) in configurations() {
+            let options = Options { codegen, ..Options::default() };
+            let Some(outputs) = (0..4).map(|_| compile_program(&path, &source, &options)).collect::<Option<Vec<_>>>() else { continue };
+            for output in &outputs[1..] {
+                assert_eq!(output.bytecode, outputs[0].bytecode, "{} ({name})", path.display());
+                assert_eq!(output.assembly().to_string(), outputs[0].assembly().to_string(), "{} ({name})", path.display());
+            }
+        }
+    }
+}
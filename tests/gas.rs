@@ -0,0 +1,80 @@
+//! Statistics of instruction sequences: static gas costs, counts and spill traffic.
+
+use alloy_primitives::U256;
+use evm_spilling::evm::{Counts, DataInstruction, EvmVersion, Instruction, InstructionSeq, StackInstruction};
+
+#[test]
+fn push0() {
+    let zero = Instruction::Stack(StackInstruction::Push(U256::ZERO.into()));
+    let one = Instruction::Stack(StackInstruction::Push(U256::from(1).into()));
+    for fork in [EvmVersion::London, EvmVersion::Paris] {
+        assert_eq!(zero.gas_cost(fork), 3);
+    }
+    for fork in [EvmVersion::Shanghai, EvmVersion::Cancun, EvmVersion::Prague, EvmVersion::Osaka] {
+        assert_eq!(zero.gas_cost(fork), 2);
+        assert_eq!(one.gas_cost(fork), 3);
+    }
+    assert_eq!(one.gas_cost(EvmVersion::London), 3);
+}
+
+#[test]
+fn static_costs() {
+    let cost = |op| Instruction::Data(op).gas_cost(EvmVersion::Cancun);
+    assert_eq!(cost(DataInstruction::Add), 3);
+    assert_eq!(cost(DataInstruction::Tload), 100);
+    assert_eq!(cost(DataInstruction::Mcopy), 3);
+    assert_eq!(cost(DataInstruction::Log2), 375 + 2 * 375);
+    // Not available yet, but priced as in Cancun.
+    assert_eq!(Instruction::Data(DataInstruction::Tstore).gas_cost(EvmVersion::London), 100);
+
+    // A jump pays for the push of its target.
+    let seq = InstructionSeq::builder().jumpdest(0).push(U256::ZERO).jumpi(0).jump(0).build();
+    assert_eq!(seq.gas(EvmVersion::London), 1 + 3 + (3 + 10) + (3 + 8));
+    assert_eq!(seq.gas(EvmVersion::Shanghai), 1 + 2 + (3 + 10) + (3 + 8));
+}
+
+/// Spills a value to the register at 0x20 and loads it back, then copies code to 0x40 and returns.
+fn sequence() -> InstructionSeq {
+    InstructionSeq::builder()
+        .push(U256::from(1))
+        .push(U256::from(0x20))
+        .op(DataInstruction::Mstore)
+        .push(U256::from(0x20))
+        .op(DataInstruction::Mload)
+        .dup(0)
+        .swap(1)
+        .op(DataInstruction::Pop)
+        .push(U256::from(32))
+        .push(U256::ZERO)
+        .push(U256::from(0x40))
+        .op(DataInstruction::Codecopy)
+        .jump(0)
+        .jumpdest(0)
+        .op(DataInstruction::Return)
+        .build()
+}
+
+#[test]
+fn counts() {
+    let counts = Counts { pushes: 6, dups: 1, swaps: 1, pops: 1, memory: 3, control: 2, other: 1 };
+    assert_eq!(sequence().counts(), counts);
+}
+
+#[test]
+fn gas() {
+    // Five pushes at 3 and a `push0` at 2, the dup and swap and `mstore`, `mload` and `codecopy` at 3,
+    // `pop` at 2, the jump at 3 + 8, the jumpdest at 1 and `return` at 0, leaving out memory
+    // expansion and the copy per word.
+    assert_eq!(sequence().gas(EvmVersion::Cancun), 3 * 5 + 2 + 3 * 2 + 3 * 3 + 2 + 11 + 1);
+    // `push1 0` instead of `push0`.
+    assert_eq!(sequence().gas(EvmVersion::London), sequence().gas(EvmVersion::Cancun) + 1);
+}
+
+#[test]
+fn register_traffic() {
+    let seq = sequence();
+    assert_eq!(seq.register_traffic(U256::from(0x20)..U256::from(0x40)), 2);
+    assert_eq!(seq.register_traffic(U256::ZERO..U256::from(0x20)), 0);
+    // The copy to 0x40 goes through `codecopy`, not a load or store.
+    assert_eq!(seq.register_traffic(U256::from(0x40)..U256::from(0x60)), 0);
+}
@@ -0,0 +1,70 @@
+//! Property tests over random programs. Run with `cargo test --features proptest`.
+
+use std::collections::HashMap;
+
+use evm_spilling::arbitrary::ProgramParams;
+use evm_spilling::evm::{Instruction, InstructionSeq, StackInstruction};
+use evm_spilling::program::Block;
+use evm_spilling::{codegen, compile, equivalence, peephole, Options};
+use proptest::prelude::*;
+
+fn source(block: &Block<String>) -> String {
+    block.0.iter().map(|s| format!("{s}\n")).collect()
+}
+
+fn check(block: &Block<String>, codegen: codegen::Options) -> Result<(), TestCaseError> {
+    let source = source(block);
+    let output = compile(&source, &Options { codegen, ..Options::default() })
+        .map_err(|e| TestCaseError::fail(format!("{e:#}\n{source}")))?;
+
+    for instr in &output.code.instructions {
+        match instr {
+            Instruction::Stack(StackInstruction::Dup(depth)) => prop_assert!(*depth < 16, "{instr} too deep"),
+            Instruction::Stack(StackInstruction::Swap(depth)) => prop_assert!(*depth <= 16, "{instr} too deep"),
+            _ => (),
+        }
+    }
+
+    let immutables = (0..4).map(|i| (format!("i{i}"), alloy_primitives::U256::from(1000 + i))).collect::<HashMap<_, _>>();
+    equivalence::check(&output, &immutables).map_err(|e| TestCaseError::fail(format!("{e:#}\n{source}")))
+}
+
+proptest! {
+    #[test]
+    fn default_options(block in any::<Block<String>>()) {
+        check(&block, codegen::Options { verify: true, ..codegen::Options::default() })?;
+    }
+
+    #[test]
+    fn stable_registers(block in any::<Block<String>>()) {
+        check(&block, codegen::Options { verify: true, stable_registers: true, ..codegen::Options::default() })?;
+    }
+
+    #[test]
+    fn peephole(block in any::<Block<String>>()) {
+        let source = source(&block);
+        let codegen = codegen::Options { minimize_swaps: false, ..codegen::Options::default() };
+        let mut output = compile(&source, &Options { codegen, ..Options::default() })
+            .map_err(|e| TestCaseError::fail(format!("{e:#}\n{source}")))?;
+        let mut seq = InstructionSeq::from(output.code.instructions);
+        peephole::optimize(&mut seq);
+        output.code.instructions = seq.into_inner();
+        equivalence::check(&output, &HashMap::new()).map_err(|e| TestCaseError::fail(format!("{e:#}\n{source}")))?;
+    }
+
+    #[test]
+    fn treeify(block in any::<Block<String>>()) {
+        let source = source(&block);
+        let codegen = codegen::Options { treeify: true, ..codegen::Options::default() };
+        let output = compile(&source, &Options { codegen: codegen.clone(), ..Options::default() })
+            .map_err(|e| TestCaseError::fail(format!("{e:#}\n{source}")))?;
+        let swap = output.code.instructions.iter().find(|i| matches!(i, Instruction::Stack(StackInstruction::Swap(_))));
+        prop_assert!(swap.is_none(), "treeify emitted a swap\n{source}");
+        check(&block, codegen)?;
+    }
+
+    #[test]
+    fn long_lifetimes(block in any_with::<Block<String>>(ProgramParams { vars: 128, lifetime: 96 })) {
+        check(&block, codegen::Options { verify: true, ..codegen::Options::default() })?;
+    }
+}
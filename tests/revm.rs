@@ -0,0 +1,117 @@
+//! Deploys the examples into revm and compares their execution with the reference interpreter.
+//! Run with `cargo test --features revm`.
+
+use std::collections::HashMap;
+
+use evm_spilling::{compile, equivalence, Options, Output};
+use revm::context::{CfgEnv, Context, TxEnv};
+use revm::context_interface::result::{ExecutionResult, Output as CallOutput};
+use revm::database::InMemoryDB;
+use revm::interpreter::interpreter::EthInterpreter;
+use revm::interpreter::Interpreter;
+use revm::primitives::hardfork::SpecId;
+use revm::primitives::{Address, Bytes, TxKind};
+use revm::{ExecuteCommitEvm, InspectCommitEvm, Inspector, MainBuilder, MainContext};
+
+/// Memory and gas spent as of the last instruction executed.
+#[derive(Default)]
+struct Recorder {
+    memory: Vec<u8>,
+    gas: u64,
+}
+
+impl<CTX> Inspector<CTX, EthInterpreter> for Recorder {
+    fn step_end(&mut self, interp: &mut Interpreter<EthInterpreter>, _: &mut CTX) {
+        self.memory = interp.memory.context_memory().to_vec();
+        self.gas = interp.gas.total_gas_spent();
+    }
+}
+
+struct Execution {
+    memory: Vec<u8>,
+    output: Vec<u8>,
+    gas: u64,
+}
+
+fn execute(output: &Output) -> Execution {
+    let context = Context::mainnet().with_db(InMemoryDB::default()).with_cfg(CfgEnv::new_with_spec(SpecId::CANCUN));
+    let mut evm = context.build_mainnet_with_inspector(Recorder::default());
+
+    let deploy = TxEnv::builder().kind(TxKind::Create).data(Bytes::from(output.init_code().unwrap())).build().unwrap();
+    let address = match evm.transact_commit(deploy).unwrap() {
+        ExecutionResult::Success { output: CallOutput::Create(_, Some(address)), .. } => address,
+        result => panic!("deployment failed: {result:?}"),
+    };
+
+    let call = TxEnv::builder().kind(TxKind::Call(address)).nonce(1).caller(Address::ZERO).build().unwrap();
+    let data = match evm.inspect_tx_commit(call).unwrap() {
+        ExecutionResult::Success { output, .. } => output.into_data().to_vec(),
+        result => panic!("call failed: {result:?}"),
+    };
+    Execution { memory: evm.inspector.memory.clone(), output: data, gas: evm.inspector.gas }
+}
+
+fn memory_cost(size: usize) -> u64 {
+    let words = size.div_ceil(32) as u64;
+    3 * words + words * words / 512
+}
+
+#[test]
+fn examples() {
+    let immutables = HashMap::new();
+    let mut paths: Vec<_> = std::fs::read_dir("examples").unwrap().map(|entry| entry.unwrap().path()).collect();
+    paths.sort();
+
+    for path in paths {
+        let source = std::fs::read_to_string(&path).unwrap();
+        let output = compile(&source, &Options::default()).unwrap();
+        let execution = execute(&output);
+        let expected = equivalence::interpret_code(&output, &immutables).unwrap();
+
+        let name = path.display();
+        assert_eq!(execution.memory, expected.memory, "{name}: memory differs");
+        assert_eq!(execution.output, expected.output, "{name}: returned data differs");
+        assert_eq!(execution.gas, output.gas() + memory_cost(expected.memory.len()), "{name}: gas differs");
+        equivalence::check(&output, &immutables).unwrap();
+    }
+}
+
+#[test]
+fn dispatch() {
+    let source = std::fs::read_to_string("tests/snapshots/contract.dsl").unwrap();
+    let contract = evm_spilling::dispatch::compile(&source, &Options::default()).unwrap();
+    let context = Context::mainnet().with_db(InMemoryDB::default()).with_cfg(CfgEnv::new_with_spec(SpecId::CANCUN));
+    let mut evm = context.build_mainnet();
+
+    let deploy = TxEnv::builder().kind(TxKind::Create).data(Bytes::from(contract.init_code().unwrap())).build().unwrap();
+    let address = match evm.transact_commit(deploy).unwrap() {
+        ExecutionResult::Success { output: CallOutput::Create(_, Some(address)), .. } => address,
+        result => panic!("deployment failed: {result:?}"),
+    };
+
+    let mut nonce = 1;
+    let mut call = |signature: &str, argument: Option<u64>| {
+        let mut data = alloy_primitives::keccak256(signature)[..4].to_vec();
+        if let Some(argument) = argument {
+            data.extend(alloy_primitives::U256::from(argument).to_be_bytes::<32>());
+        }
+        let tx = TxEnv::builder().kind(TxKind::Call(address)).nonce(nonce).caller(Address::ZERO).data(Bytes::from(data)).build().unwrap();
+        nonce += 1;
+        evm.transact_commit(tx).unwrap()
+    };
+    let word = |value: u64| alloy_primitives::U256::from(value).to_be_bytes::<32>().to_vec();
+
+    match call("get()", None) {
+        ExecutionResult::Success { output, .. } => assert_eq!(output.into_data().to_vec(), word(7)),
+        result => panic!("get() failed: {result:?}"),
+    }
+    match call("double(uint256)", Some(21)) {
+        ExecutionResult::Success { output, .. } => assert_eq!(output.into_data().to_vec(), word(42)),
+        result => panic!("double(uint256) failed: {result:?}"),
+    }
+    match call("ping()", None) {
+        ExecutionResult::Success { logs, .. } => assert_eq!(logs.len(), 1),
+        result => panic!("ping() failed: {result:?}"),
+    }
+    assert!(matches!(call("missing()", None), ExecutionResult::Revert { .. }));
+}
@@ -0,0 +1,86 @@
+//! Assembly and disassembly, and printing and parsing, undo each other on the code this crate
+//! generates.
+
+use std::path::PathBuf;
+
+use evm_spilling::evm::{disassemble, ControlInstruction, EvmVersion, Instruction, InstructionSeq};
+use evm_spilling::{codegen, compile, dispatch, parser, Options};
+
+/// The instructions and bytecode of every example and snapshot program, compiled for each EVM
+/// version that supports its operations.
+fn programs() -> Vec<(PathBuf, EvmVersion, Vec<Instruction>, Vec<u8>)> {
+    let mut paths: Vec<_> = ["examples", "tests/snapshots"].iter()
+        .flat_map(|dir| std::fs::read_dir(dir).unwrap().map(|entry| entry.unwrap().path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "evm" || ext == "dsl"))
+        .collect();
+    paths.sort();
+
+    let mut programs = Vec::new();
+    for path in paths {
+        let source = std::fs::read_to_string(&path).unwrap();
+        for evm_version in [EvmVersion::London, EvmVersion::Cancun] {
+            let options = Options { codegen: codegen::Options { evm_version, ..codegen::Options::default() }, ..Options::default() };
+            let compiled = if parser::is_contract(&source) {
+                dispatch::compile(&source, &options).map(|contract| (contract.instructions, contract.bytecode))
+            } else {
+                compile(&source, &options).map(|output| (output.code.instructions, output.bytecode))
+            };
+            match compiled {
+                Ok((instructions, bytecode)) => programs.push((path.clone(), evm_version, instructions, bytecode)),
+                // Programs using operations London lacks are only checked for Cancun.
+                Err(_) if evm_version == EvmVersion::London => (),
+                Err(e) => panic!("{}: {e:#}", path.display()),
+            }
+        }
+    }
+    programs
+}
+
+#[test]
+fn disassemble_then_assemble() {
+    for (path, version, instructions, bytecode) in programs() {
+        let seq = disassemble(&bytecode);
+        assert_eq!(seq.to_bytes(version).unwrap(), bytecode, "{} ({version:?})", path.display());
+        let jumps = |code: &[Instruction]| code.iter().filter(|i| matches!(i, Instruction::Control(ControlInstruction::Jump(_) | ControlInstruction::Jumpi(_)))).count();
+        assert_eq!(jumps(&seq[..]), jumps(&instructions), "{} ({version:?}): jumps not recovered", path.display());
+    }
+}
+
+#[test]
+fn truncated_push() {
+    let code = [0x60, 0x01, 0x61, 0xff];
+    let seq = disassemble(&code);
+    assert!(matches!(seq.iter().last(), Some(Instruction::Truncated(_))));
+    assert_eq!(seq.to_bytes(EvmVersion::Cancun).unwrap(), code);
+}
+
+#[test]
+fn push_zero() {
+    // `push1 0` only reassembles as itself before `push0`, which is narrower.
+    assert_eq!(disassemble(&[0x60, 0x00]).to_bytes(EvmVersion::London).unwrap(), [0x60, 0x00]);
+    assert_eq!(disassemble(&[0x60, 0x00]).to_bytes(EvmVersion::Cancun).unwrap(), [0x5f]);
+    assert_eq!(disassemble(&[0x5f]).to_bytes(EvmVersion::Cancun).unwrap(), [0x5f]);
+}
+
+
+#[test]
+fn print_then_parse() {
+    for (path, version, instructions, bytecode) in programs() {
+        let seq = InstructionSeq::from(instructions);
+        for text in [seq.to_string(), seq.display(version).to_string()] {
+            let parsed: InstructionSeq = text.parse().unwrap_or_else(|e| panic!("{} ({version:?}): {e:#}", path.display()));
+            assert_eq!(parsed.display(version).to_string(), seq.display(version).to_string(), "{} ({version:?})", path.display());
+            assert_eq!(parsed.to_bytes(version).unwrap(), bytecode, "{} ({version:?})", path.display());
+        }
+    }
+}
+
+#[test]
+fn parse_labels_and_truncated() {
+    // A jump to the `jumpdest` at 4, and a push cut short.
+    let code = [0x60, 0x04, 0x56, 0x00, 0x5b, 0x61, 0xff];
+    let seq = disassemble(&code);
+    let parsed: InstructionSeq = seq.to_string().parse().unwrap();
+    assert_eq!(parsed.to_string(), seq.to_string());
+    assert_eq!(parsed.to_bytes(EvmVersion::Cancun).unwrap(), code);
+}
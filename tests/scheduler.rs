@@ -0,0 +1,79 @@
+//! The incremental scheduler API, driven directly instead of through a program.
+
+use alloy_primitives::U256;
+use evm_spilling::codegen::{CodegenError, Options, Scheduler};
+use evm_spilling::evm::{DataInstruction, InstructionSeq};
+use evm_spilling::scope::Var;
+
+fn assembly(scheduler: Scheduler) -> String {
+    InstructionSeq::from(scheduler.finish().unwrap().instructions).to_string()
+}
+
+#[test]
+fn schedules_operations() {
+    let [x, y, z] = [0, 1, 2].map(Var::new);
+    let mut scheduler = Scheduler::new(&Options::default());
+    scheduler.push(x, U256::from(1));
+    scheduler.push(y, U256::from(2));
+    assert_eq!(scheduler.depth(x), Some(1));
+    assert_eq!(scheduler.depth(z), None);
+    scheduler.copy_to(x, 0).unwrap();
+    scheduler.apply(DataInstruction::Add, &[z]).unwrap();
+    scheduler.rotate_to(x, 0).unwrap();
+    scheduler.pop().unwrap();
+    assert_eq!(scheduler.depth(z), Some(0));
+    assert_eq!(assembly(scheduler), "push1 1\npush1 2\ndup2\nadd\nswap1\npop\n");
+}
+
+#[test]
+fn takes_inputs() {
+    let [a, b, c] = [0, 1, 2].map(Var::new);
+    let mut scheduler = Scheduler::with_inputs(&Options::default(), &[a, b]);
+    assert_eq!(scheduler.depth(a), Some(0));
+    assert_eq!(scheduler.depth(b), Some(1));
+    scheduler.apply(DataInstruction::Xor, &[c]).unwrap();
+    assert_eq!(assembly(scheduler), "xor\n");
+}
+
+#[test]
+fn rejects_too_few_values() {
+    let [x, y] = [0, 1].map(Var::new);
+    let mut scheduler = Scheduler::new(&Options::default());
+    scheduler.push(x, U256::from(1));
+    assert!(matches!(scheduler.apply(DataInstruction::Add, &[y]), Err(CodegenError::StackUnderflow { needed: 2, height: 1 })));
+    assert!(matches!(scheduler.rotate_to(x, 1), Err(CodegenError::StackUnderflow { needed: 2, height: 1 })));
+    assert!(matches!(scheduler.copy_to(x, 2), Err(CodegenError::StackUnderflow { needed: 2, height: 1 })));
+    scheduler.pop().unwrap();
+    assert!(scheduler.pop().is_err());
+}
+
+#[test]
+fn rejects_unknown_variables() {
+    let [x, y] = [0, 1].map(Var::new);
+    let mut scheduler = Scheduler::new(&Options::default());
+    scheduler.push(x, U256::from(1));
+    assert!(scheduler.rotate_to(y, 0).is_err());
+    assert!(scheduler.copy_to(y, 0).is_err());
+}
+
+#[test]
+fn depth_limit() {
+    for (spill_depth, reach) in [(16, 16), (8, 8)] {
+        let options = Options { spill_depth, ..Options::default() };
+        let vars: Vec<Var> = (0..=reach).map(Var::new).collect();
+        let mut scheduler = Scheduler::new(&options);
+        for (i, &var) in vars.iter().enumerate() {
+            scheduler.push(var, U256::from(i));
+        }
+        let top = vars[reach];
+        // The deepest place within reach, then one past it, where `swap16` could still put a value
+        // but nothing could copy it back.
+        scheduler.copy_to(top, reach - 1).unwrap();
+        scheduler.rotate_to(top, reach - 1).unwrap();
+        assert!(matches!(scheduler.copy_to(top, reach), Err(CodegenError::DupTooDeep { depth, .. }) if depth == reach));
+        assert!(matches!(scheduler.rotate_to(top, reach), Err(CodegenError::SwapTooDeep { depth, .. }) if depth == reach));
+        // The spiller and lowering accept what the scheduler did.
+        let code = scheduler.finish().unwrap();
+        assert!(InstructionSeq::from(code.instructions).to_string().contains(&format!("swap{}\n", reach - 1)));
+    }
+}
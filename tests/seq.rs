@@ -0,0 +1,62 @@
+//! The instruction sequence API: building, indexing, splicing and filtering.
+
+use alloy_primitives::U256;
+use evm_spilling::evm::{DataInstruction, Instruction, InstructionSeq};
+
+fn render(instrs: &[Instruction]) -> Vec<String> {
+    instrs.iter().map(Instruction::to_string).collect()
+}
+
+fn sequence() -> InstructionSeq {
+    InstructionSeq::builder()
+        .push(U256::from(1))
+        .push(U256::from(2))
+        .op(DataInstruction::Add)
+        .dup(0)
+        .swap(1)
+        .op(DataInstruction::Pop)
+        .build()
+}
+
+#[test]
+fn builder() {
+    let seq = InstructionSeq::builder().jumpdest(0).push(U256::from(1)).jumpi(0).jump(0).build();
+    assert_eq!(seq.to_string(), "jumpdest L0\npush1 1\njumpi L0\njump L0\n");
+    assert_eq!(seq.len(), 4);
+    assert!(InstructionSeq::builder().build().is_empty());
+}
+
+#[test]
+fn index() {
+    let seq = sequence();
+    assert_eq!(seq[2].to_string(), "add");
+    assert_eq!(render(&seq[3..5]), ["dup1", "swap1"]);
+    assert_eq!(render(&seq[..]), render(&sequence().into_inner()));
+}
+
+#[test]
+fn splice() {
+    let mut seq = sequence();
+    let removed = seq.splice(3..5, [Instruction::Data(DataInstruction::Mul)]);
+    assert_eq!(render(&removed), ["dup1", "swap1"]);
+    assert_eq!(render(&seq[..]), ["push1 1", "push1 2", "add", "mul", "pop"]);
+
+    let removed = seq.splice(..0, sequence());
+    assert!(removed.is_empty());
+    assert_eq!(seq.len(), 11);
+}
+
+#[test]
+fn retain() {
+    let mut seq = sequence();
+    seq.retain(|instr| matches!(instr, Instruction::Data(_)));
+    assert_eq!(render(&seq[..]), ["add", "pop"]);
+}
+
+#[test]
+fn round_trip() {
+    let instrs = sequence().into_inner();
+    let seq = InstructionSeq::from(instrs.clone());
+    assert_eq!(seq.to_string(), sequence().to_string());
+    assert_eq!(render(&seq.into_inner()), render(&instrs));
+}
@@ -0,0 +1,93 @@
+//! Golden tests: each `tests/snapshots/*.dsl` is compiled and its assembly and stats compared with
+//! the `.snap` file next to it, or for a contract its assembly and size. Run with `UPDATE_SNAPSHOTS=1` to accept the new output.
+//! A first line `// options: treeify, relocate_memory` turns on those codegen options.
+
+use std::fmt::Write;
+use std::path::Path;
+
+use evm_spilling::dispatch::Contract;
+use evm_spilling::{codegen, compile, dispatch, parser, Options, Output};
+
+fn render(output: &Output) -> String {
+    let spills = &output.code.spills;
+    let mut text = output.assembly().to_string();
+    if !text.ends_with('\n') {
+        text.push('\n');
+    }
+    writeln!(text, "---").unwrap();
+    writeln!(text, "instructions: {}", output.code.instructions.len()).unwrap();
+    writeln!(text, "bytes: {}", output.bytecode.len()).unwrap();
+    writeln!(text, "gas: {}", output.gas()).unwrap();
+    writeln!(text, "registers: {}", output.code.register_count).unwrap();
+    writeln!(text, "spills: {}", spills.iter().filter(|s| !s.restore).count()).unwrap();
+    writeln!(text, "restores: {}", spills.iter().filter(|s| s.restore).count()).unwrap();
+    text
+}
+
+fn render_contract(contract: &Contract) -> String {
+    let mut text = contract.assembly();
+    writeln!(text, "---").unwrap();
+    writeln!(text, "instructions: {}", contract.instructions.len()).unwrap();
+    writeln!(text, "bytes: {}", contract.bytecode.len()).unwrap();
+    text
+}
+
+fn options(source: &str) -> Options {
+    let mut codegen = codegen::Options::default();
+    if let Some(names) = source.lines().next().and_then(|line| line.strip_prefix("// options:")) {
+        for name in names.split(',').map(str::trim) {
+            match name {
+                "treeify" => codegen.treeify = true,
+                "relocate_memory" => codegen.relocate_memory = true,
+                _ => panic!("unknown snapshot option {name}"),
+            }
+        }
+    }
+    Options { codegen, ..Options::default() }
+}
+
+#[test]
+fn snapshots() {
+    let update = std::env::var_os("UPDATE_SNAPSHOTS").is_some();
+    let mut paths: Vec<_> = std::fs::read_dir(Path::new("tests/snapshots")).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "dsl"))
+        .collect();
+    paths.sort();
+
+    let mut failed = Vec::new();
+    for path in paths {
+        let source = std::fs::read_to_string(&path).unwrap();
+        let actual = if parser::is_contract(&source) {
+            dispatch::compile(&source, &options(&source)).map(|contract| render_contract(&contract))
+        } else {
+            compile(&source, &options(&source)).map(|output| render(&output))
+        };
+        let actual = actual.unwrap_or_else(|e| panic!("{}: {e:#}", path.display()));
+        if options(&source).codegen.treeify {
+            assert!(!actual.lines().any(|line| line.starts_with("swap")), "{}: treeify emitted a swap", path.display());
+        }
+
+        let snap = path.with_extension("snap");
+        let new = path.with_extension("snap.new");
+        if update {
+            std::fs::write(&snap, &actual).unwrap();
+            continue;
+        }
+        match std::fs::read_to_string(&snap) {
+            Ok(expected) if expected == actual => {
+                let _ = std::fs::remove_file(&new);
+            }
+            _ => {
+                std::fs::write(&new, &actual).unwrap();
+                failed.push(snap.display().to_string());
+            }
+        }
+    }
+
+    assert!(
+        failed.is_empty(),
+        "snapshots differ: {}\ncompare with the .snap.new files, and rerun with UPDATE_SNAPSHOTS=1 to accept",
+        failed.join(", ")
+    );
+}
@@ -0,0 +1,102 @@
+//! Stack accesses of the generated code: how deep its dups and swaps reach, and how high the stack
+//! grows over the reserved slots beneath it.
+
+use alloy_primitives::U256;
+use evm_spilling::codegen::{self, CodegenError};
+use evm_spilling::evm::{EvmVersion, Instruction, StackInstruction};
+use evm_spilling::{compile, Error, Options, Output};
+
+/// Programs compiled with `codegen`, leaving out those that need more than it allows.
+fn outputs(codegen: &codegen::Options) -> Vec<(String, Output)> {
+    let examples = std::fs::read_dir("examples").unwrap().map(|e| e.unwrap().path());
+    let snapshots = std::fs::read_dir("tests/snapshots").unwrap().map(|e| e.unwrap().path())
+        .filter(|p| p.extension().is_some_and(|ext| ext == "dsl"));
+    examples.chain(snapshots)
+        .filter_map(|path| {
+            let source = std::fs::read_to_string(&path).unwrap();
+            let options = Options { codegen: codegen.clone(), ..Options::default() };
+            compile(&source, &options).ok().map(|output| (path.display().to_string(), output))
+        })
+        .collect()
+}
+
+/// The dups and swaps of the code with the depth each reaches, counting the top as 0, and the
+/// number of values the program had on the stack at that point, starting from its inputs.
+fn accesses(output: &Output) -> Vec<(&Instruction, usize, usize)> {
+    let mut height = output.program.inputs().len();
+    let mut accesses = Vec::new();
+    for instr in &output.code.instructions {
+        if let Instruction::Stack(StackInstruction::Dup(depth) | StackInstruction::Swap(depth)) = instr {
+            accesses.push((instr, *depth, height));
+        }
+        let metadata = instr.metadata(EvmVersion::LATEST).unwrap();
+        height = height + metadata.outputs - metadata.inputs;
+    }
+    accesses
+}
+
+#[test]
+fn reserved_slots() {
+    let codegen = codegen::Options { reserved_slots: 3, ..codegen::Options::default() };
+    let outputs = outputs(&codegen);
+    assert!(outputs.len() > 10);
+    for (name, output) in &outputs {
+        for (instr, depth, height) in accesses(output) {
+            assert!(depth < height, "{name}: {instr} reaches into the reserved slots with {height} values above them");
+        }
+    }
+}
+
+#[test]
+fn stack_overflow() {
+    // Two values at most: the value, then the offset it is stored at.
+    let source = "let x = const 1; let p = const 0; mstore p x;";
+    let options = |reserved_slots| Options { codegen: codegen::Options { reserved_slots, ..codegen::Options::default() }, ..Options::default() };
+    compile(source, &options(1022)).unwrap();
+    let error = compile(source, &options(1023)).err().unwrap();
+    assert!(matches!(error, Error::Codegen(CodegenError::StackOverflow { height: 2, reserved: 1023 })), "{error}");
+
+    // Inputs count toward the height, here the highest the stack gets.
+    let source = "takes (a, b, c); let x = add a b; mstore x c;";
+    compile(source, &options(1021)).unwrap();
+    let error = compile(source, &options(1022)).err().unwrap();
+    assert!(matches!(error, Error::Codegen(CodegenError::StackOverflow { height: 3, reserved: 1022 })), "{error}");
+}
+
+#[test]
+fn spill_depth() {
+    for spill_depth in [2, 4, 8, 12, 16] {
+        for base_on_stack in [false, true] {
+            let codegen = codegen::Options { spill_depth, base_on_stack, spill_base: U256::from(0x1000), ..codegen::Options::default() };
+            // `base_on_stack` keeps values within 8 at least.
+            let reach = if base_on_stack { spill_depth.max(8) } else { spill_depth };
+            let outputs = outputs(&codegen);
+            assert!(outputs.len() > 5);
+            for (name, output) in &outputs {
+                // `dupN` copies from depth N - 1 and `swapN` exchanges with depth N, so N stays
+                // within the spill depth as it does within 16 by default.
+                for instr in &output.code.instructions {
+                    let n = match instr {
+                        Instruction::Stack(StackInstruction::Dup(depth)) => depth + 1,
+                        Instruction::Stack(StackInstruction::Swap(depth)) => *depth,
+                        _ => continue,
+                    };
+                    assert!(n <= reach, "{name}: {instr} past a spill depth of {spill_depth}");
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn spill_depth_bounds() {
+    let assembly = |spill_depth, base_on_stack| {
+        let codegen = codegen::Options { spill_depth, base_on_stack, spill_base: U256::from(0x1000), ..codegen::Options::default() };
+        outputs(&codegen).into_iter().map(|(name, output)| (name, output.assembly().to_string())).collect::<Vec<_>>()
+    };
+    assert_eq!(assembly(0, false), assembly(1, false));
+    assert_eq!(assembly(17, false), assembly(16, false));
+    assert_eq!(assembly(1000, false), assembly(16, false));
+    assert_eq!(assembly(2, true), assembly(8, true));
+    assert_ne!(assembly(1, false), assembly(16, false));
+}
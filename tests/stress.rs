@@ -0,0 +1,53 @@
+use alloy_primitives::U256;
+use evm_spilling::{codegen, stress, Options};
+
+fn run(codegen: codegen::Options) {
+    if let Err(e) = stress::run(0..100, Options { codegen, ..Options::default() }) {
+        panic!("{e:#}");
+    }
+}
+
+#[test]
+fn reuse_registers() {
+    run(codegen::Options::default());
+}
+
+#[test]
+fn stable_registers() {
+    run(codegen::Options { stable_registers: true, ..codegen::Options::default() });
+}
+
+#[test]
+fn base_on_stack() {
+    run(codegen::Options { spill_base: U256::from(0x1000), base_on_stack: true, ..codegen::Options::default() });
+}
+
+#[test]
+fn constant_pool() {
+    run(codegen::Options { constant_pool: Some(2), ..codegen::Options::default() });
+}
+
+#[test]
+fn constant_registers() {
+    run(codegen::Options { constant_registers: Some(1), ..codegen::Options::default() });
+}
+
+#[test]
+fn reserved_slots() {
+    run(codegen::Options { reserved_slots: 3, ..codegen::Options::default() });
+}
+
+#[test]
+fn relocate_memory() {
+    run(codegen::Options { relocate_memory: true, ..codegen::Options::default() });
+}
+
+#[test]
+fn spill_depth() {
+    run(codegen::Options { spill_depth: 12, ..codegen::Options::default() });
+}
+
+#[test]
+fn treeify() {
+    run(codegen::Options { treeify: true, ..codegen::Options::default() });
+}